@@ -13,6 +13,12 @@ fi
 cargo build
 cargo test
 
+# The native ssh transport tests start a local sshd when there is one.
+if [ `uname` = Linux ]
+then
+    cargo test --features cargo-dinghy/native-ssh
+fi
+
 # Test original cargo build
 ( \
     cd test-ws/test-app \
@@ -6,9 +6,16 @@ use clap::SubCommand;
 use dinghy_lib::compiler::CompileMode;
 use dinghy_lib::BuildArgs;
 use std::ffi::OsString;
+use std::time::Duration;
 
 pub struct CargoDinghyCli {}
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Human,
+    Json,
+}
+
 impl CargoDinghyCli {
     pub fn parse<'a, I, T>(args: I) -> ArgMatches<'a>
     where
@@ -19,17 +26,22 @@ impl CargoDinghyCli {
             App::new("dinghy")
                 .version(crate_version!())
                 .device()
+                .all_matching()
+                .shard()
+                .timeout()
                 .verbose()
                 .quiet()
                 .overlay()
                 .platform()
                 .subcommand(
                     SubCommand::with_name("all-devices")
-                        .about("List all devices that can be used with Dinghy"),
+                        .about("List all devices that can be used with Dinghy")
+                        .format(),
                 )
                 .subcommand(
                     SubCommand::with_name("all-platforms")
-                        .about("List all platforms known to dinghy"),
+                        .about("List all platforms known to dinghy")
+                        .format(),
                 )
                 .subcommand(
                     SubCommand::with_name("bench")
@@ -46,6 +58,7 @@ impl CargoDinghyCli {
                         .features()
                         .no_default_features()
                         .no_run()
+                        .no_fail_fast()
                         .all_features()
                         .common_remote()
                         .target()
@@ -81,9 +94,18 @@ impl CargoDinghyCli {
                         .about("Remove artifacts that cargo has generated in the past"),
                 )
                 .subcommand(
-                    SubCommand::with_name("devices").about(
-                        "List devices that can be used with Dinghy for the selected platform",
-                    ),
+                    SubCommand::with_name("devices")
+                        .about(
+                            "List devices that can be used with Dinghy for the selected platform",
+                        )
+                        .format(),
+                )
+                .subcommand(
+                    SubCommand::with_name("doctor")
+                        .about(
+                            "Check the platforms and devices dinghy can find, and how to fix them",
+                        )
+                        .format(),
                 )
                 .subcommand(SubCommand::with_name("lldbproxy").about("Debug through lldb"))
                 .subcommand(
@@ -104,6 +126,29 @@ impl CargoDinghyCli {
                         .strip()
                         .bearded(),
                 )
+                .subcommand(
+                    SubCommand::with_name("shell")
+                        .about("Install a test runnable on the device and open a shell next to it")
+                        .lib()
+                        .bin()
+                        .example()
+                        .test()
+                        .bench()
+                        .package()
+                        .job()
+                        .features()
+                        .all_features()
+                        .no_default_features()
+                        .debug_or_release()
+                        .target()
+                        .verbose()
+                        .env()
+                        .arg(
+                            Arg::with_name("RUNNABLE")
+                                .help("The runnable to install, needed when several are built"),
+                        )
+                        .bearded(),
+                )
                 .subcommand(
                     SubCommand::with_name("test")
                         .about("Run the tests")
@@ -120,6 +165,7 @@ impl CargoDinghyCli {
                         .all_features()
                         .no_default_features()
                         .no_run()
+                        .no_fail_fast()
                         .debug_or_release()
                         .target()
                         .verbose()
@@ -136,11 +182,27 @@ impl CargoDinghyCli {
         BuildArgs {
             compile_mode: match matches.subcommand() {
                 ("bench", Some(_)) => CompileMode::Bench,
-                ("test", Some(_)) => CompileMode::Test,
+                ("test", Some(_)) | ("shell", Some(_)) => CompileMode::Test,
                 _ => CompileMode::Build,
             },
             forced_overlays: arg_as_string_vec(matches, "OVERLAY"),
             verbose: matches.occurrences_of("VERBOSE") > 0,
+            no_fail_fast: matches
+                .subcommand()
+                .1
+                .map(|sub_args| sub_args.is_present("NO_FAIL_FAST"))
+                .unwrap_or(false),
+            timeout: matches
+                .value_of("TIMEOUT")
+                .and_then(|it| it.parse().ok())
+                .map(Duration::from_secs),
+        }
+    }
+
+    pub fn output_format_from(matches: &ArgMatches) -> OutputFormat {
+        match matches.value_of("FORMAT") {
+            Some("json") => OutputFormat::Json,
+            _ => OutputFormat::Human,
         }
     }
 }
@@ -148,26 +210,32 @@ impl CargoDinghyCli {
 pub trait CargoDinghyCliExt {
     fn additional_args(self) -> Self;
     fn all(self) -> Self;
+    fn all_matching(self) -> Self;
     fn all_features(self) -> Self;
     fn bin(self) -> Self;
     fn bench(self) -> Self;
     fn common_remote(self) -> Self;
     fn device(self) -> Self;
+    fn env(self) -> Self;
     fn example(self) -> Self;
     fn exclude(self) -> Self;
     fn exe(self) -> Self;
     fn features(self) -> Self;
+    fn format(self) -> Self;
     fn job(self) -> Self;
     fn lib(self) -> Self;
     fn no_default_features(self) -> Self;
+    fn no_fail_fast(self) -> Self;
     fn no_run(self) -> Self;
     fn overlay(self) -> Self;
     fn package(self) -> Self;
     fn platform(self) -> Self;
     fn debug_or_release(self) -> Self;
+    fn shard(self) -> Self;
     fn strip(self) -> Self;
     fn target(self) -> Self;
     fn test(self) -> Self;
+    fn timeout(self) -> Self;
     fn verbose(self) -> Self;
     fn quiet(self) -> Self;
     fn bearded(self) -> Self;
@@ -186,6 +254,15 @@ impl<'a, 'b> CargoDinghyCliExt for App<'a, 'b> {
         )
     }
 
+    fn all_matching(self) -> Self {
+        self.arg(
+            Arg::with_name("ALL_MATCHING")
+                .long("all-matching")
+                .requires("DEVICE")
+                .help("Run on every device matching the device hint, in parallel"),
+        )
+    }
+
     fn all_features(self) -> Self {
         self.arg(
             Arg::with_name("ALL_FEATURES")
@@ -223,15 +300,9 @@ impl<'a, 'b> CargoDinghyCliExt for App<'a, 'b> {
             Arg::with_name("DEBUGGER")
                 .long("debugger")
                 .takes_value(false)
-                .help("just start debugger"),
-        )
-        .arg(
-            Arg::with_name("ENVS")
-                .long("env")
-                .takes_value(true)
-                .multiple(true)
-                .help("Space-separated list of env variables to set e.g. RUST_TRACE=trace"),
+                .help("debug the executable on the device, through gdbserver or lldb-server"),
         )
+        .env()
     }
 
     fn device(self) -> Self {
@@ -240,7 +311,17 @@ impl<'a, 'b> CargoDinghyCliExt for App<'a, 'b> {
                 .short("d")
                 .long("device")
                 .takes_value(true)
-                .help("device hint"),
+                .help("device hint (may contain `*' wildcards)"),
+        )
+    }
+
+    fn env(self) -> Self {
+        self.arg(
+            Arg::with_name("ENVS")
+                .long("env")
+                .takes_value(true)
+                .multiple(true)
+                .help("Space-separated list of env variables to set e.g. RUST_TRACE=trace"),
         )
     }
 
@@ -282,6 +363,17 @@ impl<'a, 'b> CargoDinghyCliExt for App<'a, 'b> {
         )
     }
 
+    fn format(self) -> Self {
+        self.arg(
+            Arg::with_name("FORMAT")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["human", "json"])
+                .default_value("human")
+                .help("Output format"),
+        )
+    }
+
     fn job(self) -> Self {
         self.arg(
             Arg::with_name("JOBS")
@@ -304,6 +396,14 @@ impl<'a, 'b> CargoDinghyCliExt for App<'a, 'b> {
         )
     }
 
+    fn no_fail_fast(self) -> Self {
+        self.arg(
+            Arg::with_name("NO_FAIL_FAST")
+                .long("no-fail-fast")
+                .help("Run all runnables regardless of failure"),
+        )
+    }
+
     fn no_run(self) -> Self {
         self.arg(
             Arg::with_name("NO_RUN")
@@ -312,6 +412,16 @@ impl<'a, 'b> CargoDinghyCliExt for App<'a, 'b> {
         )
     }
 
+    fn shard(self) -> Self {
+        self.arg(
+            Arg::with_name("SHARD")
+                .long("shard")
+                .requires("DEVICE")
+                .conflicts_with("ALL_MATCHING")
+                .help("Split the run across the devices matching the device hint (see [sharding] in dinghy.toml)"),
+        )
+    }
+
     fn strip(self) -> Self {
         self.arg(
             Arg::with_name("STRIP")
@@ -390,6 +500,17 @@ impl<'a, 'b> CargoDinghyCliExt for App<'a, 'b> {
         )
     }
 
+    fn timeout(self) -> Self {
+        self.arg(
+            Arg::with_name("TIMEOUT")
+                .long("timeout")
+                .takes_value(true)
+                .value_name("SECONDS")
+                .validator(|it| it.parse::<u64>().map(|_| ()).map_err(|e| e.to_string()))
+                .help("Kill runnables still running after this many seconds"),
+        )
+    }
+
     fn verbose(self) -> Self {
         self.arg(
             Arg::with_name("VERBOSE")
@@ -1,24 +1,41 @@
 #[macro_use]
 extern crate clap;
+extern crate ctrlc;
 extern crate dinghy_lib;
 extern crate env_logger;
 #[macro_use]
 extern crate log;
 
 use crate::cli::CargoDinghyCli;
+use crate::cli::OutputFormat;
 use clap::ArgMatches;
+use dinghy_lib::compiler::CompileMode;
 use dinghy_lib::compiler::Compiler;
 use dinghy_lib::config::dinghy_config;
+use dinghy_lib::config::Configuration;
+use dinghy_lib::device::set_output_prefix;
+use dinghy_lib::device::without_output_echo;
+use dinghy_lib::doctor;
+use dinghy_lib::doctor::CheckStatus;
+use dinghy_lib::env::parse_envs;
+use dinghy_lib::env::EnvSpec;
 use dinghy_lib::errors::*;
 use dinghy_lib::itertools::Itertools;
 use dinghy_lib::project::Project;
+use dinghy_lib::report;
+use dinghy_lib::serde_json;
+use dinghy_lib::shard;
 use dinghy_lib::utils::arg_as_string_vec;
 use dinghy_lib::Build;
 use dinghy_lib::Device;
 use dinghy_lib::Dinghy;
 use dinghy_lib::Platform;
+use dinghy_lib::PlatformDescription;
+use dinghy_lib::Runnable;
+use std::collections::BTreeMap;
 use std::env;
 use std::env::current_dir;
+use std::mem;
 use std::sync::Arc;
 use std::thread;
 use std::time;
@@ -49,45 +66,103 @@ fn main() {
     };
     env_logger::init();
 
-    if let Err(e) = run_command(&matches) {
-        error!("{:?}", e);
-        // positively ugly.
-        if e.to_string().contains("are filtered out on platform") {
-            std::process::exit(3)
-        } else {
-            std::process::exit(1)
+    // The destructors, which shut down the emulators dinghy started, do not run when it is
+    // interrupted.
+    if let Err(e) = ctrlc::set_handler(|| {
+        Dinghy::shutdown_started_devices();
+        std::process::exit(130)
+    }) {
+        warn!("Couldn't handle interruptions: {:?}", e);
+    }
+
+    match run_command(&matches) {
+        Ok(0) => {}
+        Ok(code) => std::process::exit(code),
+        Err(e) => {
+            error!("{:?}", e);
+            std::process::exit(exit_code(&e))
         }
     }
 }
 
-fn run_command(args: &ArgMatches) -> Result<()> {
+/// The exit code of cargo-dinghy when it fails with `error`, documented in the README:
+///
+/// | code | failure                                           |
+/// |------|---------------------------------------------------|
+/// | 1    | any other failure                                 |
+/// | 2    | invalid configuration                             |
+/// | 3    | all the packages are filtered out on the platform |
+/// | 4    | operation not supported by the device             |
+/// | 5    | build failed                                      |
+/// | 6    | tests failed                                      |
+/// | 7    | a test timed out                                  |
+/// | 8    | no device found                                   |
+/// | 9    | no platform found                                 |
+/// | 10   | device unreachable                                |
+fn exit_code(error: &Error) -> i32 {
+    match DinghyError::of(error) {
+        None => 1,
+        Some(DinghyError::Config(_)) => 2,
+        Some(DinghyError::PackagesFilteredOut { .. }) => 3,
+        Some(DinghyError::Unsupported { .. }) => 4,
+        Some(DinghyError::BuildFailed) => 5,
+        Some(DinghyError::TestFailed(_)) => 6,
+        Some(DinghyError::Timeout { .. }) => 7,
+        Some(DinghyError::DeviceNotFound(_)) => 8,
+        Some(DinghyError::PlatformNotFound(_)) => 9,
+        Some(DinghyError::DeviceUnreachable(_)) => 10,
+    }
+}
+
+/// Runs the dinghy command of `args`, and returns the exit code of cargo-dinghy.
+fn run_command(args: &ArgMatches) -> Result<i32> {
     let conf = Arc::new(dinghy_config(current_dir().unwrap())?);
+    if let ("doctor", Some(sub_args)) = args.subcommand() {
+        // Before probing, which fails or leaves things out silently on the problems it reports.
+        return doctor(&conf, CargoDinghyCli::output_format_from(sub_args)).and(Ok(0));
+    }
     let compiler = Arc::new(Compiler::from_args(args.subcommand().1.unwrap_or(args))?);
     let dinghy = Dinghy::probe(&conf, &compiler)?;
     let project = Project::new(&conf);
     match args.subcommand() {
-        ("all-devices", Some(_)) => return show_all_devices(&dinghy),
-        ("all-platforms", Some(_)) => return show_all_platforms(&dinghy),
+        ("all-devices", Some(sub_args)) => {
+            return show_all_devices(&dinghy, CargoDinghyCli::output_format_from(sub_args))
+                .and(Ok(0))
+        }
+        ("all-platforms", Some(sub_args)) => {
+            return show_all_platforms(&dinghy, CargoDinghyCli::output_format_from(sub_args))
+                .and(Ok(0))
+        }
         _ => {}
     };
 
-    let (platform, device) = select_platform_and_device_from_cli(&args, &dinghy)?;
+    let (platform, devices) = select_platform_and_devices_from_cli(&args, &dinghy)?;
     info!(
         "Targeting platform '{}' and device '{}'",
         platform.id(),
-        device.as_ref().map(|it| it.id()).unwrap_or("<none>")
+        if devices.is_empty() {
+            "<none>".to_string()
+        } else {
+            devices.iter().map(|it| it.id()).join("', '")
+        }
     );
 
     match args.subcommand() {
-        ("bench", Some(sub_args)) => prepare_and_run(device, project, platform, args, sub_args),
+        ("bench", Some(sub_args)) => prepare_and_run(devices, project, platform, args, sub_args),
         ("build", Some(sub_args)) => build(&platform, &project, args, sub_args).and(Ok(())),
         ("clean", Some(_)) => compiler.clean(&**platform),
-        ("devices", Some(_)) => show_all_devices_for_platform(&dinghy, platform),
-        ("lldbproxy", Some(_)) => run_lldb(device),
-        ("run", Some(sub_args)) => prepare_and_run(device, project, platform, args, sub_args),
-        ("test", Some(sub_args)) => prepare_and_run(device, project, platform, args, sub_args),
+        ("devices", Some(sub_args)) => show_all_devices_for_platform(
+            &dinghy,
+            platform,
+            CargoDinghyCli::output_format_from(sub_args),
+        ),
+        ("lldbproxy", Some(_)) => run_lldb(devices.into_iter().next()),
+        ("run", Some(sub_args)) => prepare_and_run(devices, project, platform, args, sub_args),
+        ("shell", Some(sub_args)) => return shell(devices, project, platform, args, sub_args),
+        ("test", Some(sub_args)) => prepare_and_run(devices, project, platform, args, sub_args),
         (sub, _) => bail!("Unknown dinghy command '{}'", sub),
-    }
+    }?;
+    Ok(0)
 }
 
 fn build(
@@ -96,7 +171,15 @@ fn build(
     args: &ArgMatches,
     sub_args: &ArgMatches,
 ) -> Result<Build> {
-    let build_args = CargoDinghyCli::build_args_from(args);
+    let mut build_args = CargoDinghyCli::build_args_from(args);
+    if build_args.timeout.is_none() {
+        build_args.timeout = project
+            .conf
+            .platforms
+            .get(platform.id().as_str())
+            .and_then(|conf| conf.timeout)
+            .map(time::Duration::from_secs);
+    }
     let build = platform.build(&project, &build_args)?;
 
     if sub_args.is_present("STRIP") {
@@ -105,13 +188,47 @@ fn build(
     Ok(build)
 }
 
+#[derive(Clone, Debug)]
+struct RunOptions {
+    args: Vec<String>,
+    envs: Vec<EnvSpec>,
+    cleanup: bool,
+    debugger: bool,
+}
+
+/// Some runnables of a build to run on a device, once per set of extra arguments for their
+/// harness, their reports being merged.
+#[derive(Clone, Debug)]
+struct RunJob {
+    runnables: Vec<Runnable>,
+    runs: Vec<Vec<String>>,
+}
+
+/// The jobs to run on a device, `label` names the device in the summary.
+struct DeviceRun {
+    label: String,
+    device: Arc<Box<dyn Device>>,
+    jobs: Vec<RunJob>,
+}
+
 fn prepare_and_run(
-    device: Option<Arc<Box<dyn Device>>>,
+    devices: Vec<Arc<Box<dyn Device>>>,
     project: Project,
     platform: Arc<Box<dyn Platform>>,
     args: &ArgMatches,
     sub_args: &ArgMatches,
 ) -> Result<()> {
+    // Checked before building, rather than after a long build.
+    let envs = parse_envs(&arg_as_string_vec(sub_args, "ENVS"))?;
+    for device in &devices {
+        let capabilities = device.capabilities();
+        if sub_args.is_present("DEBUGGER") && !capabilities.debug {
+            bail!(DinghyError::unsupported(device.id(), "debugging"));
+        }
+        if sub_args.is_present("CLEANUP") && !capabilities.clean {
+            bail!(DinghyError::unsupported(device.id(), "cleaning up"));
+        }
+    }
     debug!("Build for {}", platform);
     let build = build(&platform.clone(), &project, args, sub_args)?;
 
@@ -119,22 +236,371 @@ fn prepare_and_run(
         return Ok(());
     }
 
-    debug!("Run on {:?}", device);
-    let device = device.ok_or_else(|| anyhow!("No device found"))?;
-    let args = arg_as_string_vec(sub_args, "ARGS");
-    let envs = arg_as_string_vec(sub_args, "ENVS");
-
-    let args = args.iter().map(|s| &s[..]).collect::<Vec<_>>();
-    let envs = envs.iter().map(|s| &s[..]).collect::<Vec<_>>();
-    let build_bundles = if sub_args.is_present("DEBUGGER") {
-        debug!("Debug app");
-        vec![device.debug_app(&project, &build, &*args, &*envs)?]
+    let options = RunOptions {
+        args: arg_as_string_vec(sub_args, "ARGS"),
+        envs,
+        cleanup: sub_args.is_present("CLEANUP"),
+        debugger: sub_args.is_present("DEBUGGER"),
+    };
+    let job = RunJob {
+        runnables: build.runnables.clone(),
+        runs: vec![vec![]],
+    };
+    match devices.len() {
+        0 => bail!(DinghyError::DeviceNotFound(None)),
+        1 => run_on_device(&devices[0], &project, &build, &[job], &options),
+        _ if options.debugger => bail!("--debugger can only be used with a single device"),
+        _ if args.is_present("SHARD") => run_shards(devices, &project, &build, &options),
+        _ => {
+            let runs = devices
+                .into_iter()
+                .map(|device| DeviceRun {
+                    label: device.id().to_string(),
+                    device,
+                    jobs: vec![job.clone()],
+                })
+                .collect();
+            run_in_parallel(runs, &project, &build, &options)
+        }
+    }
+}
+
+fn shell(
+    devices: Vec<Arc<Box<dyn Device>>>,
+    project: Project,
+    platform: Arc<Box<dyn Platform>>,
+    args: &ArgMatches,
+    sub_args: &ArgMatches,
+) -> Result<i32> {
+    if devices.len() != 1 {
+        bail!("A shell can only be opened on a single device")
+    }
+    if !devices[0].capabilities().shell {
+        bail!(DinghyError::unsupported(
+            devices[0].id(),
+            "interactive shells"
+        ));
+    }
+    let envs = parse_envs(&arg_as_string_vec(sub_args, "ENVS"))?;
+    let build = build(&platform, &project, args, sub_args)?;
+    let runnable = select_runnable(&build.runnables, sub_args.value_of("RUNNABLE"))?;
+    devices[0].shell(&project, &build, runnable, &envs)
+}
+
+/// The runnable named `name`, by id (`my_crate-0123456789abcdef`) or by name (`my_crate`).
+fn select_runnable<'a>(runnables: &'a [Runnable], name: Option<&str>) -> Result<&'a Runnable> {
+    let candidates = runnables
+        .iter()
+        .filter(|runnable| {
+            name.map(|name| {
+                runnable.id == name
+                    || runnable
+                        .id
+                        .strip_prefix(name)
+                        .map(|hash| hash.starts_with('-'))
+                        .unwrap_or(false)
+            })
+            .unwrap_or(true)
+        })
+        .collect_vec();
+    match candidates.len() {
+        1 => Ok(candidates[0]),
+        0 => bail!(
+            "No runnable {} among {}",
+            name.unwrap_or(""),
+            runnables.iter().map(|it| &it.id).join(", ")
+        ),
+        _ => bail!(
+            "Several runnables were built, pick one of {}",
+            candidates.iter().map(|it| &it.id).join(", ")
+        ),
+    }
+}
+
+fn run_shards(
+    devices: Vec<Arc<Box<dyn Device>>>,
+    project: &Project,
+    build: &Build,
+    options: &RunOptions,
+) -> Result<()> {
+    let sharding = &project.conf.sharding;
+    let devices = devices
+        .into_iter()
+        .take(sharding.shards.unwrap_or(usize::MAX).max(1))
+        .collect_vec();
+    let tests = if sharding.by_test.unwrap_or(false) {
+        list_tests(&devices[0], project, build, options)?
     } else {
-        debug!("Run app");
-        device.run_app(&project, &build, &*args, &*envs)?
+        BTreeMap::new()
     };
 
-    if sub_args.is_present("CLEANUP") {
+    let mut runs = vec![];
+    for (shard, device) in shard::plan(&build.runnables, &tests, devices.len())
+        .into_iter()
+        .zip(devices)
+    {
+        let label = format!(
+            "{} (shard {}/{})",
+            device.id(),
+            shard.index + 1,
+            shard.count
+        );
+        info!(
+            "Shard {}/{} runs on {}: {}",
+            shard.index + 1,
+            shard.count,
+            device.id(),
+            if shard.runnables.is_empty() {
+                "nothing".to_string()
+            } else {
+                shard
+                    .runnables
+                    .iter()
+                    .map(|it| match it.tests {
+                        Some(ref chunks) => format!(
+                            "{} ({} tests)",
+                            it.runnable.id,
+                            chunks.iter().map(|chunk| chunk.len()).sum::<usize>()
+                        ),
+                        None => it.runnable.id.clone(),
+                    })
+                    .join(", ")
+            }
+        );
+        let jobs = shard
+            .runnables
+            .into_iter()
+            .map(|it| RunJob {
+                runnables: vec![it.runnable],
+                runs: match it.tests {
+                    Some(chunks) => chunks
+                        .into_iter()
+                        .map(|mut tests| {
+                            tests.push("--exact".to_string());
+                            tests
+                        })
+                        .collect(),
+                    None => vec![vec![]],
+                },
+            })
+            .collect_vec();
+        runs.push(DeviceRun {
+            label,
+            device,
+            jobs,
+        });
+    }
+    run_in_parallel(runs, project, build, options)
+}
+
+/// Lists the tests of each runnable of `build` by running it with `--list` on `device`.
+///
+/// Runnables that can't be listed (custom harnesses, ...) are left out and get sharded as a whole.
+fn list_tests(
+    device: &Arc<Box<dyn Device>>,
+    project: &Project,
+    build: &Build,
+    options: &RunOptions,
+) -> Result<BTreeMap<String, Vec<String>>> {
+    if build.build_args.compile_mode != CompileMode::Test {
+        warn!("Sharding by test is only supported for tests, sharding by runnable instead");
+        return Ok(BTreeMap::new());
+    }
+    // User arguments (test filters...) also apply to the listing, the exact names listed then
+    // select the tests of each shard.
+    let args = ["--list", "--format", "terse"]
+        .iter()
+        .cloned()
+        .chain(options.args.iter().map(|s| &s[..]))
+        .collect::<Vec<_>>();
+    let mut tests = BTreeMap::new();
+    for runnable in &build.runnables {
+        debug!("Listing tests of {} on {}", runnable.id, device.id());
+        let build = Build {
+            runnables: vec![runnable.clone()],
+            ..build.clone()
+        };
+        let outcomes =
+            without_output_echo(|| device.run_app(project, &build, &args, &options.envs))?;
+        match outcomes.first() {
+            Some(outcome) if outcome.report.is_success() => {
+                tests.insert(
+                    runnable.id.clone(),
+                    shard::parse_test_list(&outcome.report.output),
+                );
+            }
+            _ => warn!(
+                "Couldn't list the tests of {}, it will be run as a whole",
+                runnable.id
+            ),
+        }
+    }
+    Ok(tests)
+}
+
+/// Runs jobs on several devices at once, then prints a summary of their outcomes.
+fn run_in_parallel(
+    runs: Vec<DeviceRun>,
+    project: &Project,
+    build: &Build,
+    options: &RunOptions,
+) -> Result<()> {
+    let handles = runs
+        .into_iter()
+        .map(|run| {
+            let DeviceRun {
+                label,
+                device,
+                jobs,
+            } = run;
+            let project = Project::new(&project.conf);
+            let build = build.clone();
+            let options = options.clone();
+            let handle = thread::spawn(move || {
+                set_output_prefix(Some(device.id().to_string()));
+                run_on_device(&device, &project, &build, &jobs, &options)
+            });
+            (label, handle)
+        })
+        .collect_vec();
+
+    let results = handles
+        .into_iter()
+        .map(|(label, handle)| {
+            let result = handle
+                .join()
+                .unwrap_or_else(|_| Err(anyhow!("Panic while running on {}", label)));
+            (label, result)
+        })
+        .collect_vec();
+
+    println!("Summary:");
+    for (label, result) in &results {
+        match result {
+            Ok(()) => println!("  {}: ok", label),
+            Err(e) => println!("  {}: FAILED ({})", label, e),
+        }
+    }
+    let failures = results
+        .iter()
+        .filter_map(|(_, result)| result.as_ref().err())
+        .collect_vec();
+    if failures.is_empty() {
+        return Ok(());
+    }
+    Err(devices_failure(&failures, results.len()))
+}
+
+/// The error of a run that failed on some of `devices` devices. It keeps the kind, and so the
+/// exit code, of the failures when they all share it, or else it has none.
+fn devices_failure(failures: &[&Error], devices: usize) -> Error {
+    let summary = format!(
+        "Test failed 🐛 on {} out of {} devices",
+        failures.len(),
+        devices
+    );
+    let kinds = failures
+        .iter()
+        .map(|e| DinghyError::of(e).map(mem::discriminant))
+        .collect_vec();
+    match DinghyError::of(failures[0]) {
+        Some(kind) if kinds.iter().all_equal() => anyhow!(kind.clone()).context(summary),
+        _ => anyhow!(summary),
+    }
+}
+
+fn run_on_device(
+    device: &Arc<Box<dyn Device>>,
+    project: &Project,
+    build: &Build,
+    jobs: &[RunJob],
+    options: &RunOptions,
+) -> Result<()> {
+    debug!("Run on {:?}", device);
+    let mut build_bundles = vec![];
+    let mut reports: Vec<report::RunnableReport> = vec![];
+    for job in jobs.iter().filter(|job| !job.runnables.is_empty()) {
+        let build = Build {
+            runnables: job.runnables.clone(),
+            ..build.clone()
+        };
+        // The reports of the runs of a runnable are merged into the first one.
+        let job_reports = reports.len();
+        for run in &job.runs {
+            let args = run
+                .iter()
+                .chain(&options.args)
+                .map(|s| &s[..])
+                .collect::<Vec<_>>();
+            if options.debugger {
+                debug!("Debug app");
+                build_bundles.push(device.debug_app(project, &build, &args, &options.envs)?);
+            } else {
+                debug!("Run app");
+                for outcome in device.run_app(project, &build, &args, &options.envs)? {
+                    match reports[job_reports..]
+                        .iter_mut()
+                        .find(|report| report.id == outcome.report.id)
+                    {
+                        Some(report) => report.merge(outcome.report),
+                        None => {
+                            build_bundles.push(outcome.build_bundle);
+                            reports.push(outcome.report);
+                        }
+                    }
+                }
+                if !build.build_args.no_fail_fast
+                    && reports.iter().any(|report| !report.is_success())
+                {
+                    break;
+                }
+            }
+        }
+        if !build.build_args.no_fail_fast && reports.iter().any(|report| !report.is_success()) {
+            break;
+        }
+    }
+
+    if !options.debugger {
+        let (json_report, junit_report) =
+            report::write_reports(build.target_path.join("dinghy"), device.id(), &reports)?;
+        info!(
+            "Test reports written to {} and {}",
+            json_report.display(),
+            junit_report.display()
+        );
+        let failures = reports
+            .iter()
+            .filter(|report| !report.is_success())
+            .collect_vec();
+        match failures.as_slice() {
+            [] => {}
+            [failed] if failed.is_timeout() => bail!(DinghyError::Timeout {
+                runnable: failed.id.clone(),
+                device: failed.device.clone(),
+            }),
+            [failed] => bail!(DinghyError::TestFailed(format!(
+                "Test failed 🐛 ({} on {})",
+                failed.id, failed.device
+            ))),
+            _ => bail!(DinghyError::TestFailed(format!(
+                "Tests failed 🐛 ({} out of {} runnables on {}: {})",
+                failures.len(),
+                reports.len(),
+                device.id(),
+                failures
+                    .iter()
+                    .map(|failed| if failed.is_timeout() {
+                        format!("{} timed out", failed.id)
+                    } else {
+                        failed.id.clone()
+                    })
+                    .join(", ")
+            ))),
+        }
+    }
+
+    if options.cleanup {
         for build_bundle in build_bundles {
             device.clean_app(&build_bundle)?;
         }
@@ -143,7 +609,10 @@ fn prepare_and_run(
 }
 
 fn run_lldb(device: Option<Arc<Box<dyn Device>>>) -> Result<()> {
-    let device = device.ok_or_else(|| anyhow!("No device found"))?;
+    let device = device.ok_or(DinghyError::DeviceNotFound(None))?;
+    if !device.capabilities().lldb_proxy {
+        bail!(DinghyError::unsupported(device.id(), "lldb proxies"));
+    }
     let lldb = device.start_remote_lldb()?;
     info!("lldb running at: {}", lldb);
     loop {
@@ -151,9 +620,27 @@ fn run_lldb(device: Option<Arc<Box<dyn Device>>>) -> Result<()> {
     }
 }
 
-fn show_all_platforms(dinghy: &Dinghy) -> Result<()> {
+fn show_all_platforms(dinghy: &Dinghy, format: OutputFormat) -> Result<()> {
     let mut platforms = dinghy.platforms();
     platforms.sort_by(|str1, str2| str1.id().cmp(&str2.id()));
+    if format == OutputFormat::Json {
+        let descriptions = platforms
+            .iter()
+            .map(|pf| {
+                pf.description().unwrap_or_else(|e| {
+                    warn!("Couldn't describe platform {}: {:?}", pf.id(), e);
+                    PlatformDescription {
+                        id: pf.id(),
+                        rustc_triple: pf.rustc_triple().to_string(),
+                        error: Some(format!("{:#}", e)),
+                        ..PlatformDescription::default()
+                    }
+                })
+            })
+            .collect_vec();
+        println!("{}", serde_json::to_string_pretty(&descriptions)?);
+        return Ok(());
+    }
     for pf in platforms.iter() {
         println!(
             "* {} {}",
@@ -164,20 +651,64 @@ fn show_all_platforms(dinghy: &Dinghy) -> Result<()> {
     Ok(())
 }
 
-fn show_all_devices(dinghy: &Dinghy) -> Result<()> {
-    println!("List of available devices for all platforms:");
-    show_devices(&dinghy, None)
+fn doctor(conf: &Configuration, format: OutputFormat) -> Result<()> {
+    let checks = doctor::diagnose(conf);
+    if format == OutputFormat::Json {
+        println!("{}", serde_json::to_string_pretty(&checks)?);
+    } else {
+        let mut subject = None;
+        for check in &checks {
+            if subject != Some(&check.subject) {
+                println!("{}", check.subject);
+                subject = Some(&check.subject);
+            }
+            let status = match check.status {
+                CheckStatus::Ok => "ok",
+                CheckStatus::Warning => "warning",
+                CheckStatus::Error => "error",
+            };
+            println!("  [{}] {}: {}", status, check.name, check.detail);
+            if let Some(ref hint) = check.hint {
+                println!("      hint: {}", hint);
+            }
+        }
+    }
+    let errors = checks
+        .iter()
+        .filter(|check| check.status == CheckStatus::Error)
+        .count();
+    if errors > 0 {
+        bail!("{} check(s) failed", errors)
+    }
+    Ok(())
 }
 
-fn show_all_devices_for_platform(dinghy: &Dinghy, platform: Arc<Box<dyn Platform>>) -> Result<()> {
-    println!(
-        "List of available devices for platform '{}':",
-        platform.id()
-    );
-    show_devices(&dinghy, Some(platform))
+fn show_all_devices(dinghy: &Dinghy, format: OutputFormat) -> Result<()> {
+    if format == OutputFormat::Human {
+        println!("List of available devices for all platforms:");
+    }
+    show_devices(dinghy, None, format)
 }
 
-fn show_devices(dinghy: &Dinghy, platform: Option<Arc<Box<dyn Platform>>>) -> Result<()> {
+fn show_all_devices_for_platform(
+    dinghy: &Dinghy,
+    platform: Arc<Box<dyn Platform>>,
+    format: OutputFormat,
+) -> Result<()> {
+    if format == OutputFormat::Human {
+        println!(
+            "List of available devices for platform '{}':",
+            platform.id()
+        );
+    }
+    show_devices(dinghy, Some(platform), format)
+}
+
+fn show_devices(
+    dinghy: &Dinghy,
+    platform: Option<Arc<Box<dyn Platform>>>,
+    format: OutputFormat,
+) -> Result<()> {
     let devices = dinghy
         .devices()
         .into_iter()
@@ -188,6 +719,15 @@ fn show_devices(dinghy: &Dinghy, platform: Option<Arc<Box<dyn Platform>>>) -> Re
         })
         .collect::<Vec<_>>();
 
+    if format == OutputFormat::Json {
+        let descriptions = devices
+            .iter()
+            .map(|device| dinghy.describe_device(&***device))
+            .collect::<Vec<_>>();
+        println!("{}", serde_json::to_string_pretty(&descriptions)?);
+        return Ok(());
+    }
+
     if devices.is_empty() {
         error!("No matching device found");
         println!("No matching device found");
@@ -199,79 +739,337 @@ fn show_devices(dinghy: &Dinghy, platform: Option<Arc<Box<dyn Platform>>>) -> Re
                 .filter(|pf| pf.is_compatible_with(&**device))
                 .cloned()
                 .collect();
-            println!("{}: {:?}", device, pf);
+            println!(
+                "{}: {:?} ({})",
+                device,
+                pf,
+                device.capabilities().names().join(", ")
+            );
         }
     }
     Ok(())
 }
 
-fn select_platform_and_device_from_cli(
+fn matches_device_hint(hint: &str, candidate: &str) -> bool {
+    let hint = hint.to_lowercase();
+    let candidate = candidate.to_lowercase();
+    let mut rest = candidate.as_str();
+    for part in hint.split('*') {
+        match rest.find(part) {
+            Some(ix) => rest = &rest[ix + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+/// Whether `hint`, if any, selects `device`, which it must name when it starts on demand.
+fn is_selected_by(device: &dyn Device, hint: Option<&str>) -> bool {
+    match hint {
+        None => !device.starts_on_demand(),
+        Some(hint) if device.starts_on_demand() => {
+            let prefix = hint.split('*').next().unwrap_or_default().to_lowercase();
+            !prefix.is_empty()
+                && device.id().to_lowercase().starts_with(&prefix)
+                && matches_device_hint(hint, device.id())
+        }
+        Some(hint) => matches_device_hint(hint, &format!("{}", device)),
+    }
+}
+
+/// The platform to build for `device` when only a device hint is given, see
+/// `default_platform_id`.
+fn default_platform_for(dinghy: &Dinghy, device: &dyn Device) -> Option<Arc<Box<dyn Platform>>> {
+    let compatible = dinghy
+        .platforms()
+        .into_iter()
+        .filter(|pf| pf.is_compatible_with(device))
+        .collect_vec();
+    let api_level = device
+        .description()
+        .properties
+        .get("api_level")
+        .and_then(|it| it.parse().ok());
+    let id = default_platform_id(
+        &compatible.iter().map(|pf| pf.id()).collect_vec(),
+        api_level,
+    )?;
+    compatible.into_iter().find(|pf| pf.id() == id)
+}
+
+/// Among the ids of the platforms compatible with a device, the one to build for: the
+/// auto-android platform of the highest API level when the device tells its `api_level`, the
+/// plain `auto-android-<cpu>` one, of the default API level, when it doesn't, and otherwise
+/// the first platform that is not an auto-android one.
+fn default_platform_id(compatible: &[String], api_level: Option<u32>) -> Option<String> {
+    let auto_android = if api_level.is_some() {
+        compatible
+            .iter()
+            .filter_map(|id| Some((auto_android_api_level(id)?, id)))
+            .max_by_key(|(api_level, _)| *api_level)
+            .map(|(_, id)| id)
+    } else {
+        compatible
+            .iter()
+            .find(|id| id.starts_with("auto-android") && id.split('-').count() == 3)
+    };
+    auto_android
+        .or_else(|| compatible.iter().find(|id| !id.starts_with("auto-android")))
+        .cloned()
+}
+
+/// The API level of `auto-android-<cpu>-api<level>` platforms.
+fn auto_android_api_level(platform_id: &str) -> Option<u32> {
+    if !platform_id.starts_with("auto-android") {
+        return None;
+    }
+    platform_id
+        .rsplit('-')
+        .next()?
+        .strip_prefix("api")?
+        .parse()
+        .ok()
+}
+
+fn select_platform_and_devices_from_cli(
     matches: &ArgMatches,
     dinghy: &Dinghy,
-) -> Result<(Arc<Box<dyn Platform>>, Option<Arc<Box<dyn Device>>>)> {
+) -> Result<(Arc<Box<dyn Platform>>, Vec<Arc<Box<dyn Device>>>)> {
+    let all_matching = matches.is_present("ALL_MATCHING") || matches.is_present("SHARD");
     if let Some(platform_name) = matches.value_of("PLATFORM") {
         let platform = dinghy
             .platform_by_name(platform_name)
-            .ok_or_else(|| anyhow!("No '{}' platform found", platform_name))?;
+            .ok_or_else(|| DinghyError::PlatformNotFound(format!("'{}'", platform_name)))?;
 
-        let device = dinghy
+        let devices = dinghy
             .devices()
             .into_iter()
-            .filter(|device| {
-                matches
-                    .value_of("DEVICE")
-                    .map(|filter| {
-                        format!("{}", device)
-                            .to_lowercase()
-                            .contains(&filter.to_lowercase())
-                    })
-                    .unwrap_or(true)
-            })
+            .filter(|device| is_selected_by(&***device, matches.value_of("DEVICE")))
             .filter(|it| platform.is_compatible_with(&**it.as_ref()))
-            .next();
+            .take(if all_matching { usize::MAX } else { 1 })
+            .collect();
 
-        Ok((platform, device))
+        Ok((platform, devices))
     } else if let Some(device_filter) = matches.value_of("DEVICE") {
-        let is_banned_auto_platform_id = |id: &str| -> bool {
-            id.contains("auto-android")
-                && (id.contains("min") || id.contains("latest") || id.contains("api"))
-        };
         let devices = dinghy
             .devices()
             .into_iter()
-            .filter(move |it| {
-                format!("{:?}", it)
-                    .to_lowercase()
-                    .contains(&device_filter.to_lowercase())
+            .filter(|it| {
+                matches_device_hint(device_filter, &format!("{:?}", it))
+                    && (!it.starts_on_demand() || is_selected_by(&***it, Some(device_filter)))
             })
             .collect_vec();
         if devices.len() == 0 {
-            bail!("No devices found for name hint `{}'", device_filter)
+            bail!(DinghyError::DeviceNotFound(Some(device_filter.to_string())))
         }
-        devices
-            .into_iter()
+        let (platform, device) = devices
+            .iter()
+            .cloned()
             .filter_map(|d| {
-                let pf = dinghy
-                    .platforms()
-                    .iter()
-                    .filter(|pf| !is_banned_auto_platform_id(&pf.id()))
-                    .find(|pf| pf.is_compatible_with(&**d))
-                    .cloned();
+                let pf = default_platform_for(dinghy, &**d);
                 debug!(
                     "Looking for platform for {}: found {:?}",
                     d.id(),
                     pf.as_ref().map(|p| p.id())
                 );
-                pf.map(|it| (it, Some(d)))
+                pf.map(|it| (it, d))
             })
             .next()
             .ok_or_else(|| {
-                anyhow!(
-                    "No device and platform combination found for device hint `{}'",
-                    device_filter
-                )
-            })
+                DinghyError::PlatformNotFound(format!("device hint `{}'", device_filter))
+            })?;
+        if all_matching {
+            let devices = devices
+                .into_iter()
+                .filter(|it| platform.is_compatible_with(&**it.as_ref()))
+                .collect();
+            Ok((platform, devices))
+        } else {
+            Ok((platform, vec![device]))
+        }
     } else {
-        Ok((dinghy.host_platform(), Some(dinghy.host_device())))
+        Ok((dinghy.host_platform(), vec![dinghy.host_device()]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dinghy_lib::DeviceCapabilities;
+    use dinghy_lib::DeviceCompatibility;
+    use dinghy_lib::DeviceDescription;
+    use dinghy_lib::RunnableOutcome;
+    use std::fmt;
+
+    /// A device shown as `shown`, starting on demand like an AVD whose emulator isn't running.
+    #[derive(Debug)]
+    struct FakeDevice {
+        id: &'static str,
+        shown: &'static str,
+        on_demand: bool,
+    }
+
+    impl fmt::Display for FakeDevice {
+        fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+            fmt.write_str(self.shown)
+        }
+    }
+
+    impl DeviceCompatibility for FakeDevice {}
+
+    impl Device for FakeDevice {
+        fn capabilities(&self) -> DeviceCapabilities {
+            DeviceCapabilities::default()
+        }
+
+        fn description(&self) -> DeviceDescription {
+            DeviceDescription::default()
+        }
+
+        fn id(&self) -> &str {
+            self.id
+        }
+
+        fn name(&self) -> &str {
+            self.id
+        }
+
+        fn starts_on_demand(&self) -> bool {
+            self.on_demand
+        }
+
+        fn run_app(
+            &self,
+            _project: &Project,
+            _build: &Build,
+            _args: &[&str],
+            _envs: &[EnvSpec],
+        ) -> Result<Vec<RunnableOutcome>> {
+            unimplemented!()
+        }
+    }
+
+    #[test]
+    fn test_devices_failure_keeps_a_shared_kind() {
+        let unreachable = |device: &str| anyhow!(DinghyError::DeviceUnreachable(device.into()));
+        let (a, b) = (unreachable("a"), unreachable("b"));
+        assert_eq!(10, exit_code(&devices_failure(&[&a, &b], 3)));
+
+        let timeout = anyhow!(DinghyError::Timeout {
+            runnable: "t".into(),
+            device: "b".into()
+        });
+        assert_eq!(1, exit_code(&devices_failure(&[&a, &timeout], 3)));
+        let other = anyhow!("adb crashed");
+        assert_eq!(1, exit_code(&devices_failure(&[&other], 1)));
+        assert_eq!(1, exit_code(&devices_failure(&[&a, &other], 2)));
+
+        let failed = |device: &str| anyhow!(DinghyError::TestFailed(device.into()));
+        let (c, d) = (failed("c"), failed("d"));
+        assert_eq!(6, exit_code(&devices_failure(&[&c, &d], 2)));
+        assert_eq!(1, exit_code(&devices_failure(&[&c, &other], 2)));
+    }
+
+    #[test]
+    fn test_is_selected_by() {
+        let stopped = FakeDevice {
+            id: "avd:Pixel_6_API_33",
+            shown: "avd:Pixel_6_API_33",
+            on_demand: true,
+        };
+        let running = FakeDevice {
+            id: "avd:ci",
+            shown: "avd:ci (emulator-5554 Android SDK built for x86_64)",
+            on_demand: false,
+        };
+        let selected = |device: &FakeDevice, hint| is_selected_by(device, hint);
+
+        // Without a hint, or with hints not starting its id, an AVD isn't booted.
+        assert!(!selected(&stopped, None));
+        assert!(!selected(&stopped, Some("pixel")));
+        assert!(!selected(&stopped, Some("*avd:pixel")));
+        assert!(!selected(&stopped, Some("android")));
+        assert!(selected(&stopped, Some("avd:")));
+        assert!(selected(&stopped, Some("AVD:Pixel*33")));
+        assert!(!selected(&stopped, Some("avd:Pixel*34")));
+
+        // A running emulator is selected like any device, by what it is shown as.
+        assert!(selected(&running, None));
+        assert!(selected(&running, Some("emulator-5554")));
+        assert!(selected(&running, Some("avd:ci")));
+        assert!(!selected(&running, Some("pixel")));
+    }
+
+    #[test]
+    fn test_select_runnable() {
+        let runnable = |id: &str| Runnable {
+            id: id.to_string(),
+            ..Runnable::default()
+        };
+        let runnables = vec![
+            runnable("my_crate-0123456789abcdef"),
+            runnable("my_crate_it-fedcba9876543210"),
+            runnable("other-0011223344556677"),
+        ];
+        let selected = |name| select_runnable(&runnables, name).map(|it| it.id.as_str());
+
+        assert_eq!(
+            "my_crate-0123456789abcdef",
+            selected(Some("my_crate")).unwrap()
+        );
+        assert_eq!(
+            "my_crate_it-fedcba9876543210",
+            selected(Some("my_crate_it-fedcba9876543210")).unwrap()
+        );
+        assert!(selected(Some("my")).is_err());
+        assert!(selected(Some("missing")).is_err());
+        // Without a name, only a lone runnable is picked.
+        assert!(selected(None).is_err());
+        assert_eq!(
+            "other-0011223344556677",
+            select_runnable(&runnables[2..], None).unwrap().id
+        );
+    }
+
+    #[test]
+    fn test_default_platform_id() {
+        let ids = |ids: &[&str]| ids.iter().map(|id| id.to_string()).collect_vec();
+        assert_eq!(
+            Some(33),
+            auto_android_api_level("auto-android-aarch64-api33")
+        );
+        assert_eq!(None, auto_android_api_level("auto-android-aarch64-latest"));
+        assert_eq!(None, auto_android_api_level("auto-android-aarch64"));
+        assert_eq!(None, auto_android_api_level("raspbian-api33"));
+
+        // What an API 30 device is compatible with: `-min` and `-latest` are not considered.
+        let api30 = ids(&[
+            "auto-android-aarch64-api21",
+            "auto-android-aarch64-api30",
+            "auto-android-aarch64-min",
+            "auto-android-aarch64",
+        ]);
+        assert_eq!(
+            Some("auto-android-aarch64-api30".to_string()),
+            default_platform_id(&api30, Some(30))
+        );
+        // A device of an unknown API level is compatible with all of them.
+        let unknown = ids(&[
+            "auto-android-aarch64-api21",
+            "auto-android-aarch64-api33",
+            "auto-android-aarch64-latest",
+            "auto-android-aarch64-min",
+            "auto-android-aarch64",
+        ]);
+        assert_eq!(
+            Some("auto-android-aarch64".to_string()),
+            default_platform_id(&unknown, None)
+        );
+        // A device below every API level is compatible with none of them.
+        assert_eq!(None, default_platform_id(&[], Some(16)));
+        assert_eq!(
+            Some("raspbian".to_string()),
+            default_platform_id(&ids(&["raspbian"]), None)
+        );
     }
 }
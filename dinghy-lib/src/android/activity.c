@@ -0,0 +1,268 @@
+// Entry point of the apps dinghy wraps runnables in, started as an android.app.NativeActivity.
+//
+// It extracts the test data the app packages as assets to its files directory, then forks: the
+// child loads the runnable as a library, and calls its main with the arguments, environment and
+// working directory dinghy wrote to files/dinghy.run. The parent logs what the child prints, then
+// its exit code, under the tag of the run, and finishes the activity.
+
+#include <android/asset_manager.h>
+#include <android/log.h>
+#include <android/native_activity.h>
+#include <dlfcn.h>
+#include <errno.h>
+#include <fcntl.h>
+#include <limits.h>
+#include <pthread.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/stat.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+#define RUN_FILE "dinghy.run"
+#define ASSETS_LIST "dinghy.files"
+#define RUNNABLE_LIB "libdinghy_runnable.so"
+#define PID_FILE "dinghy.pid"
+#define EXIT_CODE_SENTINEL "FORWARD_RESULT_TO_DINGHY_BECAUSE_ADB_DOES_NOT="
+// Below the size logd truncates messages to.
+#define LOG_LINE_MAX 4000
+
+static ANativeActivity *dinghy_activity;
+static char tag[64] = "dinghy";
+
+// The content of `path`, NUL terminated, or NULL.
+static char *read_file(const char *path, size_t *len) {
+    int fd = open(path, O_RDONLY | O_CLOEXEC);
+    if (fd < 0) {
+        return NULL;
+    }
+    size_t capacity = 4096;
+    char *content = malloc(capacity + 1);
+    *len = 0;
+    for (;;) {
+        if (*len == capacity) {
+            capacity *= 2;
+            content = realloc(content, capacity + 1);
+        }
+        ssize_t n = read(fd, content + *len, capacity - *len);
+        if (n < 0 && errno == EINTR) {
+            continue;
+        }
+        if (n <= 0) {
+            break;
+        }
+        *len += n;
+    }
+    close(fd);
+    content[*len] = 0;
+    return content;
+}
+
+// Creates the parent directories of `path`.
+static void make_parents(char *path) {
+    for (char *p = path + 1; *p; p++) {
+        if (*p == '/') {
+            *p = 0;
+            mkdir(path, 0700);
+            *p = '/';
+        }
+    }
+}
+
+// Extracts the assets listed, one per line, in the ASSETS_LIST asset to `files`.
+static int extract_assets(AAssetManager *assets, const char *files) {
+    AAsset *list = AAssetManager_open(assets, ASSETS_LIST, AASSET_MODE_BUFFER);
+    if (!list) {
+        return 0;
+    }
+    size_t len = AAsset_getLength(list);
+    char *names = malloc(len + 1);
+    memcpy(names, AAsset_getBuffer(list), len);
+    names[len] = 0;
+    AAsset_close(list);
+    int result = 0;
+    for (char *name = strtok(names, "\n"); name; name = strtok(NULL, "\n")) {
+        AAsset *asset = AAssetManager_open(assets, name, AASSET_MODE_STREAMING);
+        char path[PATH_MAX];
+        snprintf(path, sizeof(path), "%s/%s", files, name);
+        make_parents(path);
+        int fd = open(path, O_WRONLY | O_CREAT | O_TRUNC | O_CLOEXEC, 0600);
+        if (!asset || fd < 0) {
+            __android_log_print(ANDROID_LOG_ERROR, tag, "Couldn't extract %s to %s", name, path);
+            result = -1;
+        } else {
+            char buffer[16384];
+            int n;
+            while ((n = AAsset_read(asset, buffer, sizeof(buffer))) > 0) {
+                write(fd, buffer, n);
+            }
+        }
+        if (fd >= 0) {
+            close(fd);
+        }
+        if (asset) {
+            AAsset_close(asset);
+        }
+    }
+    free(names);
+    return result;
+}
+
+// Logs the lines read from `fd` until it is closed.
+static void log_lines(int fd) {
+    char line[LOG_LINE_MAX + 1];
+    size_t len = 0;
+    char c;
+    for (;;) {
+        ssize_t n = read(fd, &c, 1);
+        if (n < 0 && errno == EINTR) {
+            continue;
+        }
+        if (n <= 0) {
+            break;
+        }
+        if (c != '\n') {
+            line[len++] = c;
+        }
+        if (c == '\n' || len == LOG_LINE_MAX) {
+            line[len] = 0;
+            __android_log_write(ANDROID_LOG_INFO, tag, line);
+            len = 0;
+        }
+    }
+    if (len > 0) {
+        line[len] = 0;
+        __android_log_write(ANDROID_LOG_INFO, tag, line);
+    }
+}
+
+// Runs the runnable as files/dinghy.run tells, and returns its exit code.
+static int launch(void) {
+    const char *files = dinghy_activity->internalDataPath;
+    char path[PATH_MAX];
+    snprintf(path, sizeof(path), "%s/%s", files, RUN_FILE);
+    size_t len;
+    char *run = read_file(path, &len);
+    if (!run) {
+        __android_log_print(ANDROID_LOG_ERROR, tag, "Couldn't read %s", path);
+        return 1;
+    }
+
+    // NUL terminated records, whose first character tells what they are: T for the tag of the
+    // run, C for the working directory relative to the files directory, A for an argument and E
+    // for an environment variable.
+    char *cwd = "";
+    char **argv = calloc(len + 2, sizeof(char *));
+    int argc = 1;
+    char **envs = calloc(len + 1, sizeof(char *));
+    int envc = 0;
+    for (char *record = run; record < run + len; record += strlen(record) + 1) {
+        switch (record[0]) {
+        case 'T':
+            snprintf(tag, sizeof(tag), "%s", record + 1);
+            break;
+        case 'C':
+            cwd = record + 1;
+            break;
+        case 'A':
+            argv[argc++] = record + 1;
+            break;
+        case 'E':
+            envs[envc++] = record + 1;
+            break;
+        }
+    }
+
+    if (extract_assets(dinghy_activity->assetManager, files) != 0) {
+        return 1;
+    }
+
+    // The runnable is packaged next to this library.
+    Dl_info info;
+    if (!dladdr((void *)&launch, &info) || !info.dli_fname) {
+        __android_log_write(ANDROID_LOG_ERROR, tag, "Couldn't find the native libraries");
+        return 1;
+    }
+    char runnable[PATH_MAX];
+    snprintf(runnable, sizeof(runnable), "%s", info.dli_fname);
+    char *slash = strrchr(runnable, '/');
+    snprintf(slash ? slash + 1 : runnable, sizeof(runnable) - (slash ? slash + 1 - runnable : 0),
+             "%s", RUNNABLE_LIB);
+    argv[0] = runnable;
+
+    int output[2];
+    if (pipe(output) != 0) {
+        __android_log_print(ANDROID_LOG_ERROR, tag, "pipe failed: %s", strerror(errno));
+        return 1;
+    }
+    pid_t pid = fork();
+    if (pid < 0) {
+        __android_log_print(ANDROID_LOG_ERROR, tag, "fork failed: %s", strerror(errno));
+        return 1;
+    }
+    if (pid == 0) {
+        dup2(output[1], STDOUT_FILENO);
+        dup2(output[1], STDERR_FILENO);
+        close(output[0]);
+        close(output[1]);
+        setvbuf(stdout, NULL, _IOLBF, 0);
+        snprintf(path, sizeof(path), "%s/%s", files, cwd);
+        if (chdir(path) != 0) {
+            fprintf(stderr, "Couldn't change directory to %s: %s\n", path, strerror(errno));
+            _exit(1);
+        }
+        for (int i = 0; i < envc; i++) {
+            char *value = strchr(envs[i], '=');
+            if (value) {
+                *value = 0;
+                setenv(envs[i], value + 1, 1);
+            }
+        }
+        FILE *pid_file = fopen(PID_FILE, "w");
+        if (pid_file) {
+            fprintf(pid_file, "%d\n", getpid());
+            fclose(pid_file);
+        }
+        void *library = dlopen(runnable, RTLD_NOW);
+        if (!library) {
+            fprintf(stderr, "Couldn't load %s: %s\n", runnable, dlerror());
+            _exit(127);
+        }
+        int (*runnable_main)(int, char **) = (int (*)(int, char **))dlsym(library, "main");
+        if (!runnable_main) {
+            fprintf(stderr, "No exported main in %s: %s\n", runnable, dlerror());
+            _exit(127);
+        }
+        exit(runnable_main(argc, argv));
+    }
+    close(output[1]);
+    log_lines(output[0]);
+    close(output[0]);
+    int status;
+    while (waitpid(pid, &status, 0) < 0 && errno == EINTR) {
+    }
+    return WIFEXITED(status) ? WEXITSTATUS(status) : 128 + WTERMSIG(status);
+}
+
+static void *run(void *unused) {
+    (void)unused;
+    int code = launch();
+    __android_log_print(ANDROID_LOG_INFO, tag, "%s%d", EXIT_CODE_SENTINEL, code);
+    ANativeActivity_finish(dinghy_activity);
+    return NULL;
+}
+
+void ANativeActivity_onCreate(ANativeActivity *activity, void *saved_state, size_t saved_state_size) {
+    (void)saved_state;
+    (void)saved_state_size;
+    dinghy_activity = activity;
+    // Once per process, even when the activity is recreated.
+    static int started;
+    if (started++) {
+        return;
+    }
+    pthread_t thread;
+    pthread_create(&thread, NULL, run, NULL);
+    pthread_detach(thread);
+}
@@ -0,0 +1,502 @@
+//! Minimal APKs wrapping a runnable, so that it runs in an app process.
+//!
+//! The APK has no Java code: its activity is a `NativeActivity`, whose native library (built
+//! from `activity.c`) loads the runnable, one of the native libraries of the app with its
+//! dynamic libraries, and calls its `main`. The test data are assets, which the activity
+//! extracts to the files directory of the app.
+
+use crate::doctor::SystemLookup;
+use crate::errors::*;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use walkdir::WalkDir;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+/// Name of the runnable among the native libraries of the app.
+pub static RUNNABLE_LIB_NAME: &str = "libdinghy_runnable.so";
+/// Name of the native library of the activity among the native libraries of the app.
+pub static ACTIVITY_LIB_NAME: &str = "libdinghy_activity.so";
+/// The source of the native library of the activity.
+static ACTIVITY_SOURCE: &str = include_str!("activity.c");
+/// The asset listing the other assets, for the activity to extract them.
+static ASSETS_LIST: &str = "dinghy.files";
+/// Where the activity reads how to run the runnable, in the files directory of the app.
+pub static RUN_FILE: &str = "dinghy.run";
+
+/// The SDK tools building and signing APKs.
+#[derive(Clone, Debug)]
+pub struct ApkTools {
+    pub aapt2: PathBuf,
+    pub zipalign: PathBuf,
+    pub apksigner: PathBuf,
+    pub android_jar: PathBuf,
+    pub target_api: u32,
+    pub keystore: PathBuf,
+}
+
+impl ApkTools {
+    /// Finds the latest build tools and platform of the Android SDK, and the debug keystore
+    /// (created if needed, like Android Studio does).
+    pub fn find() -> Result<ApkTools> {
+        for sdk in super::probable_sdk_locs(&SystemLookup)? {
+            let build_tools = match latest(&sdk.join("build-tools"), |name| {
+                name.split('.')
+                    .map(|it| it.parse::<u32>().ok())
+                    .collect::<Option<Vec<_>>>()
+            }) {
+                Some((_, build_tools)) => build_tools,
+                None => continue,
+            };
+            let (target_api, platform) = match latest(&sdk.join("platforms"), |name| {
+                name.strip_prefix("android-")
+                    .and_then(|api| api.parse::<u32>().ok())
+            }) {
+                Some(platform) => platform,
+                None => continue,
+            };
+            return Ok(ApkTools {
+                aapt2: build_tools.join("aapt2"),
+                zipalign: build_tools.join("zipalign"),
+                apksigner: build_tools.join("apksigner"),
+                android_jar: platform.join("android.jar"),
+                target_api,
+                keystore: debug_keystore()?,
+            });
+        }
+        bail!("Android SDK build-tools and platforms not found, set ANDROID_SDK_ROOT")
+    }
+}
+
+/// The entry of `dir` with the highest version, as parsed by `version`.
+fn latest<V: Ord>(dir: &Path, version: impl Fn(&str) -> Option<V>) -> Option<(V, PathBuf)> {
+    dir.read_dir()
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let version = version(&entry.file_name().to_string_lossy())?;
+            Some((version, entry.path()))
+        })
+        .max_by(|a, b| a.0.cmp(&b.0))
+}
+
+fn debug_keystore() -> Result<PathBuf> {
+    let android_dir = dirs::home_dir()
+        .ok_or_else(|| anyhow!("No home directory"))?
+        .join(".android");
+    let keystore = android_dir.join("debug.keystore");
+    if !keystore.is_file() {
+        fs::create_dir_all(&android_dir)?;
+        let mut keytool = Command::new("keytool");
+        keytool
+            .arg("-genkeypair")
+            .arg("-keystore")
+            .arg(&keystore)
+            .args(["-storepass", "android", "-alias", "androiddebugkey"])
+            .args(["-keypass", "android", "-keyalg", "RSA", "-keysize", "2048"])
+            .args([
+                "-validity",
+                "10000",
+                "-dname",
+                "CN=Android Debug,O=Android,C=US",
+            ]);
+        run(&mut keytool)?;
+    }
+    Ok(keystore)
+}
+
+/// The package of the app wrapping `runnable_id`, like `rs.dinghy.my_crate_0123456789abcdef`.
+pub fn package_name(runnable_id: &str) -> String {
+    let name: String = runnable_id
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    format!("rs.dinghy.{}", name)
+}
+
+/// The ABI directory of the native libraries of an APK, for `rustc_triple`.
+pub fn abi(rustc_triple: &str) -> Option<&'static str> {
+    Some(match rustc_triple.split('-').next()? {
+        "aarch64" => "arm64-v8a",
+        "armv7" | "thumbv7neon" => "armeabi-v7a",
+        "arm" => "armeabi",
+        "i686" => "x86",
+        "x86_64" => "x86_64",
+        _ => return None,
+    })
+}
+
+/// The clang target of the NDK building for `abi` at `api`, like `aarch64-linux-android21`.
+pub fn clang_target(abi: &str, api: u32) -> Option<String> {
+    let target = match abi {
+        "arm64-v8a" => "aarch64-linux-android",
+        "armeabi-v7a" => "armv7a-linux-androideabi",
+        "x86" => "i686-linux-android",
+        "x86_64" => "x86_64-linux-android",
+        _ => return None,
+    };
+    Some(format!("{}{}", target, api))
+}
+
+/// The API level an NDK built `elf` for, from its `.note.android.ident` note.
+pub fn android_api(elf: &[u8]) -> Option<u32> {
+    // The note: name size (8), description size, type (1), "Android\0", then the API level.
+    let header = b"\x01\x00\x00\x00Android\x00";
+    let start = elf
+        .windows(header.len())
+        .enumerate()
+        .filter(|(start, _)| *start >= 8)
+        .find(|(start, window)| {
+            *window == header && elf[start - 8..start - 4] == 8u32.to_le_bytes()[..]
+        })?
+        .0;
+    let api = elf.get(start + header.len()..start + header.len() + 4)?;
+    Some(u32::from_le_bytes([api[0], api[1], api[2], api[3]]))
+}
+
+pub fn manifest(package: &str, min_api: u32, target_api: u32) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<manifest xmlns:android="http://schemas.android.com/apk/res/android"
+    package="{}"
+    android:versionCode="1"
+    android:versionName="1.0">
+    <uses-sdk android:minSdkVersion="{}" android:targetSdkVersion="{}" />
+    <uses-permission android:name="android.permission.INTERNET" />
+    <application
+        android:label="dinghy"
+        android:hasCode="false"
+        android:debuggable="true"
+        android:extractNativeLibs="true">
+        <activity
+            android:name="android.app.NativeActivity"
+            android:exported="true"
+            android:configChanges="orientation|screenSize|screenLayout|keyboard|keyboardHidden|navigation|uiMode|density|locale|layoutDirection|fontScale|smallestScreenSize">
+            <meta-data android:name="android.app.lib_name" android:value="{}" />
+        </activity>
+    </application>
+</manifest>
+"#,
+        package,
+        min_api,
+        target_api,
+        ACTIVITY_LIB_NAME
+            .trim_start_matches("lib")
+            .trim_end_matches(".so")
+    )
+}
+
+/// Builds, with the `clang` of the NDK for `clang_target`, the native library of the activity
+/// in `work_dir`.
+pub fn build_activity(clang: &Path, clang_target: &str, work_dir: &Path) -> Result<PathBuf> {
+    fs::create_dir_all(work_dir)?;
+    let source = work_dir.join("activity.c");
+    fs::write(&source, ACTIVITY_SOURCE)?;
+    let library = work_dir.join(ACTIVITY_LIB_NAME);
+    run(Command::new(clang)
+        .arg(format!("--target={}", clang_target))
+        .args(["-shared", "-fPIC", "-O2", "-o"])
+        .arg(&library)
+        .arg(&source)
+        .args(["-landroid", "-llog"]))?;
+    Ok(library)
+}
+
+/// The native libraries of the APK: the activity, the runnable and its dynamic libraries,
+/// with their path in the APK.
+pub fn native_entries(
+    abi: &str,
+    activity: &Path,
+    exe: &Path,
+    libraries: &[PathBuf],
+) -> Result<Vec<(String, PathBuf)>> {
+    let mut entries = vec![
+        (
+            format!("lib/{}/{}", abi, ACTIVITY_LIB_NAME),
+            activity.to_path_buf(),
+        ),
+        (
+            format!("lib/{}/{}", abi, RUNNABLE_LIB_NAME),
+            exe.to_path_buf(),
+        ),
+    ];
+    for library in libraries {
+        let name = library
+            .file_name()
+            .ok_or_else(|| anyhow!("Invalid library {}", library.display()))?;
+        entries.push((
+            format!("lib/{}/{}", abi, name.to_string_lossy()),
+            library.to_path_buf(),
+        ));
+    }
+    Ok(entries)
+}
+
+/// The assets of the APK: the files of `bundle_dir` but `exe`, as `dinghy/<bundle id>/...`,
+/// and the list of them the activity extracts, written in `work_dir`.
+pub fn asset_entries(
+    bundle_dir: &Path,
+    exe: &Path,
+    work_dir: &Path,
+) -> Result<Vec<(String, PathBuf)>> {
+    let bundle_id = bundle_dir
+        .file_name()
+        .ok_or_else(|| anyhow!("Invalid bundle {}", bundle_dir.display()))?
+        .to_string_lossy();
+    let mut assets = vec![];
+    for entry in WalkDir::new(bundle_dir)
+        .follow_links(true)
+        .sort_by(|a, b| a.file_name().cmp(b.file_name()))
+    {
+        let entry = entry?;
+        if !entry.file_type().is_file() || entry.path() == exe {
+            continue;
+        }
+        let relative = entry.path().strip_prefix(bundle_dir)?;
+        let name = Path::new("dinghy").join(&*bundle_id).join(relative);
+        assets.push((
+            name.to_string_lossy().to_string(),
+            entry.path().to_path_buf(),
+        ));
+    }
+    fs::create_dir_all(work_dir)?;
+    let list = work_dir.join(ASSETS_LIST);
+    fs::write(
+        &list,
+        assets
+            .iter()
+            .map(|(name, _)| format!("{}\n", name))
+            .collect::<String>(),
+    )?;
+    let mut entries = vec![(format!("assets/{}", ASSETS_LIST), list)];
+    entries.extend(
+        assets
+            .into_iter()
+            .map(|(name, source)| (format!("assets/{}", name), source)),
+    );
+    Ok(entries)
+}
+
+/// What the activity reads to run the runnable: the `tag` it logs under, its working
+/// directory, relative to the files directory of the app, its arguments and environment.
+pub fn run_file(tag: &str, cwd: &str, args: &[String], env: &[(String, String)]) -> Vec<u8> {
+    let mut records = vec![format!("T{}", tag), format!("C{}", cwd)];
+    records.extend(args.iter().map(|arg| format!("A{}", arg)));
+    records.extend(env.iter().map(|(key, value)| format!("E{}={}", key, value)));
+    records
+        .iter()
+        .flat_map(|record| record.bytes().chain(Some(0)))
+        .collect()
+}
+
+/// Writes to `output` the entries of `base` (the compiled manifest), then `entries`.
+pub fn write_apk(base: &Path, entries: &[(String, PathBuf)], output: &Path) -> Result<()> {
+    let mut base = ZipArchive::new(fs::File::open(base)?)?;
+    let mut apk = ZipWriter::new(fs::File::create(output)?);
+    for index in 0..base.len() {
+        // As is: the package manager wants resources.arsc stored uncompressed.
+        apk.raw_copy_file(base.by_index(index)?)?;
+    }
+    let options = FileOptions::default()
+        .compression_method(CompressionMethod::Deflated)
+        .unix_permissions(0o755);
+    for (name, source) in entries {
+        let mut content = vec![];
+        fs::File::open(source)
+            .with_context(|| format!("Couldn't read {}", source.display()))?
+            .read_to_end(&mut content)?;
+        apk.start_file(name.as_str(), options)?;
+        apk.write_all(&content)?;
+    }
+    apk.finish()?;
+    Ok(())
+}
+
+/// Builds, in `work_dir`, the signed APK of `package` with `entries`.
+pub fn build_apk(
+    tools: &ApkTools,
+    work_dir: &Path,
+    package: &str,
+    min_api: u32,
+    entries: &[(String, PathBuf)],
+) -> Result<PathBuf> {
+    fs::create_dir_all(work_dir)?;
+    let manifest_path = work_dir.join("AndroidManifest.xml");
+    fs::write(
+        &manifest_path,
+        manifest(package, min_api, tools.target_api.max(min_api)),
+    )?;
+    let base = work_dir.join("base.apk");
+    run(Command::new(&tools.aapt2)
+        .arg("link")
+        .arg("-o")
+        .arg(&base)
+        .arg("--manifest")
+        .arg(&manifest_path)
+        .arg("-I")
+        .arg(&tools.android_jar))?;
+    let unaligned = work_dir.join("unaligned.apk");
+    write_apk(&base, entries, &unaligned)?;
+    let unsigned = work_dir.join("unsigned.apk");
+    run(Command::new(&tools.zipalign)
+        .args(["-p", "-f", "4"])
+        .arg(&unaligned)
+        .arg(&unsigned))?;
+    let apk = work_dir.join(format!("{}.apk", package));
+    run(Command::new(&tools.apksigner)
+        .arg("sign")
+        .arg("--ks")
+        .arg(&tools.keystore)
+        .args([
+            "--ks-pass",
+            "pass:android",
+            "--ks-key-alias",
+            "androiddebugkey",
+        ])
+        .args(["--key-pass", "pass:android", "--out"])
+        .arg(&apk)
+        .arg(&unsigned))?;
+    Ok(apk)
+}
+
+fn run(command: &mut Command) -> Result<()> {
+    debug!("Running {:?}", command);
+    let output = command
+        .output()
+        .with_context(|| format!("Couldn't run {:?}", command))?;
+    if !output.status.success() {
+        bail!(
+            "{:?} failed: {}",
+            command,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest_and_package() {
+        let package = package_name("my-crate-0123456789abcdef");
+        assert_eq!("rs.dinghy.my_crate_0123456789abcdef", package);
+        let manifest = manifest(&package, 21, 30);
+        assert!(manifest.contains(r#"package="rs.dinghy.my_crate_0123456789abcdef""#));
+        assert!(manifest
+            .contains(r#"<uses-sdk android:minSdkVersion="21" android:targetSdkVersion="30" />"#));
+        assert!(manifest.contains(r#"android:debuggable="true""#));
+        assert!(manifest.contains(r#"android:extractNativeLibs="true""#));
+        assert!(manifest.contains(r#"android:name="android.app.NativeActivity""#));
+        assert!(manifest.contains(
+            r#"<meta-data android:name="android.app.lib_name" android:value="dinghy_activity" />"#
+        ));
+        assert_eq!(Some("arm64-v8a"), abi("aarch64-linux-android"));
+        assert_eq!(Some("armeabi-v7a"), abi("armv7-linux-androideabi"));
+    }
+
+    #[test]
+    fn test_write_apk() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("base.apk");
+        let mut zip = ZipWriter::new(fs::File::create(&base).unwrap());
+        let stored = FileOptions::default().compression_method(CompressionMethod::Stored);
+        zip.start_file("AndroidManifest.xml", stored).unwrap();
+        zip.write_all(b"compiled manifest").unwrap();
+        zip.start_file("resources.arsc", stored).unwrap();
+        zip.write_all(b"resources").unwrap();
+        zip.finish().unwrap();
+        let exe = dir.path().join("_dinghy_my_crate-0123456789abcdef");
+        fs::write(&exe, b"runnable").unwrap();
+        let library = dir.path().join("libc++_shared.so");
+        fs::write(&library, b"library").unwrap();
+        let activity = dir.path().join(ACTIVITY_LIB_NAME);
+        fs::write(&activity, b"activity").unwrap();
+
+        let entries = native_entries("arm64-v8a", &activity, &exe, &[library]).unwrap();
+        let apk = dir.path().join("app.apk");
+        write_apk(&base, &entries, &apk).unwrap();
+
+        let mut apk = ZipArchive::new(fs::File::open(&apk).unwrap()).unwrap();
+        let names = (0..apk.len())
+            .map(|index| apk.by_index(index).unwrap().name().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "AndroidManifest.xml",
+                "resources.arsc",
+                "lib/arm64-v8a/libdinghy_activity.so",
+                "lib/arm64-v8a/libdinghy_runnable.so",
+                "lib/arm64-v8a/libc++_shared.so",
+            ],
+            names
+        );
+        assert_eq!(
+            CompressionMethod::Stored,
+            apk.by_name("resources.arsc").unwrap().compression()
+        );
+        let mut runnable = String::new();
+        apk.by_name("lib/arm64-v8a/libdinghy_runnable.so")
+            .unwrap()
+            .read_to_string(&mut runnable)
+            .unwrap();
+        assert_eq!("runnable", runnable);
+    }
+
+    #[test]
+    fn test_android_api() {
+        let mut elf = b"\x7fELF........".to_vec();
+        elf.extend_from_slice(&8u32.to_le_bytes());
+        elf.extend_from_slice(&84u32.to_le_bytes());
+        elf.extend_from_slice(b"\x01\x00\x00\x00Android\x00");
+        elf.extend_from_slice(&24u32.to_le_bytes());
+        elf.extend_from_slice(b"r25c");
+        assert_eq!(Some(24), android_api(&elf));
+        assert_eq!(None, android_api(b"\x7fELF no note"));
+        assert_eq!(
+            Some("armv7a-linux-androideabi24".to_string()),
+            clang_target("armeabi-v7a", 24)
+        );
+        assert_eq!(None, clang_target("mips", 24));
+    }
+
+    #[test]
+    fn test_asset_entries_and_run_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let bundle = dir.path().join("my_crate-0123456789abcdef");
+        fs::create_dir_all(bundle.join("data/nested")).unwrap();
+        let exe = bundle.join("_dinghy_my_crate-0123456789abcdef");
+        fs::write(&exe, b"runnable").unwrap();
+        fs::write(bundle.join("data/nested/input.txt"), b"input").unwrap();
+        let work_dir = dir.path().join("apk");
+
+        let entries = asset_entries(&bundle, &exe, &work_dir).unwrap();
+
+        let names = entries.iter().map(|it| it.0.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "assets/dinghy.files",
+                "assets/dinghy/my_crate-0123456789abcdef/data/nested/input.txt",
+            ],
+            names
+        );
+        assert_eq!(
+            "dinghy/my_crate-0123456789abcdef/data/nested/input.txt\n",
+            fs::read_to_string(&entries[0].1).unwrap()
+        );
+
+        let run = run_file(
+            "dinghy-1234",
+            "dinghy/my_crate-0123456789abcdef",
+            &["--nocapture".to_string(), "a b".to_string()],
+            &[("DINGHY".to_string(), "1".to_string())],
+        );
+        assert_eq!(
+            &b"Tdinghy-1234\0Cdinghy/my_crate-0123456789abcdef\0A--nocapture\0Aa b\0EDINGHY=1\0"[..],
+            &run[..]
+        );
+    }
+}
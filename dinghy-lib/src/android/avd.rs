@@ -0,0 +1,545 @@
+//! Android virtual devices, listed as devices and booted headless when used.
+
+use super::device::supports_platform;
+use super::device::target_of_abi;
+use super::AndroidDevice;
+use crate::config::AndroidConfiguration;
+use crate::env::EnvSpec;
+use crate::errors::*;
+use crate::platform::regular_platform::RegularPlatform;
+use crate::project::Project;
+use crate::Build;
+use crate::BuildBundle;
+use crate::Device;
+use crate::DeviceCapabilities;
+use crate::DeviceCompatibility;
+use crate::DeviceDescription;
+use crate::Runnable;
+use crate::RunnableOutcome;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{env, fmt, fs, thread};
+
+/// Default of `AndroidConfiguration::avd_boot_timeout`, cold boots are slow.
+const DEFAULT_BOOT_TIMEOUT: Duration = Duration::from_secs(300);
+const BOOT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// Console ports tried when the emulator exits before adb lists it, like when it loses the race
+/// to bind its ports.
+const BOOT_ATTEMPTS: usize = 3;
+
+/// The emulators dinghy started and shuts down, so that `shutdown_emulators` still can when
+/// dinghy is interrupted before the devices are dropped.
+static STARTED: Mutex<Vec<Arc<Emulator>>> = Mutex::new(Vec::new());
+
+pub struct AvdDevice {
+    adb: PathBuf,
+    emulator: PathBuf,
+    id: String,
+    name: String,
+    pub supported_targets: Vec<&'static str>,
+    /// The API level of the system image.
+    pub api_level: Option<u32>,
+    conf: AndroidConfiguration,
+    boot_timeout: Duration,
+    shutdown: bool,
+    booted: Mutex<Option<Booted>>,
+}
+
+struct Booted {
+    device: Arc<AndroidDevice>,
+    /// The emulator, when dinghy started it.
+    emulator: Option<Arc<Emulator>>,
+}
+
+/// An emulator process dinghy started.
+struct Emulator {
+    adb: PathBuf,
+    serial: String,
+    child: Mutex<Child>,
+}
+
+impl Emulator {
+    /// Asks the emulator to quit, kills it when its console does not answer, and waits for it.
+    fn shut_down(&self) {
+        info!("Shut down emulator {}", self.serial);
+        let killed = Command::new(&self.adb)
+            .args(["-s", &self.serial, "emu", "kill"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success());
+        if let Ok(mut child) = self.child.lock() {
+            if !killed {
+                let _ = child.kill();
+            }
+            let _ = child.wait();
+        }
+    }
+
+    fn unregister(self: &Arc<Self>) {
+        if let Ok(mut started) = STARTED.lock() {
+            started.retain(|emulator| !Arc::ptr_eq(emulator, self));
+        }
+    }
+}
+
+/// Shuts down the emulators dinghy started for AVDs configured with `shutdown_avds`, for when
+/// dinghy is interrupted.
+pub fn shutdown_emulators() {
+    let started = match STARTED.lock() {
+        Ok(mut started) => std::mem::take(&mut *started),
+        Err(_) => return,
+    };
+    for emulator in started {
+        emulator.shut_down();
+    }
+}
+
+impl AvdDevice {
+    /// The AVDs `emulator` knows about. `running` maps the names of the ones already running
+    /// to their adb serial.
+    ///
+    /// The AVDs that can not be listed, or whose running emulator does not answer, are left
+    /// out with a warning.
+    pub fn list(
+        adb: &Path,
+        emulator: &Path,
+        running: &[(String, String)],
+        conf: &AndroidConfiguration,
+    ) -> Vec<AvdDevice> {
+        let output = match Command::new(emulator).arg("-list-avds").output() {
+            Ok(output) => output,
+            Err(e) => {
+                warn!("Couldn't list the AVDs with {:?}: {:?}", emulator, e);
+                return vec![];
+            }
+        };
+        let mut avds = vec![];
+        for name in avd_names(&String::from_utf8_lossy(&output.stdout)) {
+            let (abi, api_level) = avd_abi_and_api_level(&name);
+            let supported_targets = abi.as_deref().and_then(target_of_abi).into_iter().collect();
+            let booted = match running.iter().find(|(running, _)| *running == name) {
+                Some((_, serial)) => {
+                    match AndroidDevice::from_id(adb.to_path_buf(), serial, conf) {
+                        Ok(device) => Some(Booted {
+                            device: Arc::new(device),
+                            emulator: None,
+                        }),
+                        Err(e) => {
+                            warn!("Skipping AVD {}, running as {}: {:?}", name, serial, e);
+                            continue;
+                        }
+                    }
+                }
+                None => None,
+            };
+            avds.push(AvdDevice {
+                adb: adb.to_path_buf(),
+                emulator: emulator.to_path_buf(),
+                id: format!("avd:{}", name),
+                name,
+                supported_targets,
+                api_level,
+                conf: conf.clone(),
+                boot_timeout: conf
+                    .avd_boot_timeout
+                    .map(Duration::from_secs)
+                    .unwrap_or(DEFAULT_BOOT_TIMEOUT),
+                shutdown: conf.shutdown_avds.unwrap_or(false),
+                booted: Mutex::new(booted),
+            });
+        }
+        avds
+    }
+
+    /// Runs `f` with the Android device of the emulator, booting it first if needed. Only the
+    /// boot is serialized, `f` runs concurrently.
+    fn with_device<T>(&self, f: impl FnOnce(&AndroidDevice) -> Result<T>) -> Result<T> {
+        let device = {
+            let mut booted = self
+                .booted
+                .lock()
+                .map_err(|_| anyhow!("Emulator {} poisoned", self.name))?;
+            if booted.is_none() {
+                *booted = Some(self.boot()?);
+            }
+            booted.as_ref().unwrap().device.clone()
+        };
+        f(&device)
+    }
+
+    fn boot(&self) -> Result<Booted> {
+        let mut tried = vec![];
+        loop {
+            let port = free_console_port(&self.adb, &tried)?;
+            tried.push(port);
+            match self.boot_on(port)? {
+                Some(booted) => return Ok(booted),
+                None if tried.len() < BOOT_ATTEMPTS => {
+                    warn!(
+                        "Emulator {} exited before adb listed it, retrying on another port",
+                        self.name
+                    )
+                }
+                None => bail!(
+                    "Emulator {} exited before adb listed it, on ports {:?}",
+                    self.name,
+                    tried
+                ),
+            }
+        }
+    }
+
+    /// Boots the emulator with `port` as console port, or `None` when it exits before adb lists
+    /// it.
+    fn boot_on(&self, port: u16) -> Result<Option<Booted>> {
+        let serial = format!("emulator-{}", port);
+        info!("Boot emulator {} as {}", self.name, serial);
+        let mut command = Command::new(&self.emulator);
+        command
+            .arg("-avd")
+            .arg(&self.name)
+            .arg("-port")
+            .arg(port.to_string())
+            .args([
+                "-no-window",
+                "-no-audio",
+                "-no-boot-anim",
+                "-no-snapshot-save",
+            ])
+            .stdin(Stdio::null());
+        if !log_enabled!(::log::Level::Debug) {
+            command.stdout(Stdio::null()).stderr(Stdio::null());
+        }
+        // Out of the process group of dinghy, so that Ctrl-C does not reach it and it keeps
+        // running unless `shutdown_avds` is set.
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+        debug!("Running {:?}", command);
+        let emulator = Arc::new(Emulator {
+            adb: self.adb.clone(),
+            serial: serial.clone(),
+            child: Mutex::new(
+                command
+                    .spawn()
+                    .with_context(|| format!("Couldn't run {:?}", command))?,
+            ),
+        });
+        if self.shutdown {
+            if let Ok(mut started) = STARTED.lock() {
+                started.push(emulator.clone());
+            }
+        }
+
+        let deadline = Instant::now() + self.boot_timeout;
+        let mut listed = false;
+        loop {
+            let exited = emulator
+                .child
+                .lock()
+                .map_err(|_| anyhow!("Emulator {} poisoned", self.name))?
+                .try_wait()?;
+            if let Some(status) = exited {
+                emulator.unregister();
+                if !listed {
+                    debug!("Emulator {} exited ({})", self.name, status);
+                    return Ok(None);
+                }
+                bail!("Emulator {} exited while booting ({})", self.name, status)
+            }
+            listed = listed || adb_devices(&self.adb)?.contains(&serial);
+            let boot_completed = Command::new(&self.adb)
+                .args(["-s", &serial, "shell", "getprop", "sys.boot_completed"])
+                .stderr(Stdio::null())
+                .output()?;
+            if String::from_utf8_lossy(&boot_completed.stdout).trim() == "1" {
+                break;
+            }
+            if Instant::now() > deadline {
+                emulator.unregister();
+                if let Ok(mut child) = emulator.child.lock() {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                }
+                return Err(anyhow!(
+                    "Emulator {} did not boot within {}s",
+                    self.name,
+                    self.boot_timeout.as_secs()
+                )
+                .context(DinghyError::DeviceUnreachable(self.id.clone())));
+            }
+            thread::sleep(BOOT_POLL_INTERVAL);
+        }
+        debug!("Emulator {} booted", self.name);
+        Ok(Some(Booted {
+            device: Arc::new(AndroidDevice::from_id(
+                self.adb.clone(),
+                &serial,
+                &self.conf,
+            )?),
+            emulator: Some(emulator),
+        }))
+    }
+}
+
+impl Drop for AvdDevice {
+    fn drop(&mut self) {
+        if !self.shutdown {
+            return;
+        }
+        let booted = match self.booted.get_mut() {
+            Ok(Some(booted)) => booted,
+            _ => return,
+        };
+        if let Some(emulator) = booted.emulator.take() {
+            emulator.unregister();
+            emulator.shut_down();
+        }
+    }
+}
+
+/// The names of the AVDs in the output of `emulator -list-avds`, which may also have log
+/// lines.
+fn avd_names(list: &str) -> Vec<String> {
+    list.lines()
+        .map(|line| line.trim())
+        .filter(|line| {
+            !line.is_empty()
+                && line
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "._-".contains(c))
+        })
+        .map(|line| line.to_string())
+        .collect()
+}
+
+/// The value of `key` in an AVD `.ini` file.
+fn ini_value(ini: &str, key: &str) -> Option<String> {
+    ini.lines()
+        .filter_map(|line| line.split_once('='))
+        .find(|(k, _)| k.trim() == key)
+        .map(|(_, value)| value.trim().to_string())
+}
+
+/// The directory of the AVDs, as the emulator looks for it.
+fn avd_home() -> Option<PathBuf> {
+    if let Some(home) = env::var_os("ANDROID_AVD_HOME") {
+        return Some(home.into());
+    }
+    for var in &["ANDROID_EMULATOR_HOME", "ANDROID_USER_HOME"] {
+        if let Some(home) = env::var_os(var) {
+            return Some(PathBuf::from(home).join("avd"));
+        }
+    }
+    dirs::home_dir().map(|home| home.join(".android").join("avd"))
+}
+
+/// The ABI of the system image of the AVD `name`, like `x86_64`, and its API level.
+fn avd_abi_and_api_level(name: &str) -> (Option<String>, Option<u32>) {
+    let home = match avd_home() {
+        Some(home) => home,
+        None => return (None, None),
+    };
+    let ini = fs::read_to_string(home.join(format!("{}.ini", name))).unwrap_or_default();
+    let avd_dir = ini_value(&ini, "path")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| home.join(format!("{}.avd", name)));
+    let config = fs::read_to_string(avd_dir.join("config.ini")).unwrap_or_default();
+    // `target=android-33`, in the `.ini` next to the AVD directory.
+    let api_level = ini_value(&ini, "target")
+        .or_else(|| ini_value(&config, "target"))
+        .and_then(|target| target.strip_prefix("android-")?.parse().ok());
+    (ini_value(&config, "abi.type"), api_level)
+}
+
+/// The output of `adb devices`.
+fn adb_devices(adb: &Path) -> Result<String> {
+    let devices = Command::new(adb)
+        .arg("devices")
+        .stderr(Stdio::null())
+        .output()?;
+    Ok(String::from_utf8_lossy(&devices.stdout).into_owned())
+}
+
+/// The first console port, among the ones the emulator accepts and not `tried`, no emulator of
+/// adb uses and that is free to bind, with the next one the emulator binds for adb.
+fn free_console_port(adb: &Path, tried: &[u16]) -> Result<u16> {
+    first_free_console_port(&adb_devices(adb)?, tried)
+        .ok_or_else(|| anyhow!("No console port left for another emulator"))
+}
+
+fn first_free_console_port(devices: &str, tried: &[u16]) -> Option<u16> {
+    (5554..=5682).step_by(2).find(|port| {
+        !tried.contains(port)
+            && !devices.contains(&format!("emulator-{}", port))
+            && TcpListener::bind(("127.0.0.1", *port)).is_ok()
+            && TcpListener::bind(("127.0.0.1", port + 1)).is_ok()
+    })
+}
+
+/// The name of the AVD running as `serial`, as answered by `adb emu avd name`.
+pub fn running_avd_name(adb: &Path, serial: &str) -> Option<String> {
+    let output = Command::new(adb)
+        .args(["-s", serial, "emu", "avd", "name"])
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .next()
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+}
+
+impl DeviceCompatibility for AvdDevice {
+    fn is_compatible_with_regular_platform(&self, platform: &RegularPlatform) -> bool {
+        supports_platform(&self.supported_targets, self.api_level, platform)
+    }
+}
+
+impl Device for AvdDevice {
+    fn capabilities(&self) -> DeviceCapabilities {
+        // Those of the Android device it boots.
+        DeviceCapabilities {
+            debug: true,
+            lldb_proxy: false,
+            clean: true,
+            shell: true,
+            streaming_output: true,
+        }
+    }
+
+    fn clean_app(&self, build_bundle: &BuildBundle) -> Result<()> {
+        self.with_device(|device| device.clean_app(build_bundle))
+    }
+
+    fn debug_app(
+        &self,
+        project: &Project,
+        build: &Build,
+        args: &[&str],
+        envs: &[EnvSpec],
+    ) -> Result<BuildBundle> {
+        self.with_device(|device| device.debug_app(project, build, args, envs))
+    }
+
+    fn description(&self) -> DeviceDescription {
+        DeviceDescription {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            kind: "android".to_string(),
+            supported_targets: self
+                .supported_targets
+                .iter()
+                .map(|it| it.to_string())
+                .collect(),
+            properties: self
+                .api_level
+                .map(|api_level| ("api_level".to_string(), api_level.to_string()))
+                .into_iter()
+                .collect(),
+            ..DeviceDescription::default()
+        }
+    }
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn starts_on_demand(&self) -> bool {
+        self.booted.lock().is_ok_and(|booted| booted.is_none())
+    }
+
+    fn run_app(
+        &self,
+        project: &Project,
+        build: &Build,
+        args: &[&str],
+        envs: &[EnvSpec],
+    ) -> Result<Vec<RunnableOutcome>> {
+        self.with_device(|device| device.run_app(project, build, args, envs))
+    }
+
+    fn shell(
+        &self,
+        project: &Project,
+        build: &Build,
+        runnable: &Runnable,
+        envs: &[EnvSpec],
+    ) -> Result<i32> {
+        self.with_device(|device| device.shell(project, build, runnable, envs))
+    }
+}
+
+impl fmt::Display for AvdDevice {
+    // With the device of the emulator when it runs, so that the hints matching it still do.
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self.booted.try_lock().as_deref() {
+            Ok(Some(booted)) => write!(fmt, "{} ({})", self.id, booted.device),
+            _ => write!(fmt, "{}", self.id),
+        }
+    }
+}
+
+impl fmt::Debug for AvdDevice {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self.booted.try_lock().as_deref() {
+            Ok(Some(booted)) => write!(
+                fmt,
+                "Avd {{ \"id\": \"{}\", \"device\": {:?} }}",
+                self.id, booted.device
+            ),
+            _ => write!(fmt, "Avd {{ \"id\": \"{}\" }}", self.id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_avd_names_and_ini() {
+        let list = "INFO    | Storing crashdata in: /tmp/android/emu-crash.db\nPixel_6_API_33\nci-x86_64\n\n";
+        assert_eq!(vec!["Pixel_6_API_33", "ci-x86_64"], avd_names(list));
+        let config = "AvdId=ci-x86_64\nabi.type = x86_64\nhw.cpu.arch=x86_64\n";
+        assert_eq!(Some("x86_64".to_string()), ini_value(config, "abi.type"));
+        assert_eq!(None, ini_value(config, "path"));
+    }
+
+    #[test]
+    fn test_first_free_console_port() {
+        let devices = "List of devices attached\nemulator-5554\tdevice\n\n";
+        let port = first_free_console_port(devices, &[]).unwrap();
+        assert!(port > 5554 && port.is_multiple_of(2));
+        assert!(first_free_console_port(devices, &[port]).unwrap() > port);
+        // Taken by another process than an emulator.
+        let _taken = TcpListener::bind(("127.0.0.1", port + 1));
+        assert_ne!(Some(port), first_free_console_port(devices, &[]));
+    }
+
+    #[test]
+    fn test_starts_on_demand_until_booted() {
+        let avd = AvdDevice {
+            adb: "adb".into(),
+            emulator: "emulator".into(),
+            id: "avd:ci".to_string(),
+            name: "ci".to_string(),
+            supported_targets: vec!["x86_64-linux-android"],
+            api_level: Some(33),
+            conf: AndroidConfiguration::default(),
+            boot_timeout: DEFAULT_BOOT_TIMEOUT,
+            shutdown: true,
+            booted: Mutex::new(None),
+        };
+        assert!(avd.starts_on_demand());
+        assert_eq!("avd:ci", avd.to_string());
+    }
+}
@@ -1,73 +1,213 @@
+use crate::config::AndroidConfiguration;
+use crate::debugger::attach;
+use crate::debugger::runnable_to_debug;
+use crate::debugger::server_command;
+use crate::debugger::start_server;
+use crate::debugger::Debugger;
 use crate::device::make_remote_app;
+use crate::device::run_and_capture;
+use crate::device::Installed;
+use crate::device::Timeout;
+use crate::doctor::SystemLookup;
+use crate::env::to_shell_assignments;
+use crate::env::EnvSpec;
 use crate::errors::*;
 use crate::platform::regular_platform::RegularPlatform;
 use crate::project::Project;
+use crate::report::RunnableReport;
+use crate::utils::file_name_as_str;
 use crate::utils::path_to_str;
 use crate::Build;
 use crate::BuildBundle;
 use crate::Device;
+use crate::DeviceCapabilities;
 use crate::DeviceCompatibility;
+use crate::DeviceDescription;
 use crate::Runnable;
-use std::io::Write;
-use std::{fmt, io, path, process};
+use crate::RunnableOutcome;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use std::{fmt, fs, path, process};
+
+use super::apk;
+use super::apk::ApkTools;
 
 static ANDROID_WORK_DIR: &str = "/data/local/tmp/dinghy";
+/// Printed after the runnable by devices without shell v2, whose `adb shell` exits with 0.
+static EXIT_CODE_SENTINEL: &str = "FORWARD_RESULT_TO_DINGHY_BECAUSE_ADB_DOES_NOT=";
+/// Where the runnable writes its pid, in its bundle directory, when logcat is captured.
+static PID_FILE: &str = "dinghy.pid";
+/// Tags of what crashes and aborts log, from other processes than the crashing one.
+static CRASH_TAGS: &[&str] = &[
+    "DEBUG",
+    "crash_dump32",
+    "crash_dump64",
+    "libc",
+    "tombstoned",
+];
 
 pub struct AndroidDevice {
     pub adb: path::PathBuf,
     pub id: String,
     pub supported_targets: Vec<&'static str>,
+    /// With the shell v2 protocol, `adb shell` exits with the status of the remote command,
+    /// and keeps its stderr apart from its stdout.
+    pub shell_v2: bool,
+    /// The `[android]` configuration, for `run_as_app` and the NDK.
+    pub conf: AndroidConfiguration,
+    /// `ro.build.version.sdk`, platforms of higher API levels are not compatible.
+    pub api_level: Option<u32>,
+    pub manufacturer: String,
+    pub model: String,
+    /// The bundles installed by `install_app`, as `run_app` runs them.
+    installed: Installed<(BuildBundle, BuildBundle)>,
+    /// The apps installed by `install_as_app`, as `run_app` runs them.
+    installed_apps: Installed<(BuildBundle, String)>,
 }
 
 impl AndroidDevice {
-    pub fn from_id(adb: path::PathBuf, id: &str) -> Result<AndroidDevice> {
+    pub fn from_id(
+        adb: path::PathBuf,
+        id: &str,
+        conf: &AndroidConfiguration,
+    ) -> Result<AndroidDevice> {
         for prop in &[
             "ro.product.cpu.abilist",
             "ro.product.cpu.abi",
             "ro.product.cpu.abi2",
         ] {
-            let getprop_output = process::Command::new(&adb)
-                .args(&["-s", id, "shell", "getprop", prop])
-                .output()?;
-            let abilist = String::from_utf8(getprop_output.stdout)?;
-            debug!(
-                "Android device {}, getprop {} returned {}",
-                id,
-                prop,
-                abilist.trim()
-            );
-            if abilist.trim().len() > 0 {
+            let abilist = getprop(&adb, id, prop)?;
+            if !abilist.is_empty() {
                 let supported_targets = abilist
-                    .trim()
                     .split(",")
-                    .filter_map(|abi| {
-                        Some(match abi {
-                            "arm64-v8a" => "aarch64-linux-android",
-                            "armeabi-v7a" => "armv7-linux-androideabi",
-                            "armeabi" => "arm-linux-androideabi",
-                            "x86" => "i686-linux-android",
-                            "x86_64" => "x86_64-linux-android",
-                            _ => return None,
-                        })
-                    })
+                    .filter_map(target_of_abi)
                     .collect::<Vec<_>>();
 
+                let features = process::Command::new(&adb)
+                    .args(["-s", id, "features"])
+                    .output()?;
+                let shell_v2 = String::from_utf8_lossy(&features.stdout)
+                    .lines()
+                    .any(|feature| feature.trim() == "shell_v2");
+                debug!("Android device {}, shell v2: {}", id, shell_v2);
+                let api_level = getprop(&adb, id, "ro.build.version.sdk")?.parse().ok();
+                let manufacturer = getprop(&adb, id, "ro.product.manufacturer")?;
+                let model = getprop(&adb, id, "ro.product.model")?;
+
                 return Ok(AndroidDevice {
                     adb,
                     id: id.into(),
                     supported_targets: supported_targets,
+                    shell_v2,
+                    conf: conf.clone(),
+                    api_level,
+                    manufacturer,
+                    model,
+                    installed: Installed::default(),
+                    installed_apps: Installed::default(),
                 });
             }
         }
         bail!("Could not match a platform to the device")
     }
 
+    fn run_as_app(&self) -> bool {
+        self.conf.run_as_app.unwrap_or(false)
+    }
+
+    fn capture_logcat(&self) -> bool {
+        self.conf.capture_logcat.unwrap_or(false)
+    }
+
+    /// `exe` as the shell runs it, writing its pid to `PID_FILE` in the current directory
+    /// first when logcat is captured.
+    fn invocation(&self, exe: &str) -> String {
+        if self.capture_logcat() {
+            format!("sh -c 'echo $$ > {}; exec \"$0\" \"$@\"' {}", PID_FILE, exe)
+        } else {
+            exe.to_string()
+        }
+    }
+
+    /// Where the logs of a runnable start, taken before running it.
+    fn logcat_mark(&self) -> Result<LogcatMark> {
+        let since = self
+            .adb()?
+            .arg("shell")
+            .arg("date +'%m-%d %H:%M:%S.000'")
+            .output()?;
+        let since = String::from_utf8(since.stdout)?.trim().to_string();
+        if since.is_empty() {
+            bail!("Couldn't read the date of {}", self.id)
+        }
+        Ok(LogcatMark {
+            since,
+            tombstones: self.tombstones()?,
+        })
+    }
+
+    /// The `ls -l` lines of the tombstones, empty if the device does not let us see them.
+    fn tombstones(&self) -> Result<Vec<String>> {
+        let output = self
+            .adb()?
+            .arg("shell")
+            .arg("ls -l /data/tombstones")
+            .stderr(process::Stdio::null())
+            .output()?;
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|line| line.trim().to_string())
+            .collect())
+    }
+
+    /// Saves in `dir` the logcat since `mark` of the process whose pid `read_pid` prints, and
+    /// of crashes, then the new tombstones.
+    fn save_logs(&self, mark: &LogcatMark, read_pid: &str, dir: &path::Path) -> Result<()> {
+        fs::create_dir_all(dir)?;
+        let pid = self.adb()?.arg("shell").arg(read_pid).output()?;
+        let pid = String::from_utf8_lossy(&pid.stdout).trim().to_string();
+        let logcat = self
+            .adb()?
+            .arg("shell")
+            .arg(format!("logcat -d -v threadtime -T '{}'", mark.since))
+            .output()?;
+        let path = dir.join("logcat.txt");
+        fs::write(
+            &path,
+            filter_logcat(
+                &String::from_utf8_lossy(&logcat.stdout),
+                Some(pid.as_str()).filter(|pid| !pid.is_empty()),
+            ),
+        )?;
+        info!("Saved the logcat in {}", path.display());
+        for tombstone in new_tombstones(&mark.tombstones, &self.tombstones()?) {
+            let content = self
+                .adb()?
+                .arg("shell")
+                .arg(format!("cat /data/tombstones/{}", tombstone))
+                .output()?;
+            if content.status.success() {
+                let path = dir.join(&tombstone);
+                fs::write(&path, content.stdout)?;
+                info!("Saved the tombstone in {}", path.display());
+            }
+        }
+        Ok(())
+    }
+
     fn adb(&self) -> Result<process::Command> {
         let mut command = process::Command::new(&self.adb);
         command.arg("-s").arg(&self.id);
         Ok(command)
     }
 
+    /// Whether adb still sees the device, as `adb get-state` tells.
+    fn is_reachable(&self) -> bool {
+        self.adb()
+            .and_then(|mut adb| Ok(adb.arg("get-state").output()?))
+            .is_ok_and(|output| String::from_utf8_lossy(&output.stdout).trim() == "device")
+    }
+
     fn install_app(
         &self,
         project: &Project,
@@ -84,10 +224,12 @@ impl AndroidDevice {
             .status()?
             .success()
         {
-            bail!(
+            // Mostly because adb lost the device.
+            return Err(anyhow!(
                 "Failure to create dinghy work dir '{:?}' on target android device",
                 ANDROID_WORK_DIR
             )
+            .context(DinghyError::DeviceUnreachable(self.id.clone())));
         }
 
         let build_bundle = make_remote_app(project, build, runnable)?;
@@ -150,27 +292,335 @@ impl AndroidDevice {
         }
     }
 
+    /// Shell assignments of the whole environment of a runnable whose libraries are in
+    /// `lib_dir`.
+    fn remote_env(lib_dir: &path::Path, env: &[EnvSpec]) -> Result<String> {
+        Ok(format!(
+            "DINGHY=1 RUST_BACKTRACE=1 LD_LIBRARY_PATH=\"{}:$LD_LIBRARY_PATH\" {}",
+            path_to_str(lib_dir)?,
+            to_shell_assignments(env)
+        ))
+    }
+
+    /// Wraps `runnable`, its dynamic libraries and its test data in an app, and installs it.
+    ///
+    /// Returns the bundle of the runnable, and the package of the app.
+    fn install_as_app(
+        &self,
+        project: &Project,
+        build: &Build,
+        runnable: &Runnable,
+    ) -> Result<(BuildBundle, String)> {
+        let build_bundle = make_remote_app(project, build, runnable)?;
+        let abi = apk::abi(&build.rustc_triple)
+            .ok_or_else(|| anyhow!("No Android ABI known for {}", build.rustc_triple))?;
+        // The API level of the platform the runnable was built for.
+        let min_api = apk::android_api(&fs::read(&build_bundle.bundle_exe)?)
+            .or(self.api_level)
+            .ok_or_else(|| anyhow!("Couldn't tell the API level {} is built for", runnable.id))?;
+        let clang_target = apk::clang_target(abi, min_api)
+            .ok_or_else(|| anyhow!("No clang target known for {}", abi))?;
+        let ndk = super::ndk(&self.conf, &SystemLookup)?
+            .ok_or_else(|| anyhow!("Android NDK not found"))?;
+        let package = apk::package_name(&build_bundle.id);
+        let work_dir = build_bundle.root_dir.join("apk").join(&build_bundle.id);
+        let activity = apk::build_activity(&find_clang(&ndk)?, &clang_target, &work_dir)?;
+        let mut entries = apk::native_entries(
+            abi,
+            &activity,
+            &build_bundle.bundle_exe,
+            &build.dynamic_libraries,
+        )?;
+        entries.extend(apk::asset_entries(
+            &build_bundle.bundle_dir,
+            &build_bundle.bundle_exe,
+            &work_dir,
+        )?);
+        let apk_path = apk::build_apk(&ApkTools::find()?, &work_dir, &package, min_api, &entries)?;
+
+        info!(
+            "Install {} as app {} on {}",
+            build_bundle.id, package, self.id
+        );
+        let output = self
+            .adb()?
+            .arg("install")
+            .arg("-r")
+            .arg("-t")
+            .arg(&apk_path)
+            .output()?;
+        if !output.status.success() {
+            bail!(
+                "Failure to install {}: {}",
+                apk_path.display(),
+                String::from_utf8_lossy(&output.stdout).trim()
+            )
+        }
+        Ok((build_bundle, package))
+    }
+
+    /// Writes to the files directory of `package` how its activity runs the runnable of
+    /// `build_bundle`: logging under `tag`, with `args` and `env`.
+    fn write_run_file(
+        &self,
+        package: &str,
+        build_bundle: &BuildBundle,
+        tag: &str,
+        args: &[String],
+        env: &[(String, String)],
+    ) -> Result<()> {
+        let run_file = build_bundle
+            .root_dir
+            .join("apk")
+            .join(&build_bundle.id)
+            .join(apk::RUN_FILE);
+        fs::write(
+            &run_file,
+            apk::run_file(tag, &format!("dinghy/{}", build_bundle.id), args, env),
+        )?;
+        let pushed = format!("{}/{}.run", ANDROID_WORK_DIR, package);
+        let copy = format!("mkdir -p files && cp {} files/{}", pushed, apk::RUN_FILE);
+        let mut push = self.adb()?;
+        push.arg("push").arg(&run_file).arg(&pushed);
+        if !log_enabled!(::log::Level::Debug) {
+            push.stdout(process::Stdio::null());
+        }
+        if !push.status()?.success()
+            || !self
+                .adb()?
+                .arg("shell")
+                .arg(format!(
+                    "run-as {} sh -c {}",
+                    package,
+                    ::shell_escape::escape(copy.into())
+                ))
+                .status()?
+                .success()
+        {
+            bail!("Failure to copy {} to {}", run_file.display(), package)
+        }
+        Ok(())
+    }
+
+    /// Pushes the gdbserver, or lldb-server, of the NDK for `rustc_triple` to the device, and
+    /// returns its path there.
+    fn install_debug_server(&self, rustc_triple: &str) -> Result<String> {
+        let ndk = super::ndk(&self.conf, &SystemLookup)?
+            .ok_or_else(|| anyhow!("Android NDK not found"))?;
+        let server = find_debug_server(&ndk, rustc_triple)?;
+        let remote_server = format!("{}/{}", ANDROID_WORK_DIR, file_name_as_str(&server)?);
+        debug!("Push {} to {}", server.display(), remote_server);
+        self.sync(&server, &remote_server)?;
+        if !self
+            .adb()?
+            .arg("shell")
+            .arg("chmod")
+            .arg("755")
+            .arg(&remote_server)
+            .status()?
+            .success()
+        {
+            bail!("Failure to install {} on {}", remote_server, self.id)
+        }
+        Ok(remote_server)
+    }
+
     fn to_remote_bundle(build_bundle: &BuildBundle) -> Result<BuildBundle> {
         build_bundle.replace_prefix_with(ANDROID_WORK_DIR)
     }
 }
 
-impl DeviceCompatibility for AndroidDevice {
-    fn is_compatible_with_regular_platform(&self, platform: &RegularPlatform) -> bool {
-        if platform.id.starts_with("auto-android") {
-            let cpu = platform.id.split("-").nth(2).unwrap();
-            self.supported_targets
+/// Where logcat and the tombstones stood before a run, to save only what the run added.
+struct LogcatMark {
+    /// Device time, in the format of `logcat -T`.
+    since: String,
+    tombstones: Vec<String>,
+}
+
+/// The lines of `logcat`, in the threadtime format, logged by `pid` or about crashes.
+fn filter_logcat(logcat: &str, pid: Option<&str>) -> String {
+    logcat
+        .lines()
+        .filter(|line| {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            fields.len() > 5
+                && (pid == Some(fields[2]) || CRASH_TAGS.contains(&fields[5].trim_end_matches(':')))
+        })
+        .map(|line| format!("{}\n", line.trim_end()))
+        .collect()
+}
+
+/// Where the logs of `runnable` on the device `id` are saved, in the `root_dir` of its bundle:
+/// `target/<triple>/dinghy/logs/<device id>/<runnable id>/`, so that devices running it at once
+/// do not overwrite each other's logs. Device ids like `192.168.1.5:5555` are made path safe.
+fn logs_dir(root_dir: &path::Path, id: &str, runnable: &str) -> path::PathBuf {
+    let id = id
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "._-".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    root_dir.join("logs").join(id).join(runnable)
+}
+
+/// The names of the tombstones of `after` that `before` doesn't have, as listed by `ls -l`:
+/// their names get reused.
+fn new_tombstones(before: &[String], after: &[String]) -> Vec<String> {
+    after
+        .iter()
+        .filter(|line| !before.contains(line))
+        .filter_map(|line| line.split_whitespace().last())
+        .filter(|name| name.starts_with("tombstone_"))
+        .map(|name| name.to_string())
+        .collect()
+}
+
+fn getprop(adb: &path::Path, id: &str, prop: &str) -> Result<String> {
+    let output = process::Command::new(adb)
+        .args(["-s", id, "shell", "getprop", prop])
+        .output()?;
+    let value = String::from_utf8(output.stdout)?.trim().to_string();
+    debug!("Android device {}, getprop {} returned {}", id, prop, value);
+    Ok(value)
+}
+
+/// The rustc target of the Android `abi`, like `arm64-v8a`.
+pub fn target_of_abi(abi: &str) -> Option<&'static str> {
+    Some(match abi {
+        "arm64-v8a" => "aarch64-linux-android",
+        "armeabi-v7a" => "armv7-linux-androideabi",
+        "armeabi" => "arm-linux-androideabi",
+        "x86" => "i686-linux-android",
+        "x86_64" => "x86_64-linux-android",
+        _ => return None,
+    })
+}
+
+/// The API level an auto-android platform builds for, like 21 for `aarch64-linux-android21`.
+pub fn platform_api_level(platform: &RegularPlatform) -> Option<u32> {
+    if !platform.id.starts_with("auto-android") {
+        return None;
+    }
+    let cc_prefix = &platform.toolchain.cc_prefix;
+    let digits = cc_prefix.trim_end_matches(|c: char| c.is_ascii_digit());
+    cc_prefix[digits.len()..].parse().ok()
+}
+
+/// Whether a device supporting `supported_targets`, at `api_level`, can run the runnables of
+/// `platform`.
+pub fn supports_platform(
+    supported_targets: &[&str],
+    api_level: Option<u32>,
+    platform: &RegularPlatform,
+) -> bool {
+    if platform.id.starts_with("auto-android") {
+        let cpu = platform.id.split("-").nth(2).unwrap();
+        let api_supported = match (api_level, platform_api_level(platform)) {
+            (Some(device), Some(platform)) => platform <= device,
+            _ => true,
+        };
+        api_supported
+            && supported_targets
                 .iter()
                 .any(|target| target.starts_with(cpu))
-        } else {
-            self.supported_targets
-                .contains(&&*platform.toolchain.binutils_prefix)
+    } else {
+        supported_targets.contains(&&*platform.toolchain.binutils_prefix)
+    }
+}
+
+/// The exit code the sentinel echoed after the runnable on devices without shell v2.
+fn sentinel_exit_code(output: &str) -> Option<i32> {
+    output
+        .lines()
+        .last()
+        .and_then(|last_line| last_line.trim().strip_prefix(EXIT_CODE_SENTINEL))
+        .and_then(|code| code.parse::<i32>().ok())
+}
+
+/// A logcat tag unique to a run of a runnable in an app.
+fn run_tag() -> String {
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |it| it.subsec_nanos());
+    format!(
+        "dinghy-{:x}-{}",
+        process::id() ^ nanos,
+        RUNS.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// The clang of the NDK.
+fn find_clang(ndk: &path::Path) -> Result<path::PathBuf> {
+    ndk.join("toolchains/llvm/prebuilt")
+        .read_dir()?
+        .flatten()
+        .map(|host| host.path().join("bin/clang"))
+        .find(|clang| clang.is_file())
+        .ok_or_else(|| anyhow!("No clang in the Android NDK at {}", ndk.display()))
+}
+
+/// The gdbserver of the NDK for `rustc_triple`, or its lldb-server for NDK 21 and later,
+/// which do not ship gdbserver.
+fn find_debug_server(ndk: &path::Path, rustc_triple: &str) -> Result<path::PathBuf> {
+    let (gdbserver_arch, lldb_server_arch) = match rustc_triple.split('-').next() {
+        Some("aarch64") => ("arm64", "aarch64"),
+        Some("armv7") | Some("thumbv7neon") | Some("arm") => ("arm", "arm"),
+        Some("i686") => ("x86", "i386"),
+        Some("x86_64") => ("x86_64", "x86_64"),
+        _ => bail!("No debug server known for {}", rustc_triple),
+    };
+    let gdbserver = ndk
+        .join("prebuilt")
+        .join(format!("android-{}", gdbserver_arch))
+        .join("gdbserver/gdbserver");
+    if gdbserver.is_file() {
+        return Ok(gdbserver);
+    }
+    let prebuilt = ndk.join("toolchains/llvm/prebuilt");
+    for host in prebuilt.read_dir().into_iter().flatten().flatten() {
+        let clang = host.path().join("lib64/clang");
+        for version in clang.read_dir().into_iter().flatten().flatten() {
+            let lldb_server = version
+                .path()
+                .join("lib/linux")
+                .join(lldb_server_arch)
+                .join("lldb-server");
+            if lldb_server.is_file() {
+                return Ok(lldb_server);
+            }
         }
     }
+    bail!(
+        "Neither gdbserver nor lldb-server found in the Android NDK at {}",
+        ndk.display()
+    )
+}
+
+impl DeviceCompatibility for AndroidDevice {
+    fn is_compatible_with_regular_platform(&self, platform: &RegularPlatform) -> bool {
+        supports_platform(&self.supported_targets, self.api_level, platform)
+    }
 }
 
 impl Device for AndroidDevice {
+    fn capabilities(&self) -> DeviceCapabilities {
+        DeviceCapabilities {
+            debug: true,
+            lldb_proxy: false,
+            clean: true,
+            shell: true,
+            streaming_output: true,
+        }
+    }
+
     fn clean_app(&self, build_bundle: &BuildBundle) -> Result<()> {
+        self.installed.remove(&build_bundle.id);
+        self.installed_apps.remove(&build_bundle.id);
         let remote_bundle = AndroidDevice::to_remote_bundle(build_bundle)?;
         debug!("Cleaup device");
         if !self
@@ -195,17 +645,114 @@ impl Device for AndroidDevice {
         {
             bail!("Failure in android clean")
         }
+        if self.run_as_app() {
+            let package = apk::package_name(&build_bundle.id);
+            if !self
+                .adb()?
+                .arg("uninstall")
+                .arg(&package)
+                .status()?
+                .success()
+            {
+                bail!("Failure to uninstall {}", package)
+            }
+        }
         Ok(())
     }
 
     fn debug_app(
         &self,
-        _project: &Project,
-        _build: &Build,
-        _args: &[&str],
-        _envs: &[&str],
+        project: &Project,
+        build: &Build,
+        args: &[&str],
+        envs: &[EnvSpec],
     ) -> Result<BuildBundle> {
-        unimplemented!()
+        let runnable = runnable_to_debug(build)?;
+        let debugger = Debugger::for_remote()?;
+        let server = self.install_debug_server(&build.rustc_triple)?;
+        let env = build
+            .runtime_env
+            .iter()
+            .chain(envs)
+            .cloned()
+            .collect::<Vec<_>>();
+        let (build_bundle, remote_bundle) = self.install_app(project, build, runnable)?;
+        let args: Vec<String> = args
+            .iter()
+            .map(|&a| ::shell_escape::escape(a.into()).to_string())
+            .collect();
+        let command = format!(
+            "cd '{}'; export {}; exec {}",
+            path_to_str(&remote_bundle.bundle_dir)?,
+            AndroidDevice::remote_env(&remote_bundle.lib_dir, &env)?,
+            server_command(&server, path_to_str(&remote_bundle.bundle_exe)?, &args)
+        );
+        info!("Debug {} on {}", runnable.id, self.id);
+        let mut server = start_server(self.adb()?.arg("shell").arg(&command))?;
+        // `tcp:0` lets adb pick the port of the host, which it prints.
+        let forwarded = self
+            .adb()?
+            .arg("forward")
+            .arg("tcp:0")
+            .arg(format!("tcp:{}", server.port))
+            .output()?;
+        let port = match String::from_utf8_lossy(&forwarded.stdout)
+            .trim()
+            .parse::<u16>()
+        {
+            Ok(port) if forwarded.status.success() => port,
+            _ => {
+                let _ = server.child.kill();
+                let _ = server.child.wait();
+                bail!(
+                    "Couldn't forward port {} from {}: {}",
+                    server.port,
+                    self.id,
+                    String::from_utf8_lossy(&forwarded.stderr).trim()
+                )
+            }
+        };
+        let debugged = attach(
+            debugger.attach_command(
+                &runnable.exe,
+                build.sysroot.as_deref(),
+                &build_bundle.lib_dir,
+                port,
+                "remote-android",
+            ),
+            vec![server],
+        );
+        let _ = self
+            .adb()?
+            .arg("forward")
+            .arg("--remove")
+            .arg(format!("tcp:{}", port))
+            .status();
+        debugged?;
+        Ok(build_bundle)
+    }
+
+    fn description(&self) -> DeviceDescription {
+        DeviceDescription {
+            id: self.id.clone(),
+            name: self.name().to_string(),
+            kind: "android".to_string(),
+            supported_targets: self
+                .supported_targets
+                .iter()
+                .map(|it| it.to_string())
+                .collect(),
+            properties: [
+                ("manufacturer", Some(self.manufacturer.clone())),
+                ("model", Some(self.model.clone())),
+                ("api_level", self.api_level.map(|it| it.to_string())),
+            ]
+            .iter()
+            .filter_map(|(key, value)| Some((key.to_string(), value.clone()?)))
+            .filter(|(_, value)| !value.is_empty())
+            .collect(),
+            ..DeviceDescription::default()
+        }
     }
 
     fn id(&self) -> &str {
@@ -221,66 +768,216 @@ impl Device for AndroidDevice {
         project: &Project,
         build: &Build,
         args: &[&str],
-        envs: &[&str],
-    ) -> Result<Vec<BuildBundle>> {
-        let mut build_bundles = vec![];
+        envs: &[EnvSpec],
+    ) -> Result<Vec<RunnableOutcome>> {
+        let mut outcomes = vec![];
+        let raw_args = args;
         let args: Vec<String> = args
             .iter()
             .map(|&a| ::shell_escape::escape(a.into()).to_string())
             .collect();
+        let env = build
+            .runtime_env
+            .iter()
+            .chain(envs)
+            .cloned()
+            .collect::<Vec<_>>();
+        let bench = if build.build_args.compile_mode == ::cargo::core::compiler::CompileMode::Bench
+        {
+            "--bench"
+        } else {
+            ""
+        };
         for runnable in &build.runnables {
-            let (build_bundle, remote_bundle) = self.install_app(&project, &build, &runnable)?;
-            let command = format!(
-                "cd '{}'; {} DINGHY=1 RUST_BACKTRACE=1 LD_LIBRARY_PATH=\"{}:$LD_LIBRARY_PATH\" {} {} {} ; echo FORWARD_RESULT_TO_DINGHY_BECAUSE_ADB_DOES_NOT=$?",
-                path_to_str(&remote_bundle.bundle_dir)?,
-                envs.join(" "),
-                path_to_str(&remote_bundle.lib_dir)?,
-                path_to_str(&remote_bundle.bundle_exe)?,
-                if build.build_args.compile_mode == ::cargo::core::compiler::CompileMode::Bench { "--bench" } else { "" },
-                args.join(" "));
+            let (build_bundle, command, kill_command, read_pid) = if self.run_as_app() {
+                let (build_bundle, package) =
+                    self.installed_apps.get_or_install(&runnable.id, || {
+                        self.install_as_app(project, build, runnable)
+                    })?;
+                let tag = run_tag();
+                let env = [("DINGHY", "1"), ("RUST_BACKTRACE", "1")]
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .chain(env.iter().map(|env| (env.key.clone(), env.value.clone())))
+                    .collect::<Vec<_>>();
+                let app_args = raw_args
+                    .iter()
+                    .map(|arg| arg.to_string())
+                    .chain(Some(bench.to_string()).filter(|it| !it.is_empty()))
+                    .collect::<Vec<_>>();
+                self.write_run_file(&package, &build_bundle, &tag, &app_args, &env)?;
+                // The activity logs the output of the runnable, then its exit code, under
+                // `tag`: logcat is followed until then.
+                let logcat_pid = format!("{}/{}.logcat.pid", ANDROID_WORK_DIR, tag);
+                let command = format!(
+                    "am start -S -W -n {}/android.app.NativeActivity > /dev/null && \
+                     (logcat -v raw -s {}:V & echo $! > {}; wait) | \
+                     while IFS= read -r line; do case \"$line\" in \
+                     \"--------- \"*) ;; \
+                     {}*) printf '%s\\n' \"$line\"; kill $(cat {}); break;; \
+                     *) printf '%s\\n' \"$line\";; \
+                     esac; done",
+                    package, tag, logcat_pid, EXIT_CODE_SENTINEL, logcat_pid
+                );
+                let kill = format!("am force-stop {}; kill $(cat {})", package, logcat_pid);
+                let read_pid = format!(
+                    "run-as {} cat files/dinghy/{}/{}",
+                    package, build_bundle.id, PID_FILE
+                );
+                (build_bundle, command, kill, read_pid)
+            } else {
+                let (build_bundle, remote_bundle) = self
+                    .installed
+                    .get_or_install(&runnable.id, || self.install_app(project, build, runnable))?;
+                let command = format!(
+                    "cd '{}'; {} {} {} {}",
+                    path_to_str(&remote_bundle.bundle_dir)?,
+                    AndroidDevice::remote_env(&remote_bundle.lib_dir, &env)?,
+                    self.invocation(path_to_str(&remote_bundle.bundle_exe)?),
+                    bench,
+                    args.join(" ")
+                );
+                let kill = format!(
+                    "kill -9 $(pidof {})",
+                    file_name_as_str(&remote_bundle.bundle_exe)?
+                );
+                let read_pid = format!(
+                    "cat {}/{}",
+                    path_to_str(&remote_bundle.bundle_dir)?,
+                    PID_FILE
+                );
+                (build_bundle, command, kill, read_pid)
+            };
+            // The app prints the exit code itself.
+            let command = if self.shell_v2 || self.run_as_app() {
+                command
+            } else {
+                format!("{} ; echo {}$?", command, EXIT_CODE_SENTINEL)
+            };
             info!(
                 "Run {} on {} ({:?})",
                 runnable.id, self.id, build.build_args.compile_mode
             );
 
-            if !self
-                .adb()?
-                .arg("shell")
-                .arg(&command)
-                .output()
-                .with_context(|| format!("Couldn't run {} using adb.", runnable.exe.display()))
-                .and_then(|output| {
-                    if output.status.success() {
-                        let _ = io::stdout().write(output.stdout.as_slice());
-                        let _ = io::stderr().write(output.stderr.as_slice());
-                        String::from_utf8(output.stdout).with_context(|| {
-                            format!("Couldn't run {} using adb.", runnable.exe.display())
-                        })
-                    } else {
-                        bail!("Couldn't run {} using adb.", runnable.exe.display())
-                    }
-                })
-                .map(|output| output.lines().last().unwrap_or("").to_string())
-                .map(|last_line| {
-                    last_line.contains("FORWARD_RESULT_TO_DINGHY_BECAUSE_ADB_DOES_NOT=0")
-                })?
-            {
-                bail!("Test failed 🐛")
+            if self.conf.clear_logcat.unwrap_or(false) {
+                match self.adb()?.arg("logcat").arg("-c").status() {
+                    Ok(status) if status.success() => {}
+                    Ok(status) => warn!("Couldn't clear the logcat of {} ({})", self.id, status),
+                    Err(e) => warn!("Couldn't clear the logcat of {}: {:?}", self.id, e),
+                }
             }
+            // The logs are not worth failing the run for.
+            let logcat_mark = if self.capture_logcat() {
+                match self.logcat_mark() {
+                    Ok(mark) => Some(mark),
+                    Err(e) => {
+                        warn!(
+                            "Couldn't mark the logcat of {}, not capturing it: {:?}",
+                            self.id, e
+                        );
+                        None
+                    }
+                }
+            } else {
+                None
+            };
 
-            build_bundles.push(build_bundle);
+            let mut kill = self.adb()?;
+            kill.arg("shell").arg(kill_command);
+            let timeout = Timeout::new(build.build_args.timeout, Some(kill));
+
+            let start = Instant::now();
+            let captured = run_and_capture(self.adb()?.arg("shell").arg(&command), timeout)
+                .with_context(|| format!("Couldn't run {} using adb.", runnable.exe.display()))?;
+            let exit_code = if self.shell_v2 {
+                // adb exits with the status of the remote command, but also fails on its own
+                // when it loses the device.
+                if !captured.timed_out && !captured.status.success() && !self.is_reachable() {
+                    bail!(DinghyError::DeviceUnreachable(self.id.clone()))
+                }
+                if self.run_as_app() {
+                    sentinel_exit_code(&captured.output)
+                } else {
+                    captured.status.code()
+                }
+            } else {
+                if !captured.timed_out && !captured.status.success() {
+                    bail!("Couldn't run {} using adb.", runnable.exe.display())
+                }
+                sentinel_exit_code(&captured.output)
+            };
+            let report = RunnableReport::new(
+                &runnable.id,
+                &self.id,
+                exit_code,
+                start.elapsed(),
+                &captured.output,
+            );
+            let report = if captured.timed_out {
+                report.timed_out()
+            } else {
+                report
+            };
+            let success = report.is_success();
+            if let Some(mark) = logcat_mark.filter(|_| !success) {
+                let dir = logs_dir(&build_bundle.root_dir, &self.id, &runnable.id);
+                if let Err(e) = self.save_logs(&mark, &read_pid, &dir) {
+                    warn!("Couldn't save the logs of {}: {:?}", runnable.id, e);
+                }
+            }
+            outcomes.push(RunnableOutcome {
+                build_bundle,
+                report,
+            });
+            if !success && !build.build_args.no_fail_fast {
+                break;
+            }
         }
-        Ok(build_bundles)
+        Ok(outcomes)
     }
 
-    fn start_remote_lldb(&self) -> Result<String> {
-        unimplemented!()
+    fn shell(
+        &self,
+        project: &Project,
+        build: &Build,
+        runnable: &Runnable,
+        envs: &[EnvSpec],
+    ) -> Result<i32> {
+        let env = build
+            .runtime_env
+            .iter()
+            .chain(envs)
+            .cloned()
+            .collect::<Vec<_>>();
+        let (_, remote_bundle) = self.install_app(project, build, runnable)?;
+        info!(
+            "Opening a shell on {} in {}, run {}",
+            self.id,
+            remote_bundle.bundle_dir.display(),
+            remote_bundle.bundle_exe.display()
+        );
+        let command = format!(
+            "cd '{}' && export {} && exec sh",
+            path_to_str(&remote_bundle.bundle_dir)?,
+            AndroidDevice::remote_env(&remote_bundle.lib_dir, &env)?
+        );
+        let status = self.adb()?.arg("shell").arg("-t").arg(command).status()?;
+        // Killed by a signal: there is no exit code to pass on.
+        Ok(status.code().unwrap_or(1))
     }
 }
 
 impl fmt::Display for AndroidDevice {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        write!(fmt, "Android/{}", self.id)
+        write!(fmt, "Android/{}", self.id)?;
+        if !self.model.is_empty() {
+            write!(fmt, " ({} {}", self.manufacturer, self.model)?;
+            if let Some(api_level) = self.api_level {
+                write!(fmt, ", API {}", api_level)?;
+            }
+            write!(fmt, ")")?;
+        }
+        Ok(())
     }
 }
 
@@ -288,10 +985,76 @@ impl fmt::Debug for AndroidDevice {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         Ok(fmt.write_str(
             format!(
-                "Android {{ \"id\": \"{}\", \"supported_targets\": {:?} }}",
-                self.id, self.supported_targets
+                "Android {{ \"id\": \"{}\", \"supported_targets\": {:?}, \"manufacturer\": {:?}, \"model\": {:?}, \"api_level\": {:?} }}",
+                self.id, self.supported_targets, self.manufacturer, self.model, self.api_level
             )
             .as_str(),
         )?)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sentinel_exit_code() {
+        assert_eq!(
+            Some(101),
+            sentinel_exit_code(
+                "test result: FAILED. 2 passed; 1 failed\r\nFORWARD_RESULT_TO_DINGHY_BECAUSE_ADB_DOES_NOT=101\r\n"
+            )
+        );
+        assert_eq!(
+            Some(0),
+            sentinel_exit_code("FORWARD_RESULT_TO_DINGHY_BECAUSE_ADB_DOES_NOT=0")
+        );
+        // adb lost the device before the runnable ended.
+        assert_eq!(None, sentinel_exit_code("running 3 tests\n"));
+    }
+
+    #[test]
+    fn test_logs_dir() {
+        let root = path::Path::new("target/x86_64-linux-android/dinghy");
+        assert_eq!(
+            root.join("logs/emulator-5554/my_crate-0123456789abcdef"),
+            logs_dir(root, "emulator-5554", "my_crate-0123456789abcdef")
+        );
+        assert_eq!(
+            root.join("logs/192.168.1.5_5555/my_crate-0123456789abcdef"),
+            logs_dir(root, "192.168.1.5:5555", "my_crate-0123456789abcdef")
+        );
+    }
+
+    #[test]
+    fn test_filter_logcat_and_new_tombstones() {
+        let logcat = "--------- beginning of main\r
+10-18 01:02:03.456  4321  4321 I my_crate: starting\r
+10-18 01:02:03.457   812   830 D ActivityManager: unrelated\r
+10-18 01:02:03.460  4321  4322 F libc    : Fatal signal 6 (SIGABRT), code -1 (SI_QUEUE) in tid 4322\r
+10-18 01:02:03.500  4400  4400 F DEBUG   : pid: 4321, tid: 4322, name: my_crate  >>> ./_dinghy_my_crate <<<\r
+";
+        assert_eq!(
+            "10-18 01:02:03.456  4321  4321 I my_crate: starting
+10-18 01:02:03.460  4321  4322 F libc    : Fatal signal 6 (SIGABRT), code -1 (SI_QUEUE) in tid 4322
+10-18 01:02:03.500  4400  4400 F DEBUG   : pid: 4321, tid: 4322, name: my_crate  >>> ./_dinghy_my_crate <<<
+",
+            filter_logcat(logcat, Some("4321"))
+        );
+        assert_eq!(2, filter_logcat(logcat, None).lines().count());
+
+        let before = vec![
+            "-rw-r----- 1 tombstoned system 4096 2026-10-17 09:12 tombstone_00".to_string(),
+            "-rw-r----- 1 tombstoned system 4096 2026-10-17 09:13 tombstone_01".to_string(),
+        ];
+        let after = vec![
+            "-rw-r----- 1 tombstoned system 4096 2026-10-17 09:12 tombstone_00".to_string(),
+            "-rw-r----- 1 tombstoned system 8192 2026-10-18 01:02 tombstone_01".to_string(),
+            "-rw-r----- 1 tombstoned system 8192 2026-10-18 01:02 tombstone_01.pb".to_string(),
+        ];
+        assert_eq!(
+            vec!["tombstone_01", "tombstone_01.pb"],
+            new_tombstones(&before, &after)
+        );
+    }
+}
@@ -1,17 +1,40 @@
+use crate::config::AndroidConfiguration;
+use crate::config::Configuration;
 use crate::config::PlatformConfiguration;
+use crate::doctor::Check;
+use crate::doctor::Lookup;
+use crate::doctor::SystemLookup;
+use crate::errors::DinghyError;
 use crate::platform::regular_platform::RegularPlatform;
 use crate::toolchain::ToolchainConfig;
 use crate::{Compiler, Device, Platform, PlatformManager, Result};
-use std::{env, fs, path, process, sync};
+use std::{fs, path, process, sync};
 
+pub use self::avd::shutdown_emulators;
+pub use self::avd::AvdDevice;
 pub use self::device::AndroidDevice;
 
 use anyhow::Context;
 
+mod apk;
+mod avd;
 mod device;
 
+/// API level of the `auto-android-<cpu>` platforms, the first one with both 32 and 64 bits
+/// architectures.
+const DEFAULT_API_LEVEL: u32 = 21;
+
+/// The auto-android platforms: ABI, rustc cpu, clang cpu, binutils cpu and triple suffix.
+const AUTO_PLATFORMS: &[(&str, &str, &str, &str, &str)] = &[
+    ("arm64-v8a", "aarch64", "aarch64", "aarch64", "android"),
+    ("armeabi-v7a", "armv7", "armv7a", "arm", "androideabi"),
+    ("x86", "i686", "i686", "i686", "android"),
+    ("x86_64", "x86_64", "x86_64", "x86_64", "android"),
+];
+
 pub struct AndroidManager {
     compiler: sync::Arc<Compiler>,
+    conf: sync::Arc<Configuration>,
     adb: path::PathBuf,
 }
 
@@ -19,10 +42,35 @@ impl PlatformManager for AndroidManager {
     fn devices(&self) -> Result<Vec<Box<dyn Device>>> {
         let result = process::Command::new(&self.adb).arg("devices").output()?;
         let mut devices = vec![];
+        let mut running_avds = vec![];
         let device_regex = ::regex::Regex::new(r#"^(\S+)\tdevice\r?$"#)?;
         for line in String::from_utf8(result.stdout)?.split("\n").skip(1) {
             if let Some(caps) = device_regex.captures(line) {
-                let d = AndroidDevice::from_id(self.adb.clone(), &caps[1])?;
+                if caps[1].starts_with("emulator-") {
+                    if let Some(name) = avd::running_avd_name(&self.adb, &caps[1]) {
+                        // Listed once, as its AVD.
+                        running_avds.push((name, caps[1].to_string()));
+                        continue;
+                    }
+                }
+                let d = AndroidDevice::from_id(self.adb.clone(), &caps[1], &self.conf.android)?;
+                debug!(
+                    "Discovered Android device {} ({:?})",
+                    d, d.supported_targets
+                );
+                devices.push(Box::new(d) as Box<dyn Device>);
+            }
+        }
+        let avds = match emulator(&SystemLookup) {
+            Some(emulator) => {
+                AvdDevice::list(&self.adb, &emulator, &running_avds, &self.conf.android)
+            }
+            None => vec![],
+        };
+        for (name, serial) in &running_avds {
+            // Running AVDs the emulator does not list, like the ones of another SDK.
+            if !avds.iter().any(|avd| avd.name() == name) {
+                let d = AndroidDevice::from_id(self.adb.clone(), serial, &self.conf.android)?;
                 debug!(
                     "Discovered Android device {} ({:?})",
                     d, d.supported_targets
@@ -30,108 +78,45 @@ impl PlatformManager for AndroidManager {
                 devices.push(Box::new(d) as Box<dyn Device>);
             }
         }
+        for avd in avds {
+            debug!("Discovered AVD {} ({:?})", avd, avd.supported_targets);
+            devices.push(Box::new(avd) as Box<dyn Device>);
+        }
         Ok(devices)
     }
     fn platforms(&self) -> Result<Vec<Box<dyn Platform>>> {
-        if let Some(ndk) = ndk()? {
-            let default_api_level = "21";
-            debug!("Android NDK: {:?}", ndk);
-            let version = ndk_version(&ndk)?;
-            let major = version
-                .split(".")
-                .next()
-                .ok_or_else(|| anyhow!("Invalid version found for ndk {:?}", &ndk))?;
-            let major: usize = major
-                .parse()
-                .with_context(|| format!("Invalid version found for ndk {:?}", &ndk))?;
-            debug!(
-                "Android ndk: {:?}, ndk version: {}, major: {}",
-                ndk, version, major
-            );
-            if major >= 19 {
-                let mut platforms = vec![];
-                let prebuilt = ndk.join("toolchains/llvm/prebuilt");
-                let tools = prebuilt
-                    .read_dir()?
-                    .next()
-                    .ok_or_else(|| anyhow!("No tools in toolchain"))??;
-                let bin = tools.path().join("bin");
-                debug!("Android tools bin: {:?}", bin);
-                for (rustc_cpu, cc_cpu, binutils_cpu, abi_kind) in &[
-                    ("aarch64", "aarch64", "aarch64", "android"),
-                    ("armv7", "armv7a", "arm", "androideabi"),
-                    ("i686", "i686", "i686", "android"),
-                    ("x86_64", "x86_64", "x86_64", "android"),
-                ] {
-                    let mut api_levels: Vec<String> = Vec::new();
-                    for entry in tools
-                        .path()
-                        .join(format!(
-                            "sysroot/usr/lib/{}-linux-{}",
-                            binutils_cpu, abi_kind
-                        ))
-                        .read_dir()?
-                    {
-                        let entry = entry?;
-                        if entry.file_type()?.is_dir() {
-                            let folder_name = entry.file_name().into_string().unwrap();
-                            match folder_name.parse::<u32>() {
-                                Ok(_) => api_levels.push(folder_name),
-                                Err(_) => {}
-                            }
-                        }
-                    }
-                    api_levels.sort();
-                    let create_platform = |api: &str, suffix: &str| {
-                        let id = format!("auto-android-{}{}", rustc_cpu, suffix);
-                        let tc = ToolchainConfig {
-                            bin_dir: bin.clone(),
-                            rustc_triple: format!("{}-linux-{}", rustc_cpu, abi_kind),
-                            root: prebuilt.clone(),
-                            sysroot: Some(tools.path().join("sysroot")),
-                            cc: "clang".to_string(),
-                            binutils_prefix: format!("{}-linux-{}", binutils_cpu, abi_kind),
-                            cc_prefix: format!("{}-linux-{}{}", cc_cpu, abi_kind, api),
-                        };
-                        RegularPlatform::new_with_tc(
-                            self.compiler.clone(),
-                            PlatformConfiguration::default(),
-                            id,
-                            tc,
-                        )
-                    };
-                    for api in api_levels.iter() {
-                        platforms.push(create_platform(&api, &format!("-api{}", api))?);
-                    }
-                    if !api_levels.is_empty() {
-                        platforms.push(create_platform(
-                            api_levels
-                                .first()
-                                .expect("The api level vector shouldn't be empty"),
-                            "-min",
-                        )?);
-                        platforms.push(create_platform(
-                            api_levels
-                                .last()
-                                .expect("The api level vector shouldn't be empty"),
-                            "-latest",
-                        )?);
-                    }
-                    platforms.push(create_platform(default_api_level, "")?);
-                }
-                return Ok(platforms);
+        let conf = &self.conf.android;
+        let ndk = match platforms_ndk(conf, &SystemLookup)? {
+            Some(ndk) => ndk,
+            None => return Ok(vec![]),
+        };
+        match self.auto_platforms(&ndk) {
+            Err(e) if conf.ndk.is_none() => {
+                warn!(
+                    "No auto-android platforms for the NDK at {}: {:?}",
+                    ndk.display(),
+                    e
+                );
+                Ok(vec![])
             }
+            platforms => platforms,
         }
-        return Ok(vec![]);
     }
 }
 
 impl AndroidManager {
-    pub fn probe(compiler: sync::Arc<Compiler>) -> Option<AndroidManager> {
-        match adb() {
+    pub fn probe(
+        compiler: sync::Arc<Compiler>,
+        conf: sync::Arc<Configuration>,
+    ) -> Option<AndroidManager> {
+        match adb(&SystemLookup) {
             Ok(adb) => {
                 debug!("ADB found: {:?}", adb);
-                Some(AndroidManager { adb, compiler })
+                Some(AndroidManager {
+                    adb,
+                    compiler,
+                    conf,
+                })
             }
             Err(_) => {
                 debug!("adb not found in path, android disabled");
@@ -139,9 +124,139 @@ impl AndroidManager {
             }
         }
     }
+
+    /// The auto-android platforms of `ndk`, checked by `platforms_ndk`.
+    fn auto_platforms(&self, ndk: &path::Path) -> Result<Vec<Box<dyn Platform>>> {
+        let conf = &self.conf.android;
+        let default_api_level = conf
+            .default_api_level
+            .unwrap_or(DEFAULT_API_LEVEL)
+            .to_string();
+        let mut platforms = vec![];
+        let prebuilt = ndk.join("toolchains/llvm/prebuilt");
+        let tools = prebuilt
+            .read_dir()?
+            .next()
+            .ok_or_else(|| anyhow!("No tools in toolchain"))??;
+        let bin = tools.path().join("bin");
+        debug!("Android tools bin: {:?}", bin);
+        for (abi, rustc_cpu, cc_cpu, binutils_cpu, abi_kind) in AUTO_PLATFORMS {
+            if let Some(abis) = &conf.abis {
+                if !abis.iter().any(|it| it == abi) {
+                    continue;
+                }
+            }
+            let mut api_levels: Vec<String> = Vec::new();
+            for entry in tools
+                .path()
+                .join(format!(
+                    "sysroot/usr/lib/{}-linux-{}",
+                    binutils_cpu, abi_kind
+                ))
+                .read_dir()?
+            {
+                let entry = entry?;
+                if entry.file_type()?.is_dir() {
+                    let folder_name = entry.file_name().into_string().unwrap();
+                    match folder_name.parse::<u32>() {
+                        Ok(_) => api_levels.push(folder_name),
+                        Err(_) => {}
+                    }
+                }
+            }
+            api_levels.sort();
+            let create_platform = |api: &str, suffix: &str| {
+                let id = format!("auto-android-{}{}", rustc_cpu, suffix);
+                let tc = ToolchainConfig {
+                    bin_dir: bin.clone(),
+                    rustc_triple: format!("{}-linux-{}", rustc_cpu, abi_kind),
+                    root: prebuilt.clone(),
+                    sysroot: Some(tools.path().join("sysroot")),
+                    cc: "clang".to_string(),
+                    binutils_prefix: format!("{}-linux-{}", binutils_cpu, abi_kind),
+                    cc_prefix: format!("{}-linux-{}{}", cc_cpu, abi_kind, api),
+                    // The app wrapping a runnable loads it as a library, and calls its main.
+                    link_args: if conf.run_as_app.unwrap_or(false) {
+                        vec!["-Wl,--export-dynamic".to_string()]
+                    } else {
+                        vec![]
+                    },
+                };
+                RegularPlatform::new_with_tc(
+                    self.compiler.clone(),
+                    PlatformConfiguration::default(),
+                    id,
+                    tc,
+                )
+            };
+            for api in api_levels.iter() {
+                platforms.push(create_platform(&api, &format!("-api{}", api))?);
+            }
+            if !api_levels.is_empty() {
+                platforms.push(create_platform(
+                    api_levels
+                        .first()
+                        .expect("The api level vector shouldn't be empty"),
+                    "-min",
+                )?);
+                platforms.push(create_platform(
+                    api_levels
+                        .last()
+                        .expect("The api level vector shouldn't be empty"),
+                    "-latest",
+                )?);
+            }
+            platforms.push(create_platform(&default_api_level, "")?);
+        }
+        Ok(platforms)
+    }
+}
+
+/// The NDK to make the auto-android platforms with, if any.
+///
+/// What `[android] ndk` and `abis` set explicitly fails with a `DinghyError::Config` when dinghy
+/// can't use it. An NDK dinghy finds itself and can't use only leaves the auto-android platforms
+/// out, with a warning, rather than failing every command: `cargo dinghy doctor` reports it.
+fn platforms_ndk(
+    conf: &AndroidConfiguration,
+    lookup: &dyn Lookup,
+) -> Result<Option<path::PathBuf>> {
+    check_abis(conf)?;
+    let ndk = match ndk(conf, lookup) {
+        Ok(Some(ndk)) => ndk,
+        Ok(None) => return Ok(None),
+        Err(e) if conf.ndk.is_none() => {
+            warn!("Couldn't look for an Android NDK: {:?}", e);
+            return Ok(None);
+        }
+        Err(e) => return Err(e),
+    };
+    let checked = ndk_version(&ndk).and_then(|version| {
+        let major = ndk_major(&ndk, &version)?;
+        debug!(
+            "Android ndk: {:?}, ndk version: {}, major: {}",
+            ndk, version, major
+        );
+        if major < 19 {
+            bail!(DinghyError::Config(format!(
+                "the NDK {} at {} is not supported by dinghy, which needs NDK 19 or newer, older ones need a standalone toolchain",
+                version,
+                ndk.display()
+            )))
+        }
+        Ok(())
+    });
+    match checked {
+        Ok(()) => Ok(Some(ndk)),
+        Err(e) if conf.ndk.is_none() => {
+            warn!("Ignoring the Android NDK at {}: {:?}", ndk.display(), e);
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
 }
 
-fn probable_sdk_locs() -> Result<Vec<path::PathBuf>> {
+fn probable_sdk_locs(lookup: &dyn Lookup) -> Result<Vec<path::PathBuf>> {
     let mut v = vec![];
     for var in &[
         "ANDROID_HOME",
@@ -149,14 +264,14 @@ fn probable_sdk_locs() -> Result<Vec<path::PathBuf>> {
         "ANDROID_SDK_ROOT",
         "ANDROID_SDK_HOME",
     ] {
-        if let Ok(path) = env::var(var) {
+        if let Some(path) = lookup.var(var) {
             let path = path::Path::new(&path);
             if path.is_dir() {
                 v.push(path.to_path_buf())
             }
         }
     }
-    if let Ok(home) = env::var("HOME") {
+    if let Some(home) = lookup.var("HOME") {
         let mac = path::Path::new(&home).join("/Library/Android/sdk");
         if mac.is_dir() {
             v.push(mac);
@@ -175,15 +290,48 @@ fn probable_sdk_locs() -> Result<Vec<path::PathBuf>> {
     Ok(v)
 }
 
-fn ndk() -> Result<Option<path::PathBuf>> {
-    if let Ok(path) = env::var("ANDROID_NDK_HOME") {
+/// Fails on the ABIs of `[android] abis` that have no auto-android platform.
+fn check_abis(conf: &AndroidConfiguration) -> Result<()> {
+    for abi in conf.abis.iter().flatten() {
+        if !AUTO_PLATFORMS.iter().any(|it| it.0 == abi) {
+            bail!(DinghyError::Config(format!(
+                "unknown ABI {} in [android] abis, expected some of {}",
+                abi,
+                AUTO_PLATFORMS
+                    .iter()
+                    .map(|it| it.0)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )))
+        }
+    }
+    Ok(())
+}
+
+fn ndk(conf: &AndroidConfiguration, lookup: &dyn Lookup) -> Result<Option<path::PathBuf>> {
+    if let Some(ndk) = &conf.ndk {
+        let path = path::Path::new(ndk);
+        if path.is_dir() {
+            return Ok(Some(path.to_path_buf()));
+        }
+        for sdk in probable_sdk_locs(lookup)? {
+            if let Some(ndk) = find_non_legacy_ndk(&sdk, Some(ndk))? {
+                return Ok(Some(ndk));
+            }
+        }
+        bail!(DinghyError::Config(format!(
+            "NDK {} of [android] ndk is neither a directory nor installed in the SDK",
+            ndk
+        )))
+    }
+    if let Some(path) = lookup.var("ANDROID_NDK_HOME") {
         return Ok(Some(path.into()));
     }
-    for sdk in probable_sdk_locs()? {
+    for sdk in probable_sdk_locs(lookup)? {
         if sdk.join("ndk-bundle/source.properties").is_file() {
             return Ok(Some(sdk.join("ndk-bundle")));
         }
-        if let Some(ndk) = find_non_legacy_ndk(&sdk)? {
+        if let Some(ndk) = find_non_legacy_ndk(&sdk, None)? {
             return Ok(Some(ndk));
         }
     }
@@ -211,7 +359,132 @@ fn ndk_version(ndk: &path::Path) -> Result<String> {
     Ok(revision_line.split(" ").last().unwrap().to_string())
 }
 
-fn adb() -> Result<path::PathBuf> {
+fn ndk_major(ndk: &path::Path, version: &str) -> Result<usize> {
+    let major = version
+        .split(".")
+        .next()
+        .ok_or_else(|| anyhow!("Invalid version found for ndk {:?}", &ndk))?;
+    major
+        .parse()
+        .with_context(|| format!("Invalid version found for ndk {:?}", &ndk))
+}
+
+/// Checks the Android SDK tools and NDK dinghy looks for.
+pub fn doctor(conf: &Configuration, lookup: &dyn Lookup) -> Vec<Check> {
+    let mut checks = vec![];
+    match adb(lookup) {
+        Ok(adb) => match process::Command::new(&adb).arg("devices").output() {
+            Ok(output) if output.status.success() => {
+                checks.push(Check::ok("android", "adb", adb.display().to_string()));
+                let devices = String::from_utf8_lossy(&output.stdout)
+                    .lines()
+                    .skip(1)
+                    .filter(|line| line.ends_with("\tdevice"))
+                    .count();
+                checks.push(Check::ok(
+                    "android",
+                    "devices",
+                    format!("{} connected", devices),
+                ));
+            }
+            _ => checks.push(Check::error(
+                "android",
+                "adb",
+                format!("`{} devices` failed", adb.display()),
+                "Check that DINGHY_ANDROID_ADB, if set, points to a working adb, or run `adb kill-server`",
+            )),
+        },
+        Err(_) => checks.push(Check::warning(
+            "android",
+            "adb",
+            "not found",
+            "Install the Android SDK platform-tools and put adb in the PATH, set ANDROID_SDK_ROOT, or set DINGHY_ANDROID_ADB",
+        )),
+    }
+    if let Some(emulator) = emulator(lookup) {
+        checks.push(Check::ok(
+            "android",
+            "emulator",
+            emulator.display().to_string(),
+        ));
+    }
+    if let Err(e) = check_abis(&conf.android) {
+        checks.push(Check::error(
+            "android",
+            "abis",
+            e.to_string(),
+            "Fix [android] abis in dinghy.toml",
+        ));
+    }
+    let ndk = match ndk(&conf.android, lookup) {
+        Ok(Some(ndk)) => ndk,
+        Err(e) => {
+            checks.push(Check::error(
+                "android",
+                "ndk",
+                e.to_string(),
+                "Install the NDK [android] ndk names with `sdkmanager 'ndk;<version>'`, or fix its path",
+            ));
+            return checks;
+        }
+        Ok(None) => {
+            checks.push(Check::warning(
+                "android",
+                "ndk",
+                "not found",
+                "Install the NDK with `sdkmanager ndk-bundle` and set ANDROID_NDK_HOME to its location",
+            ));
+            return checks;
+        }
+    };
+    checks.push(Check::ok("android", "ndk", ndk.display().to_string()));
+    let version = match ndk_version(&ndk) {
+        Ok(version) => version,
+        Err(e) => {
+            checks.push(Check::error(
+                "android",
+                "ndk version",
+                e.to_string(),
+                "ANDROID_NDK_HOME should point to an NDK, with a source.properties file",
+            ));
+            return checks;
+        }
+    };
+    match ndk_major(&ndk, &version) {
+        Ok(major) if major >= 19 => checks.push(Check::ok("android", "ndk version", version)),
+        _ => {
+            checks.push(Check::error(
+                "android",
+                "ndk version",
+                format!("{} is not supported", version),
+                "Dinghy needs NDK 19 or newer, install one with `sdkmanager 'ndk;<version>'`",
+            ));
+            return checks;
+        }
+    }
+    let prebuilt = ndk.join("toolchains/llvm/prebuilt");
+    match prebuilt
+        .read_dir()
+        .ok()
+        .and_then(|mut dir| dir.next())
+        .and_then(|tools| tools.ok())
+    {
+        Some(tools) => checks.push(Check::ok(
+            "android",
+            "toolchain bin dir",
+            tools.path().join("bin").display().to_string(),
+        )),
+        None => checks.push(Check::error(
+            "android",
+            "toolchain bin dir",
+            format!("no toolchain in {}", prebuilt.display()),
+            "The NDK installation looks incomplete, reinstall it",
+        )),
+    }
+    checks
+}
+
+fn adb(lookup: &dyn Lookup) -> Result<path::PathBuf> {
     fn try_out(command: &path::Path) -> bool {
         match process::Command::new(command)
             .arg("--version")
@@ -223,13 +496,13 @@ fn adb() -> Result<path::PathBuf> {
             Err(_) => false,
         }
     }
-    if let Ok(adb) = env::var("DINGHY_ANDROID_ADB") {
+    if let Some(adb) = lookup.var("DINGHY_ANDROID_ADB") {
         return Ok(adb.into());
     }
-    if let Ok(adb) = ::which::which("adb") {
+    if let Some(adb) = lookup.which("adb") {
         return Ok(adb);
     }
-    for loc in probable_sdk_locs()? {
+    for loc in probable_sdk_locs(lookup)? {
         let adb = loc.join("platform-tools/adb");
         if try_out(&adb) {
             return Ok(adb.into());
@@ -238,7 +511,24 @@ fn adb() -> Result<path::PathBuf> {
     bail!("Adb could be found")
 }
 
-fn find_non_legacy_ndk(sdk: &path::Path) -> Result<Option<path::PathBuf>> {
+/// The Android emulator, from `DINGHY_ANDROID_EMULATOR`, the `PATH` or the SDK.
+fn emulator(lookup: &dyn Lookup) -> Option<path::PathBuf> {
+    if let Some(emulator) = lookup.var("DINGHY_ANDROID_EMULATOR") {
+        return Some(emulator.into());
+    }
+    if let Some(emulator) = lookup.which("emulator") {
+        return Some(emulator);
+    }
+    probable_sdk_locs(lookup)
+        .ok()?
+        .into_iter()
+        .map(|sdk| sdk.join("emulator/emulator"))
+        .find(|emulator| emulator.is_file())
+}
+
+/// The latest NDK installed in `sdk`, of `version` if given: `25` or `25.2` picks the latest
+/// `25.*` or `25.2.*` one.
+fn find_non_legacy_ndk(sdk: &path::Path, version: Option<&str>) -> Result<Option<path::PathBuf>> {
     let ndk_root = sdk.join("ndk");
     if !ndk_root.is_dir() {
         return Ok(None);
@@ -251,6 +541,15 @@ fn find_non_legacy_ndk(sdk: &path::Path) -> Result<Option<path::PathBuf>> {
             directory
                 .path()
                 .file_name()
+                .filter(|name| {
+                    let name = name.to_string_lossy();
+                    match version {
+                        Some(version) => {
+                            name == version || name.starts_with(&format!("{}.", version))
+                        }
+                        None => true,
+                    }
+                })
                 .and_then(|name| {
                     let name = name.to_string_lossy();
                     // Filter out directory if we fail to parse directory name to semver
@@ -271,6 +570,9 @@ fn find_non_legacy_ndk(sdk: &path::Path) -> Result<Option<path::PathBuf>> {
 mod tests {
 
     use super::*;
+    use crate::doctor::tests::status_of;
+    use crate::doctor::tests::FakeLookup;
+    use crate::doctor::CheckStatus;
 
     #[test]
     fn test_find_non_legacy_ndk() {
@@ -283,18 +585,177 @@ mod tests {
             fs::create_dir_all(path).unwrap();
         }
 
-        let ndk = find_non_legacy_ndk(sdk).unwrap();
+        let ndk = find_non_legacy_ndk(sdk, None).unwrap();
 
         let expected = ndk_root.join("21.3.6528147");
         assert_eq!(Some(expected), ndk);
     }
 
+    #[test]
+    fn test_find_non_legacy_ndk_of_version() {
+        let sdk_dir = tempfile::tempdir().unwrap();
+        let sdk = sdk_dir.path();
+        let ndk_root = sdk.join("ndk");
+        let ndk_versions = ["21.1.123456", "21.3.6528147", "20.1.5948944", "210.0.1"];
+        for version in &ndk_versions {
+            let path = ndk_root.join(version);
+            fs::create_dir_all(path).unwrap();
+        }
+
+        let ndk = find_non_legacy_ndk(sdk, Some("21")).unwrap();
+        assert_eq!(Some(ndk_root.join("21.3.6528147")), ndk);
+        let ndk = find_non_legacy_ndk(sdk, Some("21.1.123456")).unwrap();
+        assert_eq!(Some(ndk_root.join("21.1.123456")), ndk);
+        let ndk = find_non_legacy_ndk(sdk, Some("19")).unwrap();
+        assert_eq!(None, ndk);
+    }
+
     #[test]
     fn test_find_non_legacy_ndk_on_non_existing_directory() {
         let sdk = tempfile::tempdir().unwrap();
 
-        let ndk = find_non_legacy_ndk(sdk.path()).unwrap();
+        let ndk = find_non_legacy_ndk(sdk.path(), None).unwrap();
 
         assert_eq!(None, ndk);
     }
+
+    #[test]
+    fn test_doctor_warns_about_a_missing_ndk() {
+        let checks = doctor(&Configuration::default(), &FakeLookup::default());
+
+        assert_eq!(
+            Some(CheckStatus::Warning),
+            status_of(&checks, "android", "ndk")
+        );
+        assert_eq!(
+            Some(CheckStatus::Warning),
+            status_of(&checks, "android", "adb")
+        );
+    }
+
+    #[test]
+    fn test_doctor_accepts_an_ndk_from_android_ndk_home() {
+        let ndk = tempfile::tempdir().unwrap();
+        fs::write(
+            ndk.path().join("source.properties"),
+            "Pkg.Desc = Android NDK\nPkg.Revision = 25.2.9519653\n",
+        )
+        .unwrap();
+        fs::create_dir_all(ndk.path().join("toolchains/llvm/prebuilt/linux-x86_64")).unwrap();
+        let mut lookup = FakeLookup::default();
+        lookup.vars.insert(
+            "ANDROID_NDK_HOME".to_string(),
+            ndk.path().display().to_string(),
+        );
+
+        let checks = doctor(&Configuration::default(), &lookup);
+
+        assert_eq!(Some(CheckStatus::Ok), status_of(&checks, "android", "ndk"));
+        assert_eq!(
+            Some(CheckStatus::Ok),
+            status_of(&checks, "android", "ndk version")
+        );
+        assert_eq!(
+            Some(CheckStatus::Ok),
+            status_of(&checks, "android", "toolchain bin dir")
+        );
+    }
+
+    /// An NDK of `version`, without toolchains.
+    fn fake_ndk(version: &str) -> tempfile::TempDir {
+        let ndk = tempfile::tempdir().unwrap();
+        fs::write(
+            ndk.path().join("source.properties"),
+            format!("Pkg.Desc = Android NDK\nPkg.Revision = {}\n", version),
+        )
+        .unwrap();
+        ndk
+    }
+
+    fn is_config_error(result: Result<Option<path::PathBuf>>) -> bool {
+        matches!(
+            result.map_err(|e| DinghyError::of(&e).cloned()),
+            Err(Some(DinghyError::Config(_)))
+        )
+    }
+
+    #[test]
+    fn test_platforms_ndk_rejects_what_android_sets_explicitly() {
+        let conf = AndroidConfiguration {
+            abis: Some(vec!["mips".to_string()]),
+            ..AndroidConfiguration::default()
+        };
+        assert!(is_config_error(platforms_ndk(
+            &conf,
+            &FakeLookup::default()
+        )));
+
+        let mut conf = AndroidConfiguration {
+            ndk: Some("/nonexistent/ndk".to_string()),
+            ..AndroidConfiguration::default()
+        };
+        assert!(is_config_error(platforms_ndk(
+            &conf,
+            &FakeLookup::default()
+        )));
+
+        let old = fake_ndk("18.1.5063045");
+        conf.ndk = Some(old.path().display().to_string());
+        assert!(is_config_error(platforms_ndk(
+            &conf,
+            &FakeLookup::default()
+        )));
+
+        let ndk = fake_ndk("25.2.9519653");
+        conf.ndk = Some(ndk.path().display().to_string());
+        assert_eq!(
+            Some(ndk.path().to_path_buf()),
+            platforms_ndk(&conf, &FakeLookup::default()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_platforms_ndk_ignores_an_unusable_detected_ndk() {
+        let conf = AndroidConfiguration::default();
+        let with_ndk_home = |ndk: &path::Path| {
+            let mut lookup = FakeLookup::default();
+            lookup
+                .vars
+                .insert("ANDROID_NDK_HOME".to_string(), ndk.display().to_string());
+            lookup
+        };
+        assert_eq!(None, platforms_ndk(&conf, &FakeLookup::default()).unwrap());
+
+        let old = fake_ndk("18.1.5063045");
+        assert_eq!(
+            None,
+            platforms_ndk(&conf, &with_ndk_home(old.path())).unwrap()
+        );
+        let missing = path::Path::new("/nonexistent/ndk");
+        assert_eq!(None, platforms_ndk(&conf, &with_ndk_home(missing)).unwrap());
+
+        let ndk = fake_ndk("25.2.9519653");
+        assert_eq!(
+            Some(ndk.path().to_path_buf()),
+            platforms_ndk(&conf, &with_ndk_home(ndk.path())).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_doctor_rejects_a_bad_android_configuration() {
+        let mut conf = Configuration::default();
+        conf.android.abis = Some(vec!["mips".to_string()]);
+        conf.android.ndk = Some("/nonexistent/ndk".to_string());
+
+        let checks = doctor(&conf, &FakeLookup::default());
+
+        assert_eq!(
+            Some(CheckStatus::Error),
+            status_of(&checks, "android", "abis")
+        );
+        assert_eq!(
+            Some(CheckStatus::Error),
+            status_of(&checks, "android", "ndk")
+        );
+    }
 }
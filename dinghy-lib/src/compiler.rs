@@ -1,5 +1,6 @@
 extern crate cargo;
 
+use crate::errors::DinghyError;
 use crate::utils::arg_as_string_vec;
 use crate::utils::copy_and_sync_file;
 use crate::utils::is_library;
@@ -45,9 +46,9 @@ use anyhow::Context;
 use crate::Platform;
 
 pub struct Compiler {
-    build_command: Box<dyn Fn(&dyn Platform, &BuildArgs) -> Result<Build>>,
-    clean_command: Box<dyn Fn(&dyn Platform) -> Result<()>>,
-    run_command: Box<dyn Fn(&dyn Platform, &BuildArgs, &[&str]) -> Result<()>>,
+    build_command: Box<dyn Fn(&dyn Platform, &BuildArgs) -> Result<Build> + Send + Sync>,
+    clean_command: Box<dyn Fn(&dyn Platform) -> Result<()> + Send + Sync>,
+    run_command: Box<dyn Fn(&dyn Platform, &BuildArgs, &[&str]) -> Result<()> + Send + Sync>,
 }
 
 impl Compiler {
@@ -124,7 +125,7 @@ fn profile(release: bool, build_args: &BuildArgs) -> InternedString {
 
 fn create_build_command(
     matches: &ArgMatches,
-) -> Result<Box<dyn Fn(&dyn Platform, &BuildArgs) -> Result<Build>>> {
+) -> Result<Box<dyn Fn(&dyn Platform, &BuildArgs) -> Result<Build> + Send + Sync>> {
     let all = matches.is_present("ALL");
     let all_features = matches.is_present("ALL_FEATURES");
     let benches = arg_as_string_vec(matches, "BENCH");
@@ -176,11 +177,10 @@ fn create_build_command(
                 .collect::<Vec<_>>();
 
             if filtered_packages.is_empty() {
-                bail!(
-                    "packages {:?} are filtered out on platform {:?}",
-                    packages,
-                    platform
-                )
+                bail!(DinghyError::PackagesFilteredOut {
+                    packages: packages.clone(),
+                    platform: platform.id(),
+                })
             } else {
                 (filtered_packages, vec![]) // Exclude not allowed with -p, hence empty vec.
             }
@@ -225,7 +225,8 @@ fn create_build_command(
         if bearded {
             setup_dinghy_wrapper(&workspace, platform)?;
         }
-        let compilation = ops::compile(&workspace, &compile_options)?;
+        let compilation =
+            ops::compile(&workspace, &compile_options).context(DinghyError::BuildFailed)?;
         let build = to_build(compilation, &config, build_args, platform)?;
         copy_dependencies_to_target(&build)?;
         Ok(build)
@@ -233,14 +234,16 @@ fn create_build_command(
     Ok(f)
 }
 
-fn create_clean_command(matches: &ArgMatches) -> Result<Box<dyn Fn(&dyn Platform) -> Result<()>>> {
+fn create_clean_command(
+    matches: &ArgMatches,
+) -> Result<Box<dyn Fn(&dyn Platform) -> Result<()> + Send + Sync>> {
     let packages = arg_as_string_vec(matches, "SPEC");
     let release = matches.is_present("RELEASE");
     let offline = matches.is_present("OFFLINE");
     let verbosity = matches.occurrences_of("VERBOSE") as u32;
-    let config = config(offline, verbosity)?;
 
     let f = Box::new(move |platform: &dyn Platform| {
+        let config = config(offline, verbosity)?;
         let workspace = Workspace::new(&find_root_manifest_for_wd(&current_dir()?)?, &config)?;
         let requested_profile = InternedString::new(if release { "release" } else { "debug" });
 
@@ -261,7 +264,7 @@ fn create_clean_command(matches: &ArgMatches) -> Result<Box<dyn Fn(&dyn Platform
 
 fn create_run_command(
     matches: &ArgMatches,
-) -> Result<Box<dyn Fn(&dyn Platform, &BuildArgs, &[&str]) -> Result<()>>> {
+) -> Result<Box<dyn Fn(&dyn Platform, &BuildArgs, &[&str]) -> Result<()> + Send + Sync>> {
     let all = matches.is_present("ALL");
     let all_features = matches.is_present("ALL_FEATURES");
     let benches = arg_as_string_vec(matches, "BENCH");
@@ -342,7 +345,7 @@ fn create_run_command(
             let test_options = TestOptions {
                 compile_opts: compile_options,
                 no_run: false,
-                no_fail_fast: false,
+                no_fail_fast: build_args.no_fail_fast,
             };
 
             if bearded {
@@ -441,6 +444,11 @@ fn to_build(
     build_args: &BuildArgs,
     platform: &dyn Platform,
 ) -> Result<Build> {
+    // Only running under qemu or a debugger needs it, which then goes without.
+    let sysroot = platform.sysroot().unwrap_or_else(|e| {
+        debug!("No sysroot for {}: {:?}", platform.id(), e);
+        None
+    });
     match build_args.compile_mode {
         CompileMode::Build => Ok(Build {
             build_args: build_args.clone(),
@@ -466,6 +474,9 @@ fn to_build(
                     })
                 })
                 .collect::<Result<Vec<_>>>()?,
+            runtime_env: vec![],
+            rustc_triple: platform.rustc_triple().to_string(),
+            sysroot: sysroot.clone(),
             target_path: compilation.root_output[&platform.as_cargo_kind()].clone(),
         }),
 
@@ -492,6 +503,9 @@ fn to_build(
                     })
                 })
                 .collect::<Result<Vec<_>>>()?,
+            runtime_env: vec![],
+            rustc_triple: platform.rustc_triple().to_string(),
+            sysroot,
             target_path: compilation.root_output[&platform.as_cargo_kind()].clone(),
         }),
     }
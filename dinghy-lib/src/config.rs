@@ -6,6 +6,7 @@ use std::result;
 use std::{collections, fs, path};
 //use walkdir::WalkDir;
 
+use crate::env::EnvSpec;
 use crate::errors::*;
 
 #[derive(Clone, Debug)]
@@ -80,17 +81,23 @@ impl<'de> de::Deserialize<'de> for TestDataConfiguration {
 
 #[derive(Clone, Debug, Default)]
 pub struct Configuration {
+    pub android: AndroidConfiguration,
     pub platforms: collections::BTreeMap<String, PlatformConfiguration>,
     pub ssh_devices: collections::BTreeMap<String, SshDeviceConfiguration>,
     pub script_devices: collections::BTreeMap<String, ScriptDeviceConfiguration>,
+    pub container_devices: collections::BTreeMap<String, ContainerDeviceConfiguration>,
+    pub sharding: ShardingConfiguration,
     pub test_data: Vec<TestData>,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug, Default)]
 struct ConfigurationFileContent {
+    pub android: Option<AndroidConfiguration>,
     pub platforms: Option<collections::BTreeMap<String, PlatformConfiguration>>,
     pub ssh_devices: Option<collections::BTreeMap<String, SshDeviceConfiguration>>,
     pub script_devices: Option<collections::BTreeMap<String, ScriptDeviceConfiguration>>,
+    pub container_devices: Option<collections::BTreeMap<String, ContainerDeviceConfiguration>>,
+    pub sharding: Option<ShardingConfiguration>,
     pub test_data: Option<collections::BTreeMap<String, TestDataConfiguration>>,
 }
 
@@ -100,7 +107,12 @@ pub struct PlatformConfiguration {
     pub env: Option<collections::HashMap<String, String>>,
     pub overlays: Option<collections::HashMap<String, OverlayConfiguration>>,
     pub rustc_triple: Option<String>,
+    /// Environment variables set on the device when running, where `env` is set on the host
+    /// when building.
+    pub runtime_env: Option<collections::BTreeMap<String, String>>,
     pub sysroot: Option<String>,
+    /// Seconds after which runnables are killed on the devices of this platform.
+    pub timeout: Option<u64>,
     pub toolchain: Option<String>,
 }
 
@@ -111,7 +123,9 @@ impl PlatformConfiguration {
             env: None,
             overlays: None,
             rustc_triple: None,
+            runtime_env: None,
             sysroot: None,
+            timeout: None,
             toolchain: None,
         }
     }
@@ -126,6 +140,14 @@ impl PlatformConfiguration {
             })
             .unwrap_or(vec![])
     }
+
+    pub fn runtime_env(&self) -> Result<Vec<EnvSpec>> {
+        self.runtime_env
+            .iter()
+            .flatten()
+            .map(|(key, value)| EnvSpec::new(key.as_str(), value.as_str()))
+            .collect()
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug, Default)]
@@ -145,13 +167,120 @@ pub struct SshDeviceConfiguration {
     pub platform: Option<String>,
     #[serde(default)]
     pub remote_shell_vars: collections::HashMap<String, String>,
+    /// Environment variables set when running on this device.
+    #[serde(default)]
+    pub env: collections::BTreeMap<String, String>,
     pub install_adhoc_rsync_local_path: Option<String>,
+    /// Seconds after which runnables are killed on this device.
+    pub timeout: Option<u64>,
+    pub transport: Option<SshTransport>,
+    /// Private key used to authenticate, instead of the ssh agent and default keys.
+    pub identity_file: Option<String>,
+    /// Host to go through to reach the device, as in ssh's `-J` option.
+    pub proxy_jump: Option<String>,
+    /// Extra ssh options, passed as `-o key=value`.
+    #[serde(default)]
+    pub ssh_options: collections::BTreeMap<String, String>,
+    /// Reuse a single ssh connection for all the commands run on the device.
+    pub control_master: Option<bool>,
+    /// With the native transport, connect to a device whose host key is not in
+    /// `~/.ssh/known_hosts` instead of failing.
+    pub accept_unknown_host_key: Option<bool>,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SshTransport {
+    /// Shell out to the `ssh`, `scp` and `rsync` commands.
+    Openssh,
+    /// Talk ssh in-process, requires the `native-ssh` feature.
+    Native,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct ScriptDeviceConfiguration {
     pub path: String,
     pub platform: Option<String>,
+    pub protocol: Option<ScriptProtocol>,
+    /// Give the script a self-contained bundle (executable, dynamic libraries and test data),
+    /// as sent to ssh devices, instead of the executable from the target directory.
+    #[serde(default)]
+    pub bundle: bool,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ScriptProtocol {
+    /// The script is called with the executable and its arguments.
+    Simple,
+    /// The script is called with a subcommand, and details about the device, the bundle and
+    /// the arguments as JSON on stdin.
+    Extended,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ContainerDeviceConfiguration {
+    pub image: String,
+    pub runtime: Option<ContainerRuntime>,
+    pub platform: Option<String>,
+    /// Extra volumes, as given to `--volume` (`/host/path:/container/path[:options]`).
+    #[serde(default)]
+    pub volumes: Vec<String>,
+    /// Environment variables set when running in the container.
+    #[serde(default)]
+    pub env: collections::BTreeMap<String, String>,
+    /// Extra arguments given to `docker run` or `podman run` (`--user`, `--network`...).
+    #[serde(default)]
+    pub run_args: Vec<String>,
+    /// Seconds after which runnables are killed in the container.
+    pub timeout: Option<u64>,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ContainerRuntime {
+    Docker,
+    Podman,
+}
+
+impl ContainerRuntime {
+    pub fn command(&self) -> &'static str {
+        match self {
+            ContainerRuntime::Docker => "docker",
+            ContainerRuntime::Podman => "podman",
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct AndroidConfiguration {
+    /// Run the runnables in a generated, debuggable, app rather than from `/data/local/tmp`:
+    /// its `NativeActivity` loads them as libraries, and logs their output to logcat.
+    pub run_as_app: Option<bool>,
+    /// Seconds an emulator dinghy starts may take to boot (defaults to 300).
+    pub avd_boot_timeout: Option<u64>,
+    /// Shut down the emulators dinghy started once done with them.
+    pub shutdown_avds: Option<bool>,
+    /// The NDK to build with: its path, or its version (like `25` or `25.2.9519653`) among
+    /// the NDKs installed in the SDK. Defaults to `ANDROID_NDK_HOME`, then the latest one.
+    pub ndk: Option<String>,
+    /// API level of the `auto-android-<cpu>` platforms (defaults to 21).
+    pub default_api_level: Option<u32>,
+    /// ABIs to generate auto-android platforms for, like `arm64-v8a` (defaults to all).
+    pub abis: Option<Vec<String>>,
+    /// Clear logcat before running each runnable.
+    pub clear_logcat: Option<bool>,
+    /// Save the logcat of failed runnables, and the tombstones of their crashes, in
+    /// `target/<triple>/dinghy/logs/<device>/<runnable>/`.
+    pub capture_logcat: Option<bool>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct ShardingConfiguration {
+    /// Maximum number of devices to split a run on (defaults to all matching devices).
+    pub shards: Option<usize>,
+    /// Also split the tests of each runnable, as listed by `--list`.
+    pub by_test: Option<bool>,
 }
 
 impl Configuration {
@@ -164,6 +293,29 @@ impl Configuration {
             .extend(other.ssh_devices.unwrap_or(collections::BTreeMap::new()));
         self.script_devices
             .extend(other.script_devices.unwrap_or(collections::BTreeMap::new()));
+        self.container_devices.extend(
+            other
+                .container_devices
+                .unwrap_or(collections::BTreeMap::new()),
+        );
+        if let Some(android) = other.android {
+            self.android = AndroidConfiguration {
+                run_as_app: android.run_as_app.or(self.android.run_as_app),
+                avd_boot_timeout: android.avd_boot_timeout.or(self.android.avd_boot_timeout),
+                shutdown_avds: android.shutdown_avds.or(self.android.shutdown_avds),
+                ndk: android.ndk.or(self.android.ndk.take()),
+                default_api_level: android.default_api_level.or(self.android.default_api_level),
+                abis: android.abis.or(self.android.abis.take()),
+                clear_logcat: android.clear_logcat.or(self.android.clear_logcat),
+                capture_logcat: android.capture_logcat.or(self.android.capture_logcat),
+            };
+        }
+        if let Some(sharding) = other.sharding {
+            self.sharding = ShardingConfiguration {
+                shards: sharding.shards.or(self.sharding.shards),
+                by_test: sharding.by_test.or(self.sharding.by_test),
+            };
+        }
         for (id, source) in other.test_data.unwrap_or(collections::BTreeMap::new()) {
             // TODO Remove key
             self.test_data.push(TestData {
@@ -210,7 +362,8 @@ pub fn dinghy_config<P: AsRef<path::Path>>(dir: P) -> Result<Configuration> {
     for file in files_to_try {
         if path::Path::new(&file).exists() {
             debug!("Loading configuration from {:?}", file);
-            conf.merge(&file)?;
+            conf.merge(&file)
+                .with_context(|| DinghyError::Config(file.display().to_string()))?;
         } else {
             trace!("No configuration found at {:?}", file);
         }
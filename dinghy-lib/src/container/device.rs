@@ -0,0 +1,289 @@
+use crate::compiler::CompileMode;
+use crate::config::ContainerDeviceConfiguration;
+use crate::config::ContainerRuntime;
+use crate::device::make_remote_app;
+use crate::device::run_runnable;
+use crate::device::Timeout;
+use crate::env::EnvSpec;
+use crate::host::HostPlatform;
+use crate::platform::regular_platform::RegularPlatform;
+use crate::project::Project;
+use crate::utils::path_to_str;
+use crate::Build;
+use crate::BuildBundle;
+use crate::Device;
+use crate::DeviceCapabilities;
+use crate::DeviceCompatibility;
+use crate::DeviceDescription;
+use crate::Result;
+use crate::Runnable;
+use crate::RunnableOutcome;
+use std::fmt;
+use std::process::Command;
+use std::time::Duration;
+
+/// Where the bundles are mounted in the container.
+const CONTAINER_ROOT: &str = "/dinghy";
+
+#[derive(Debug)]
+pub struct ContainerDevice {
+    pub id: String,
+    pub conf: ContainerDeviceConfiguration,
+}
+
+impl ContainerDevice {
+    fn runtime(&self) -> &'static str {
+        self.conf
+            .runtime
+            .unwrap_or(ContainerRuntime::Docker)
+            .command()
+    }
+
+    /// A name for the container running `runnable`, so that it can be killed.
+    fn container_name(&self, runnable: &Runnable) -> String {
+        format!("dinghy-{}-{}-{}", self.id, std::process::id(), runnable.id)
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '-' {
+                    c
+                } else {
+                    '-'
+                }
+            })
+            .collect()
+    }
+
+    fn run_command(
+        &self,
+        build: &Build,
+        runnable: &Runnable,
+        build_bundle: &BuildBundle,
+        args: &[&str],
+        envs: &[EnvSpec],
+    ) -> Result<Command> {
+        let container_bundle = build_bundle.replace_prefix_with(CONTAINER_ROOT)?;
+        let mut command = Command::new(self.runtime());
+        command
+            .arg("run")
+            .arg("--rm")
+            .arg("--name")
+            .arg(self.container_name(runnable))
+            .arg("--volume")
+            .arg(format!(
+                "{}:{}",
+                path_to_str(&build_bundle.root_dir)?,
+                CONTAINER_ROOT
+            ))
+            .arg("--workdir")
+            .arg(&container_bundle.bundle_dir);
+        for volume in &self.conf.volumes {
+            command.arg("--volume").arg(volume);
+        }
+        let mut env = vec![
+            EnvSpec::new("DINGHY", "1")?,
+            EnvSpec::new("RUST_BACKTRACE", "1")?,
+        ];
+        env.extend(build.runtime_env.iter().cloned());
+        for (key, value) in &self.conf.env {
+            env.push(EnvSpec::new(key.as_str(), value.as_str())?);
+        }
+        env.extend(envs.iter().cloned());
+        for env in env {
+            command.arg("--env").arg(env.to_string());
+        }
+        command.args(&self.conf.run_args);
+        command.arg(&self.conf.image);
+        // Through a shell, to extend the LD_LIBRARY_PATH of the image rather than replacing it.
+        command.arg("sh").arg("-c").arg(format!(
+            "LD_LIBRARY_PATH=\"{}:$LD_LIBRARY_PATH\" exec \"$0\" \"$@\"",
+            path_to_str(&container_bundle.lib_dir)?
+        ));
+        command.arg(&container_bundle.bundle_exe);
+        if build.build_args.compile_mode == CompileMode::Bench {
+            command.arg("--bench");
+        }
+        command.args(args);
+        Ok(command)
+    }
+}
+
+impl DeviceCompatibility for ContainerDevice {
+    fn is_compatible_with_regular_platform(&self, platform: &RegularPlatform) -> bool {
+        self.conf
+            .platform
+            .as_ref()
+            .map(|it| *it == platform.id)
+            .unwrap_or(false)
+    }
+
+    fn is_compatible_with_host_platform(&self, platform: &HostPlatform) -> bool {
+        self.conf
+            .platform
+            .as_ref()
+            .map(|it| *it == platform.id)
+            .unwrap_or(true)
+    }
+}
+
+impl Device for ContainerDevice {
+    fn capabilities(&self) -> DeviceCapabilities {
+        DeviceCapabilities {
+            debug: false,
+            lldb_proxy: false,
+            clean: true,
+            shell: false,
+            streaming_output: true,
+        }
+    }
+
+    fn clean_app(&self, _build_bundle: &BuildBundle) -> Result<()> {
+        Ok(())
+    }
+
+    fn description(&self) -> DeviceDescription {
+        DeviceDescription {
+            id: self.id.clone(),
+            name: self.conf.image.clone(),
+            kind: self.runtime().to_string(),
+            ..DeviceDescription::default()
+        }
+    }
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn name(&self) -> &str {
+        &self.conf.image
+    }
+
+    fn run_app(
+        &self,
+        project: &Project,
+        build: &Build,
+        args: &[&str],
+        envs: &[EnvSpec],
+    ) -> Result<Vec<RunnableOutcome>> {
+        let mut outcomes = vec![];
+        for runnable in &build.runnables {
+            let build_bundle = make_remote_app(project, build, runnable)?;
+            info!(
+                "Run {} on {} ({:?})",
+                runnable.id, self.id, build.build_args.compile_mode
+            );
+            let mut command = self.run_command(build, runnable, &build_bundle, args, envs)?;
+            let mut kill = Command::new(self.runtime());
+            kill.arg("kill").arg(self.container_name(runnable));
+            let timeout = build
+                .build_args
+                .timeout
+                .or(self.conf.timeout.map(Duration::from_secs));
+            let report = run_runnable(
+                &self.id,
+                runnable,
+                &mut command,
+                Timeout::new(timeout, Some(kill)),
+            )?;
+            let success = report.is_success();
+            outcomes.push(RunnableOutcome {
+                build_bundle,
+                report,
+            });
+            if !success && !build.build_args.no_fail_fast {
+                break;
+            }
+        }
+        Ok(outcomes)
+    }
+}
+
+impl fmt::Display for ContainerDevice {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", self.id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BuildArgs;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_run_command_mounts_bundle_and_sets_env() {
+        let device = ContainerDevice {
+            id: "centos 7".to_string(),
+            conf: ::toml::from_str(
+                r#"
+                    image = "centos:7"
+                    runtime = "podman"
+                    env = { RUST_LOG = "debug" }
+                "#,
+            )
+            .unwrap(),
+        };
+        let runnable = Runnable {
+            id: "app-123".to_string(),
+            ..Runnable::default()
+        };
+        let build = Build {
+            build_args: BuildArgs {
+                compile_mode: CompileMode::Test,
+                verbose: false,
+                forced_overlays: vec![],
+                no_fail_fast: false,
+                timeout: None,
+            },
+            dynamic_libraries: vec![],
+            runnables: vec![runnable.clone()],
+            runtime_env: vec![],
+            rustc_triple: "x86_64-unknown-linux-gnu".to_string(),
+            sysroot: None,
+            target_path: PathBuf::from("/ws/target/debug"),
+        };
+        let build_bundle = BuildBundle {
+            id: "app-123".to_string(),
+            bundle_dir: PathBuf::from("/ws/target/debug/dinghy/app-123"),
+            bundle_exe: PathBuf::from("/ws/target/debug/dinghy/app-123/_dinghy_app-123"),
+            lib_dir: PathBuf::from("/ws/target/debug/dinghy/overlay"),
+            root_dir: PathBuf::from("/ws/target/debug/dinghy"),
+        };
+        let envs = vec![EnvSpec::new("GREETING", "hello world").unwrap()];
+        let command = device
+            .run_command(&build, &runnable, &build_bundle, &["--nocapture"], &envs)
+            .unwrap();
+        assert_eq!(
+            format!(
+                "{:?}",
+                Command::new("podman").args(
+                    [
+                        "run",
+                        "--rm",
+                        "--name",
+                        &format!("dinghy-centos-7-{}-app-123", std::process::id()),
+                        "--volume",
+                        "/ws/target/debug/dinghy:/dinghy",
+                        "--workdir",
+                        "/dinghy/app-123",
+                        "--env",
+                        "DINGHY=1",
+                        "--env",
+                        "RUST_BACKTRACE=1",
+                        "--env",
+                        "RUST_LOG=debug",
+                        "--env",
+                        "GREETING=hello world",
+                        "centos:7",
+                        "sh",
+                        "-c",
+                        "LD_LIBRARY_PATH=\"/dinghy/overlay:$LD_LIBRARY_PATH\" exec \"$0\" \"$@\"",
+                        "/dinghy/app-123/_dinghy_app-123",
+                        "--nocapture",
+                    ]
+                    .iter()
+                )
+            ),
+            format!("{:?}", command)
+        );
+    }
+}
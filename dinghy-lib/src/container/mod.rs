@@ -0,0 +1,57 @@
+use crate::config::ContainerRuntime;
+use crate::doctor::Check;
+use crate::{Configuration, Device, Platform, PlatformManager, Result};
+use std::sync;
+
+mod device;
+
+use self::device::ContainerDevice;
+
+pub struct ContainerDeviceManager {
+    conf: sync::Arc<Configuration>,
+}
+
+impl ContainerDeviceManager {
+    pub fn probe(conf: sync::Arc<Configuration>) -> Option<ContainerDeviceManager> {
+        Some(ContainerDeviceManager { conf })
+    }
+}
+
+impl PlatformManager for ContainerDeviceManager {
+    fn devices(&self) -> Result<Vec<Box<dyn Device>>> {
+        Ok(self
+            .conf
+            .container_devices
+            .iter()
+            .map(|(k, conf)| {
+                Box::new(ContainerDevice {
+                    id: k.clone(),
+                    conf: conf.clone(),
+                }) as _
+            })
+            .collect())
+    }
+    fn platforms(&self) -> Result<Vec<Box<dyn Platform>>> {
+        Ok(vec![])
+    }
+}
+
+/// Checks the container runtimes the configuration uses are installed.
+pub fn doctor(conf: &Configuration) -> Vec<Check> {
+    conf.container_devices
+        .iter()
+        .map(|(id, conf)| {
+            let subject = format!("container device {}", id);
+            let runtime = conf.runtime.unwrap_or(ContainerRuntime::Docker).command();
+            match which::which(runtime) {
+                Ok(path) => Check::ok(subject, runtime, path.display().to_string()),
+                Err(_) => Check::error(
+                    subject,
+                    runtime,
+                    "not found",
+                    format!("Install {} and put it in the PATH", runtime),
+                ),
+            }
+        })
+        .collect()
+}
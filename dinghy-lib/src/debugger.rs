@@ -0,0 +1,289 @@
+use crate::errors::*;
+use crate::Build;
+use crate::Runnable;
+use std::io::{BufRead, BufReader, Read};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc;
+use std::time::Duration;
+use std::{env, thread};
+
+/// How long a debug server may take to start listening.
+const SERVER_START_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DebuggerKind {
+    Gdb,
+    Lldb,
+}
+
+/// A debugger of the host, `DINGHY_DEBUGGER` overrides the one dinghy picks.
+#[derive(Clone, Debug)]
+pub struct Debugger {
+    pub kind: DebuggerKind,
+    pub path: PathBuf,
+}
+
+impl Debugger {
+    fn find(candidates: &[&str]) -> Result<Debugger> {
+        let path = match env::var_os("DINGHY_DEBUGGER") {
+            Some(path) => PathBuf::from(path),
+            None => candidates
+                .iter()
+                .find_map(|candidate| which::which(candidate).ok())
+                .ok_or_else(|| {
+                    anyhow!(
+                        "No debugger found, install one of {} or set DINGHY_DEBUGGER",
+                        candidates.join(", ")
+                    )
+                })?,
+        };
+        let kind = if path.to_string_lossy().contains("lldb") {
+            DebuggerKind::Lldb
+        } else {
+            DebuggerKind::Gdb
+        };
+        Ok(Debugger { kind, path })
+    }
+
+    /// A debugger for executables of the host.
+    pub fn for_host() -> Result<Debugger> {
+        if cfg!(target_os = "macos") {
+            Debugger::find(&["lldb", "gdb"])
+        } else {
+            Debugger::find(&["gdb", "lldb"])
+        }
+    }
+
+    /// A debugger able to debug other architectures, through a debug server.
+    pub fn for_remote() -> Result<Debugger> {
+        Debugger::find(&["gdb-multiarch", "lldb", "gdb"])
+    }
+
+    /// Runs `exe` under the debugger.
+    pub fn run_command(&self, exe: &Path, args: &[&str]) -> Command {
+        let mut command = Command::new(&self.path);
+        match self.kind {
+            DebuggerKind::Gdb => command.arg("-q").arg("--args"),
+            DebuggerKind::Lldb => command.arg("--"),
+        };
+        command.arg(exe).args(args);
+        command
+    }
+
+    /// Connects the debugger to a debug server forwarded to `port` on localhost.
+    ///
+    /// `exe` is the unstripped executable the server runs, its libraries are looked for in
+    /// `sysroot` and `lib_dir`. `lldb_platform` is the lldb platform of the device, like
+    /// `remote-linux`.
+    pub fn attach_command(
+        &self,
+        exe: &Path,
+        sysroot: Option<&Path>,
+        lib_dir: &Path,
+        port: u16,
+        lldb_platform: &str,
+    ) -> Command {
+        let mut command = Command::new(&self.path);
+        match self.kind {
+            DebuggerKind::Gdb => {
+                command.arg("-q");
+                if let Some(sysroot) = sysroot {
+                    command
+                        .arg("-ex")
+                        .arg(format!("set sysroot {}", sysroot.display()));
+                }
+                command
+                    .arg("-ex")
+                    .arg(format!("set solib-search-path {}", lib_dir.display()))
+                    .arg("-ex")
+                    .arg(format!("target remote localhost:{}", port))
+                    .arg(exe);
+            }
+            DebuggerKind::Lldb => {
+                command.arg(exe).arg("-o").arg(match sysroot {
+                    Some(sysroot) => format!(
+                        "platform select --sysroot {} {}",
+                        sysroot.display(),
+                        lldb_platform
+                    ),
+                    None => format!("platform select {}", lldb_platform),
+                });
+                command
+                    .arg("-o")
+                    .arg(format!(
+                        "settings append target.exec-search-paths {}",
+                        lib_dir.display()
+                    ))
+                    .arg("-o")
+                    .arg(format!("gdb-remote localhost:{}", port));
+            }
+        }
+        command
+    }
+}
+
+/// The runnable `--debugger` debugs: the first one, as only one can be debugged at once.
+pub fn runnable_to_debug(build: &Build) -> Result<&Runnable> {
+    let runnable = build
+        .runnables
+        .first()
+        .ok_or_else(|| anyhow!("No executable compiled"))?;
+    if build.runnables.len() > 1 {
+        warn!(
+            "Several executables were built, debugging {}, select another one with --bin, --test...",
+            runnable.id
+        );
+    }
+    Ok(runnable)
+}
+
+/// A port of the host nobody listens on.
+pub fn free_port() -> Result<u16> {
+    Ok(TcpListener::bind("127.0.0.1:0")?.local_addr()?.port())
+}
+
+/// A shell command starting `server` (`gdbserver` or `lldb-server`) for `exe` with `args`,
+/// which must already be escaped. The server picks a free port of the device, and tells it.
+pub fn server_command(server: &str, exe: &str, args: &[String]) -> String {
+    let args = args.join(" ");
+    if server.ends_with("lldb-server") {
+        format!("{} gdbserver :0 -- {} {}", server, exe, args)
+    } else {
+        format!("{} :0 {} {}", server, exe, args)
+    }
+}
+
+/// A process listening, once started, to `port`.
+pub struct DebugServer {
+    pub child: Child,
+    pub port: u16,
+}
+
+/// Starts `command`, running a debug server on a device, then waits for it to listen.
+///
+/// The output of the server, and of the program it runs, is forwarded to dinghy's.
+pub fn start_server(command: &mut Command) -> Result<DebugServer> {
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    debug!("Running {:?}", command);
+    let mut child = command
+        .spawn()
+        .with_context(|| format!("Couldn't run {:?}", command))?;
+    let (listening, listening_rx) = mpsc::channel();
+    forward_output(child.stdout.take(), listening.clone(), false);
+    forward_output(child.stderr.take(), listening, true);
+    // Both senders are dropped when the server exits without listening.
+    match listening_rx.recv_timeout(SERVER_START_TIMEOUT) {
+        Ok(port) => Ok(DebugServer { child, port }),
+        Err(_) => {
+            let _ = child.kill();
+            let status = child.wait()?;
+            bail!("The debug server did not start ({})", status)
+        }
+    }
+}
+
+/// The port of "Listening on port 2345" (gdbserver) or "Listening to port 2345 for a
+/// connection from *..." (lldb-server).
+fn listening_port(line: &str) -> Option<u16> {
+    let port = line.split("Listening ").nth(1)?.split("port ").nth(1)?;
+    port.split(|c: char| !c.is_ascii_digit())
+        .next()?
+        .parse()
+        .ok()
+}
+
+fn forward_output<R: Read + Send + 'static>(
+    output: Option<R>,
+    listening: mpsc::Sender<u16>,
+    is_stderr: bool,
+) {
+    if let Some(output) = output {
+        thread::spawn(move || {
+            for line in BufReader::new(output).lines().map_while(Result::ok) {
+                if let Some(port) = listening_port(&line) {
+                    let _ = listening.send(port);
+                }
+                if is_stderr {
+                    eprintln!("{}", line);
+                } else {
+                    println!("{}", line);
+                }
+            }
+        });
+    }
+}
+
+/// Runs `attach` (the local debugger) until it exits, then stops `servers`.
+pub fn attach(mut attach: Command, servers: Vec<DebugServer>) -> Result<()> {
+    debug!("Running {:?}", attach);
+    let status = attach
+        .status()
+        .with_context(|| format!("Couldn't run {:?}", attach));
+    for mut server in servers {
+        let _ = server.child.kill();
+        let _ = server.child.wait();
+    }
+    status?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_server_and_attach_commands() {
+        let args = vec!["--nocapture".to_string(), "'a b'".to_string()];
+        assert_eq!(
+            "gdbserver :0 /tmp/dinghy/app/_dinghy_app --nocapture 'a b'",
+            server_command("gdbserver", "/tmp/dinghy/app/_dinghy_app", &args)
+        );
+        assert_eq!(
+            "/data/local/tmp/dinghy/lldb-server gdbserver :0 -- ./app --nocapture 'a b'",
+            server_command("/data/local/tmp/dinghy/lldb-server", "./app", &args)
+        );
+        assert_eq!(Some(41235), listening_port("Listening on port 41235"));
+        assert_eq!(
+            Some(41235),
+            listening_port("Listening to port 41235 for a connection from *...")
+        );
+        assert_eq!(None, listening_port("Process ./app created; pid = 1234"));
+
+        let gdb = Debugger {
+            kind: DebuggerKind::Gdb,
+            path: PathBuf::from("gdb-multiarch"),
+        };
+        let command = gdb.attach_command(
+            Path::new("/ws/target/app"),
+            Some(Path::new("/tc/sysroot")),
+            Path::new("/ws/target/dinghy/overlay"),
+            2345,
+            "remote-linux",
+        );
+        assert_eq!(
+            r#""gdb-multiarch" "-q" "-ex" "set sysroot /tc/sysroot" "-ex" "set solib-search-path /ws/target/dinghy/overlay" "-ex" "target remote localhost:2345" "/ws/target/app""#,
+            format!("{:?}", command)
+        );
+
+        let lldb = Debugger {
+            kind: DebuggerKind::Lldb,
+            path: PathBuf::from("lldb"),
+        };
+        let command = lldb.attach_command(
+            Path::new("/ws/target/app"),
+            None,
+            Path::new("/ws/target/dinghy/overlay"),
+            2345,
+            "remote-android",
+        );
+        assert_eq!(
+            r#""lldb" "/ws/target/app" "-o" "platform select remote-android" "-o" "settings append target.exec-search-paths /ws/target/dinghy/overlay" "-o" "gdb-remote localhost:2345""#,
+            format!("{:?}", command)
+        );
+    }
+}
@@ -1,12 +1,97 @@
 use crate::errors::*;
 use crate::project;
 use crate::project::Project;
+use crate::report::RunnableReport;
 use crate::utils::copy_and_sync_file;
 use crate::Build;
 use crate::BuildBundle;
 use crate::Runnable;
+use std::cell::Cell;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::io::BufReader;
 use std::path::Path;
+use std::path::PathBuf;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Whether this process made the bundle, or the overlay of dynamic libraries, of a directory.
+///
+/// A bundle only depends on the build, so when running on several devices at once it is made
+/// once and shared instead of being wiped while another device is still syncing it. Each entry
+/// has its own lock, held while making it, so that different bundles are made in parallel.
+static BUNDLES: Mutex<BTreeMap<PathBuf, Arc<Mutex<bool>>>> = Mutex::new(BTreeMap::new());
+
+/// The entry of `dir` in `BUNDLES`, added if missing.
+fn bundle_entry(dir: &Path) -> Result<Arc<Mutex<bool>>> {
+    let mut bundles = BUNDLES
+        .lock()
+        .map_err(|_| anyhow!("Couldn't lock bundle cache"))?;
+    Ok(bundles.entry(dir.to_path_buf()).or_default().clone())
+}
+
+/// What a device installed for each runnable, by runnable id.
+///
+/// Running a runnable several times on a device, like the chunks of the tests of a shard, then
+/// installs it once.
+pub struct Installed<T>(Mutex<BTreeMap<String, T>>);
+
+impl<T: Clone> Installed<T> {
+    /// What was installed for `id`, installing it with `install` the first time.
+    pub fn get_or_install<F: FnOnce() -> Result<T>>(&self, id: &str, install: F) -> Result<T> {
+        let mut installed = self
+            .0
+            .lock()
+            .map_err(|_| anyhow!("Couldn't lock installed runnables"))?;
+        if let Some(it) = installed.get(id) {
+            debug!("{} is already installed", id);
+            return Ok(it.clone());
+        }
+        let it = install()?;
+        installed.insert(id.to_string(), it.clone());
+        Ok(it)
+    }
+
+    /// Forgets what was installed for `id`, once removed from the device.
+    pub fn remove(&self, id: &str) {
+        if let Ok(mut installed) = self.0.lock() {
+            installed.remove(id);
+        }
+    }
+}
+
+impl<T> Default for Installed<T> {
+    fn default() -> Self {
+        Installed(Mutex::new(BTreeMap::new()))
+    }
+}
+
+thread_local! {
+    static OUTPUT_PREFIX: RefCell<Option<String>> = const { RefCell::new(None) };
+    static OUTPUT_QUIET: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Prefixes every line echoed by `run_and_capture` on the current thread with `[prefix]`.
+///
+/// Used when running on several devices at once, so that interleaved outputs stay readable.
+pub fn set_output_prefix(prefix: Option<String>) {
+    OUTPUT_PREFIX.with(|it| *it.borrow_mut() = prefix);
+}
+
+/// Calls `f` without echoing the stdout captured by `run_and_capture` on the current thread.
+pub fn without_output_echo<T, F: FnOnce() -> T>(f: F) -> T {
+    let previous = OUTPUT_QUIET.with(|it| it.replace(true));
+    let result = f();
+    OUTPUT_QUIET.with(|it| it.set(previous));
+    result
+}
 
 pub fn make_remote_app(
     project: &Project,
@@ -22,19 +107,6 @@ pub fn make_remote_app_with_name(
     runnable: &Runnable,
     bundle_name: Option<&str>,
 ) -> Result<BuildBundle> {
-    fn is_sysroot_library(path: &Path) -> bool {
-        path.ancestors()
-            .find(|ancestor_path| ancestor_path.ends_with("sysroot/usr/lib"))
-            .is_some()
-            && (!path
-                .file_name()
-                .unwrap()
-                .to_str()
-                .unwrap()
-                .eq_ignore_ascii_case("libc++_shared.so")
-                && !path.to_str().unwrap().contains("android"))
-    }
-
     let project = project.for_runnable(runnable)?;
     let root_dir = build.target_path.join("dinghy");
     let bundle_path = match bundle_name {
@@ -45,16 +117,30 @@ pub fn make_remote_app_with_name(
     let bundle_target_path = &bundle_path;
     let bundle_exe_path = bundle_target_path.join(format!("_dinghy_{}", &runnable.id));
 
+    let bundle = BuildBundle {
+        id: runnable.id.clone(),
+        bundle_dir: bundle_path.to_path_buf(),
+        bundle_exe: bundle_exe_path.to_path_buf(),
+        lib_dir: bundle_libs_path.to_path_buf(),
+        root_dir,
+    };
+    let entry = bundle_entry(&bundle_path)?;
+    let mut made = entry
+        .lock()
+        .map_err(|_| anyhow!("Couldn't lock bundle {:?}", bundle_path))?;
+    // Named bundles (iOS apps) are altered by the caller after being made, so they can't be reused.
+    if *made && bundle_name.is_none() {
+        debug!("Reusing bundle {:?}", bundle_path);
+        return Ok(bundle);
+    }
+
     debug!("Removing previous bundle {:?}", bundle_path);
     let _ = fs::remove_dir_all(&bundle_path);
-    let _ = fs::remove_dir_all(&bundle_libs_path);
     let _ = fs::remove_dir_all(&bundle_target_path);
 
     debug!("Making bundle {:?}", bundle_path);
     fs::create_dir_all(&bundle_path)
         .with_context(|| format!("Couldn't create {}", &bundle_path.display()))?;
-    fs::create_dir_all(&bundle_libs_path)
-        .with_context(|| format!("Couldn't create {}", &bundle_libs_path.display()))?;
     fs::create_dir_all(&bundle_target_path)
         .with_context(|| format!("Couldn't create {}", &bundle_target_path.display()))?;
 
@@ -70,9 +156,56 @@ pub fn make_remote_app_with_name(
         )
     })?;
 
+    make_overlay(build, &bundle_libs_path)?;
+
+    debug!(
+        "Copying src {} to bundle {}",
+        runnable.source.display(),
+        bundle_path.display()
+    );
+    project::rec_copy_excl(
+        &runnable.source,
+        &bundle_path,
+        false,
+        &[runnable.source.join("target")],
+    )?;
+    debug!("Copying test_data to bundle {}", bundle_path.display());
+    project.copy_test_data(&bundle_path)?;
+
+    *made = true;
+    Ok(bundle)
+}
+
+/// Copies the dynamic libraries of `build` to `overlay`, shared by all its bundles, unless this
+/// process already did.
+fn make_overlay(build: &Build, overlay: &Path) -> Result<()> {
+    fn is_sysroot_library(path: &Path) -> bool {
+        path.ancestors()
+            .find(|ancestor_path| ancestor_path.ends_with("sysroot/usr/lib"))
+            .is_some()
+            && (!path
+                .file_name()
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .eq_ignore_ascii_case("libc++_shared.so")
+                && !path.to_str().unwrap().contains("android"))
+    }
+
+    let entry = bundle_entry(overlay)?;
+    let mut made = entry
+        .lock()
+        .map_err(|_| anyhow!("Couldn't lock overlay {:?}", overlay))?;
+    if *made {
+        return Ok(());
+    }
+    let _ = fs::remove_dir_all(overlay);
+    fs::create_dir_all(overlay)
+        .with_context(|| format!("Couldn't create {}", overlay.display()))?;
+
     debug!("Copying dynamic libs to bundle");
     for src_lib_path in &build.dynamic_libraries {
-        let target_lib_path = bundle_libs_path.join(
+        let target_lib_path = overlay.join(
             src_lib_path
                 .file_name()
                 .ok_or_else(|| anyhow!("Invalid file name {:?}", src_lib_path.file_name()))?,
@@ -98,25 +231,328 @@ pub fn make_remote_app_with_name(
         }
     }
 
-    debug!(
-        "Copying src {} to bundle {}",
-        runnable.source.display(),
-        bundle_path.display()
-    );
-    project::rec_copy_excl(
-        &runnable.source,
-        &bundle_path,
-        false,
-        &[runnable.source.join("target")],
-    )?;
-    debug!("Copying test_data to bundle {}", bundle_path.display());
-    project.copy_test_data(&bundle_path)?;
+    *made = true;
+    Ok(())
+}
 
-    Ok(BuildBundle {
-        id: runnable.id.clone(),
-        bundle_dir: bundle_path.to_path_buf(),
-        bundle_exe: bundle_exe_path.to_path_buf(),
-        lib_dir: bundle_libs_path.to_path_buf(),
-        root_dir,
+/// How long stopping a timed out process on a device may take.
+const KILL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long the output of a timed out command is still read once it is killed: processes it
+/// started in the background may keep it open.
+const OUTPUT_GRACE: Duration = Duration::from_secs(1);
+
+/// Bounds how long a command may run.
+pub struct Timeout {
+    pub duration: Duration,
+    /// Stops what the command started on the device, when killing the command is not enough.
+    pub kill: Option<Command>,
+}
+
+impl Timeout {
+    pub fn new(duration: Option<Duration>, kill: Option<Command>) -> Option<Timeout> {
+        duration.map(|duration| Timeout { duration, kill })
+    }
+}
+
+/// Runs `kill`, stopping a process on a device, for at most `KILL_TIMEOUT`: a wedged device
+/// may never answer.
+fn kill_on_device(kill: &mut Command) {
+    debug!("Running {:?}", kill);
+    let mut kill = match kill
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+    {
+        Ok(kill) => kill,
+        Err(e) => {
+            warn!("Couldn't kill the process on the device ({})", e);
+            return;
+        }
+    };
+    let deadline = Instant::now() + KILL_TIMEOUT;
+    loop {
+        match kill.try_wait() {
+            Ok(Some(status)) if status.success() => return,
+            Ok(None) if Instant::now() < deadline => thread::sleep(Duration::from_millis(50)),
+            Ok(None) => {
+                let _ = kill.kill();
+                let _ = kill.wait();
+                warn!(
+                    "Gave up killing the process on the device after {}s",
+                    KILL_TIMEOUT.as_secs()
+                );
+                return;
+            }
+            result => {
+                warn!("Couldn't kill the process on the device ({:?})", result);
+                return;
+            }
+        }
+    }
+}
+
+pub struct CapturedOutput {
+    pub status: ExitStatus,
+    pub output: String,
+    pub timed_out: bool,
+}
+
+/// Runs `command`, echoing its stdout line by line while keeping a copy of it.
+///
+/// Unless an output prefix is set, stderr and stdin are left untouched so that interactive
+/// commands (like `ssh -t`) still work.
+pub fn run_and_capture(command: &mut Command, timeout: Option<Timeout>) -> Result<CapturedOutput> {
+    run_and_capture_with_input(command, None, timeout)
+}
+
+/// Same as `run_and_capture`, writing `input` to the stdin of the command.
+pub fn run_and_capture_with_input(
+    command: &mut Command,
+    input: Option<Vec<u8>>,
+    timeout: Option<Timeout>,
+) -> Result<CapturedOutput> {
+    let prefix = OUTPUT_PREFIX.with(|it| it.borrow().clone());
+    let quiet = OUTPUT_QUIET.with(|it| it.get());
+    command.stdout(Stdio::piped());
+    if prefix.is_some() {
+        command.stdin(Stdio::null()).stderr(Stdio::piped());
+    }
+    if input.is_some() {
+        command.stdin(Stdio::piped());
+    }
+    debug!("Running {:?}", command);
+    let mut child = command
+        .spawn()
+        .with_context(|| format!("Couldn't run {:?}", command))?;
+
+    if let (Some(mut stdin), Some(input)) = (child.stdin.take(), input) {
+        // From another thread, so that a command not reading its input can't block us.
+        thread::spawn(move || {
+            if let Err(e) = stdin.write_all(&input) {
+                debug!("Couldn't write the input of the command ({})", e);
+            }
+        });
+    }
+
+    let captured = Arc::new(Mutex::new(String::new()));
+    let stderr_echo = child.stderr.take().map(|stderr| {
+        let prefix = prefix.clone();
+        thread::spawn(move || echo_lines(stderr, io::stderr(), prefix.as_deref(), None))
+    });
+    let stdout_echo = child.stdout.take().map(|stdout| {
+        let captured = captured.clone();
+        thread::spawn(move || {
+            if quiet {
+                echo_lines(stdout, io::sink(), None, Some(&captured))
+            } else {
+                echo_lines(stdout, io::stdout(), prefix.as_deref(), Some(&captured))
+            }
+        })
+    });
+
+    let mut timed_out = false;
+    let status = match timeout {
+        None => child.wait()?,
+        Some(mut timeout) => {
+            let deadline = Instant::now() + timeout.duration;
+            loop {
+                if let Some(status) = child.try_wait()? {
+                    break status;
+                }
+                if !timed_out && Instant::now() >= deadline {
+                    warn!(
+                        "Timed out after {}s, killing it",
+                        timeout.duration.as_secs()
+                    );
+                    timed_out = true;
+                    // The child may have exited in between, in which case there is nothing to kill.
+                    let _ = child.kill();
+                    if let Some(kill) = timeout.kill.as_mut() {
+                        kill_on_device(kill);
+                    }
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+        }
+    };
+
+    let echo_deadline = Instant::now() + OUTPUT_GRACE;
+    for (name, echo) in [("stdout", stdout_echo), ("stderr", stderr_echo)] {
+        let echo = match echo {
+            Some(echo) => echo,
+            None => continue,
+        };
+        if timed_out {
+            while !echo.is_finished() && Instant::now() < echo_deadline {
+                thread::sleep(Duration::from_millis(50));
+            }
+            if !echo.is_finished() {
+                warn!(
+                    "Stopped reading the {} of {:?}, kept open by processes it started",
+                    name, command
+                );
+                continue;
+            }
+        }
+        echo.join()
+            .map_err(|_| anyhow!("Panic while forwarding {} of {:?}", name, command))??;
+    }
+    let output = captured
+        .lock()
+        .map_err(|_| anyhow!("Couldn't read the output of {:?}", command))?
+        .clone();
+    Ok(CapturedOutput {
+        status,
+        output,
+        timed_out,
     })
 }
+
+fn echo_lines<R: Read, W: Write>(
+    from: R,
+    mut to: W,
+    prefix: Option<&str>,
+    captured: Option<&Mutex<String>>,
+) -> Result<()> {
+    let mut reader = BufReader::new(from);
+    let mut line = vec![];
+    while reader.read_until(b'\n', &mut line)? > 0 {
+        if let Some(captured) = captured {
+            captured
+                .lock()
+                .map_err(|_| anyhow!("Couldn't lock captured output"))?
+                .push_str(&String::from_utf8_lossy(&line));
+        }
+        write_line(&mut to, prefix, &line)?;
+        line.clear();
+    }
+    Ok(())
+}
+
+fn write_line<W: Write>(to: &mut W, prefix: Option<&str>, line: &[u8]) -> Result<()> {
+    if let Some(prefix) = prefix {
+        write!(to, "[{}] ", prefix)?;
+    }
+    to.write_all(line)?;
+    to.flush()?;
+    Ok(())
+}
+
+/// Echoes output received in chunks line by line, the way `run_and_capture` does for commands.
+///
+/// Stdout is captured, stderr is only echoed.
+#[cfg(feature = "native-ssh")]
+pub(crate) struct OutputEcho {
+    prefix: Option<String>,
+    quiet: bool,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    captured: String,
+}
+
+#[cfg(feature = "native-ssh")]
+impl OutputEcho {
+    pub(crate) fn new() -> OutputEcho {
+        OutputEcho {
+            prefix: OUTPUT_PREFIX.with(|it| it.borrow().clone()),
+            quiet: OUTPUT_QUIET.with(|it| it.get()),
+            stdout: vec![],
+            stderr: vec![],
+            captured: String::new(),
+        }
+    }
+
+    pub(crate) fn stdout(&mut self, data: &[u8]) -> Result<()> {
+        self.stdout.extend_from_slice(data);
+        while let Some(end) = self.stdout.iter().position(|&byte| byte == b'\n') {
+            let line = self.stdout.drain(..=end).collect::<Vec<_>>();
+            self.stdout_line(&line)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn stderr(&mut self, data: &[u8]) -> Result<()> {
+        self.stderr.extend_from_slice(data);
+        while let Some(end) = self.stderr.iter().position(|&byte| byte == b'\n') {
+            let line = self.stderr.drain(..=end).collect::<Vec<_>>();
+            write_line(&mut io::stderr(), self.prefix.as_deref(), &line)?;
+        }
+        Ok(())
+    }
+
+    /// Echoes what is left of unterminated lines and returns the captured stdout.
+    pub(crate) fn finish(mut self) -> Result<String> {
+        let stdout = std::mem::take(&mut self.stdout);
+        if !stdout.is_empty() {
+            self.stdout_line(&stdout)?;
+        }
+        if !self.stderr.is_empty() {
+            write_line(&mut io::stderr(), self.prefix.as_deref(), &self.stderr)?;
+        }
+        Ok(self.captured)
+    }
+
+    fn stdout_line(&mut self, line: &[u8]) -> Result<()> {
+        self.captured.push_str(&String::from_utf8_lossy(line));
+        if !self.quiet {
+            write_line(&mut io::stdout(), self.prefix.as_deref(), line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs `command` for `runnable` and builds its report from the captured output.
+pub fn run_runnable(
+    device_id: &str,
+    runnable: &Runnable,
+    command: &mut Command,
+    timeout: Option<Timeout>,
+) -> Result<RunnableReport> {
+    run_runnable_with_input(device_id, runnable, command, None, timeout)
+}
+
+/// Same as `run_runnable`, writing `input` to the stdin of the command.
+pub fn run_runnable_with_input(
+    device_id: &str,
+    runnable: &Runnable,
+    command: &mut Command,
+    input: Option<Vec<u8>>,
+    timeout: Option<Timeout>,
+) -> Result<RunnableReport> {
+    let start = Instant::now();
+    let captured = run_and_capture_with_input(command, input, timeout)?;
+    let report = RunnableReport::new(
+        &runnable.id,
+        device_id,
+        captured.status.code(),
+        start.elapsed(),
+        &captured.output,
+    );
+    Ok(if captured.timed_out {
+        report.timed_out()
+    } else {
+        report
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timeout_does_not_wait_for_background_processes() {
+        let start = Instant::now();
+        let captured = run_and_capture(
+            Command::new("sh")
+                .arg("-c")
+                .arg("echo started; sleep 100 & wait"),
+            Timeout::new(Some(Duration::from_secs(1)), None),
+        )
+        .unwrap();
+        assert!(captured.timed_out);
+        assert_eq!("started\n", captured.output);
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+}
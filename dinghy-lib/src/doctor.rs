@@ -0,0 +1,137 @@
+use crate::Configuration;
+use std::env;
+use std::path::PathBuf;
+
+/// Outcome of a check of the environment dinghy runs in.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Ok,
+    /// Something optional is missing, like the Android tools when not targeting Android.
+    Warning,
+    Error,
+}
+
+/// A check made by `cargo dinghy doctor`, as printed by `doctor --format json`.
+#[derive(Clone, Debug, Serialize)]
+pub struct Check {
+    /// What is checked, like `android` or `ssh device raspberry`.
+    pub subject: String,
+    pub name: String,
+    pub status: CheckStatus,
+    /// What was found, or why it was not.
+    pub detail: String,
+    /// How to fix the problem, for failed checks.
+    pub hint: Option<String>,
+}
+
+impl Check {
+    pub fn ok<S: Into<String>, N: Into<String>, D: Into<String>>(
+        subject: S,
+        name: N,
+        detail: D,
+    ) -> Check {
+        Check {
+            subject: subject.into(),
+            name: name.into(),
+            status: CheckStatus::Ok,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    pub fn warning<S: Into<String>, N: Into<String>, D: Into<String>, H: Into<String>>(
+        subject: S,
+        name: N,
+        detail: D,
+        hint: H,
+    ) -> Check {
+        Check {
+            status: CheckStatus::Warning,
+            hint: Some(hint.into()),
+            ..Check::ok(subject, name, detail)
+        }
+    }
+
+    pub fn error<S: Into<String>, N: Into<String>, D: Into<String>, H: Into<String>>(
+        subject: S,
+        name: N,
+        detail: D,
+        hint: H,
+    ) -> Check {
+        Check {
+            status: CheckStatus::Error,
+            hint: Some(hint.into()),
+            ..Check::ok(subject, name, detail)
+        }
+    }
+}
+
+/// What the checks look up on the host besides files, replaced in tests.
+pub trait Lookup {
+    /// The value of the environment variable `name`.
+    fn var(&self, name: &str) -> Option<String>;
+
+    /// Where `executable` is in the `PATH`.
+    fn which(&self, executable: &str) -> Option<PathBuf>;
+}
+
+/// The environment and the `PATH` of dinghy.
+pub struct SystemLookup;
+
+impl Lookup for SystemLookup {
+    fn var(&self, name: &str) -> Option<String> {
+        env::var(name).ok()
+    }
+
+    fn which(&self, executable: &str) -> Option<PathBuf> {
+        ::which::which(executable).ok()
+    }
+}
+
+/// Checks what every platform manager and configured platform or device needs, including the
+/// ones `Dinghy::probe` would silently leave out.
+pub fn diagnose(conf: &Configuration) -> Vec<Check> {
+    let mut checks = vec![];
+    checks.extend(crate::platform::regular_platform::doctor(
+        conf,
+        &SystemLookup,
+    ));
+    checks.extend(crate::android::doctor(conf, &SystemLookup));
+    checks.extend(crate::ssh::doctor(conf));
+    checks.extend(crate::script::doctor(conf));
+    checks.extend(crate::container::doctor(conf));
+    checks.extend(crate::qemu::doctor());
+    checks
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    /// A `Lookup` seeing only the variables and executables it is given.
+    #[derive(Default)]
+    pub(crate) struct FakeLookup {
+        pub vars: BTreeMap<String, String>,
+        pub executables: BTreeMap<String, PathBuf>,
+    }
+
+    impl Lookup for FakeLookup {
+        fn var(&self, name: &str) -> Option<String> {
+            self.vars.get(name).cloned()
+        }
+
+        fn which(&self, executable: &str) -> Option<PathBuf> {
+            self.executables.get(executable).cloned()
+        }
+    }
+
+    /// The status of the check `name` of `subject` among `checks`.
+    pub(crate) fn status_of(checks: &[Check], subject: &str, name: &str) -> Option<CheckStatus> {
+        checks
+            .iter()
+            .find(|check| check.subject == subject && check.name == name)
+            .map(|check| check.status)
+    }
+}
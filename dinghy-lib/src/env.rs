@@ -0,0 +1,102 @@
+use crate::errors::*;
+use std::fmt;
+use std::str::FromStr;
+
+/// An environment variable to set when running on a device, as in `--env KEY=VALUE`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EnvSpec {
+    pub key: String,
+    pub value: String,
+}
+
+impl EnvSpec {
+    pub fn new<K: Into<String>, V: Into<String>>(key: K, value: V) -> Result<EnvSpec> {
+        let key = key.into();
+        let valid = key
+            .chars()
+            .next()
+            .map(|first| first.is_ascii_alphabetic() || first == '_')
+            .unwrap_or(false)
+            && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            bail!(
+                "Invalid environment variable name '{}' (expected letters, digits and underscores)",
+                key
+            )
+        }
+        Ok(EnvSpec {
+            key,
+            value: value.into(),
+        })
+    }
+
+    /// A `KEY=VALUE` assignment for a POSIX shell, the value being escaped.
+    pub fn to_shell(&self) -> String {
+        format!(
+            "{}={}",
+            self.key,
+            ::shell_escape::unix::escape(self.value.as_str().into())
+        )
+    }
+}
+
+impl FromStr for EnvSpec {
+    type Err = Error;
+
+    fn from_str(spec: &str) -> Result<EnvSpec> {
+        let mut parts = spec.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some(key), Some(value)) => EnvSpec::new(key, value)
+                .with_context(|| format!("Invalid environment variable '{}'", spec)),
+            _ => bail!(
+                "Invalid environment variable '{}' (expected KEY=VALUE)",
+                spec
+            ),
+        }
+    }
+}
+
+impl fmt::Display for EnvSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", self.key, self.value)
+    }
+}
+
+/// Parses `KEY=VALUE` specs, failing on the first malformed one.
+pub fn parse_envs<S: AsRef<str>>(specs: &[S]) -> Result<Vec<EnvSpec>> {
+    specs.iter().map(|spec| spec.as_ref().parse()).collect()
+}
+
+/// `KEY=VALUE` assignments setting `envs` at the beginning of a POSIX shell command.
+pub fn to_shell_assignments(envs: &[EnvSpec]) -> String {
+    envs.iter()
+        .map(|env| env.to_shell())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_envs() {
+        let envs = parse_envs(&["RUST_LOG=debug", "EMPTY=", "URL=http://h/?a=b"]).unwrap();
+        assert_eq!(EnvSpec::new("RUST_LOG", "debug").unwrap(), envs[0]);
+        assert_eq!(EnvSpec::new("EMPTY", "").unwrap(), envs[1]);
+        assert_eq!(EnvSpec::new("URL", "http://h/?a=b").unwrap(), envs[2]);
+
+        for malformed in &["RUST_LOG", "=value", "1KEY=value", "MY-KEY=value", "A B=c"] {
+            assert!(malformed.parse::<EnvSpec>().is_err(), "{}", malformed);
+        }
+    }
+
+    #[test]
+    fn test_to_shell_assignments_escapes_values() {
+        let envs = parse_envs(&["A=plain", "B=with space", "C=it's $HOME `id`"]).unwrap();
+        assert_eq!(
+            r#"A=plain B='with space' C='it'\''s $HOME `id`'"#,
+            to_shell_assignments(&envs)
+        );
+    }
+}
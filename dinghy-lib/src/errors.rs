@@ -1,35 +1,97 @@
 pub use anyhow::*;
 
-/*
-
-error_chain! {
-    foreign_links {
-        Io(::std::io::Error);
-        StringFromUtf8(::std::string::FromUtf8Error);
-        ParseInt(::std::num::ParseIntError);
-        PathStripPrefix(::std::path::StripPrefixError);
-        Plist(::plist::Error);
-        Regex(::regex::Error);
-        Json(::json::Error);
-        Ignore(::ignore::Error);
-        Toml(::toml::de::Error);
-    }
+use std::fmt;
+
+/// The failures callers of dinghy may want to tell apart, `cargo dinghy` maps each of them
+/// to its own exit code.
+///
+/// They are usually found as the context, or the source, of an `anyhow::Error`: use
+/// `DinghyError::of` to find them.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DinghyError {
+    /// Cargo could not build the runnables.
+    BuildFailed,
+    /// Some runnables failed.
+    TestFailed(String),
+    /// No device matches the `-d` hint, if any.
+    DeviceNotFound(Option<String>),
+    /// No platform matches the name, or the device hint, it is given.
+    PlatformNotFound(String),
+    /// All the packages to build are excluded on the platform (see `[package.metadata.dinghy]`).
+    PackagesFilteredOut {
+        packages: Vec<String>,
+        platform: String,
+    },
+    /// The device could not be reached.
+    DeviceUnreachable(String),
+    /// A runnable did not finish in time.
+    Timeout { runnable: String, device: String },
+    /// An operation a device can not perform, as told by its `DeviceCapabilities`.
+    Unsupported { device: String, operation: String },
+    /// A configuration file is invalid.
+    Config(String),
+}
 
-    errors {
-        PackagesCannotBeCompiledForPlatform(packages: Vec<String>) {
-            description("Cannot compile selected packages for the selected platform")
-            display("{:?} cannot be compiled for the selected platform (see project's [package.metadata.dinghy] in Cargo.toml)", packages)
+impl DinghyError {
+    pub fn unsupported<D: Into<String>, O: Into<String>>(device: D, operation: O) -> DinghyError {
+        DinghyError::Unsupported {
+            device: device.into(),
+            operation: operation.into(),
         }
-        Cargo(err: String) {
-            description("A cargo error")
-            display("{:?}", err)
+    }
+
+    /// The `DinghyError` `error` was built from, or with as context.
+    pub fn of(error: &Error) -> Option<&DinghyError> {
+        error.downcast_ref::<DinghyError>()
+    }
+}
+
+impl fmt::Display for DinghyError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DinghyError::BuildFailed => write!(fmt, "Build failed"),
+            DinghyError::TestFailed(summary) => write!(fmt, "{}", summary),
+            DinghyError::DeviceNotFound(None) => write!(fmt, "No device found"),
+            DinghyError::DeviceNotFound(Some(hint)) => {
+                write!(fmt, "No devices found for name hint `{}'", hint)
+            }
+            DinghyError::PlatformNotFound(what) => write!(fmt, "No platform found for {}", what),
+            DinghyError::PackagesFilteredOut { packages, platform } => write!(
+                fmt,
+                "packages {:?} are filtered out on platform {:?}",
+                packages, platform
+            ),
+            DinghyError::DeviceUnreachable(device) => write!(fmt, "{} is unreachable", device),
+            DinghyError::Timeout { runnable, device } => {
+                write!(fmt, "Timed out 🕑 ({} on {})", runnable, device)
+            }
+            DinghyError::Unsupported { device, operation } => {
+                write!(fmt, "{} does not support {}", device, operation)
+            }
+            DinghyError::Config(what) => write!(fmt, "Invalid configuration ({})", what),
         }
     }
 }
 
-impl From<anyhow::Error> for Error {
-    fn from(err: failure::Error) -> Error {
-        Error::from_kind(ErrorKind::Cargo(err.to_string()))
+impl std::error::Error for DinghyError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dinghy_error_is_found_through_context() {
+        let error: Error = anyhow!("connection refused")
+            .context(DinghyError::DeviceUnreachable("raspi".to_string()))
+            .context("Installing on raspi");
+        assert_eq!(
+            Some(&DinghyError::DeviceUnreachable("raspi".to_string())),
+            DinghyError::of(&error)
+        );
+        let error: Error = Err::<(), _>(DinghyError::BuildFailed)
+            .context("Building")
+            .unwrap_err();
+        assert_eq!(Some(&DinghyError::BuildFailed), DinghyError::of(&error));
+        assert_eq!(None, DinghyError::of(&anyhow!("something else")));
     }
 }
-*/
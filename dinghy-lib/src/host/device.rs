@@ -1,29 +1,32 @@
-use crate::compiler::Compiler;
+use crate::compiler::CompileMode;
+use crate::debugger::runnable_to_debug;
+use crate::debugger::Debugger;
+use crate::device::run_runnable;
+use crate::device::Timeout;
+use crate::env::EnvSpec;
+use crate::errors::*;
 use crate::project::Project;
 use crate::Build;
 use crate::BuildBundle;
 use crate::Device;
+use crate::DeviceCapabilities;
 use crate::DeviceCompatibility;
-use crate::Result;
-use dinghy_build::build_env::set_env;
-use itertools::Itertools;
+use crate::DeviceDescription;
+use crate::Platform;
+use crate::RunnableOutcome;
 use std::fmt;
 use std::fmt::Formatter;
 use std::fmt::{Debug, Display};
-use std::sync::Arc;
+use std::process::Command;
 use crate::host::HostPlatform;
 
 pub struct HostDevice {
     platform: HostPlatform,
-    compiler: Arc<Compiler>,
 }
 
 impl HostDevice {
-    pub fn new(platform: HostPlatform, compiler: &Arc<Compiler>) -> Self {
-        HostDevice {
-            platform,
-            compiler: compiler.clone(),
-        }
+    pub fn new(platform: HostPlatform) -> Self {
+        HostDevice { platform }
     }
 
     fn install_all_apps(&self, project: &Project, build: &Build) -> Result<Vec<BuildBundle>> {
@@ -50,6 +53,16 @@ impl HostDevice {
 }
 
 impl Device for HostDevice {
+    fn capabilities(&self) -> DeviceCapabilities {
+        DeviceCapabilities {
+            debug: true,
+            lldb_proxy: false,
+            clean: true,
+            shell: false,
+            streaming_output: true,
+        }
+    }
+
     fn clean_app(&self, _build_bundle: &BuildBundle) -> Result<()> {
         debug!("No cleanup performed as it is not required for host platform");
         Ok(())
@@ -57,12 +70,50 @@ impl Device for HostDevice {
 
     fn debug_app(
         &self,
-        _project: &Project,
-        _build: &Build,
-        _args: &[&str],
-        _envs: &[&str],
+        project: &Project,
+        build: &Build,
+        args: &[&str],
+        envs: &[EnvSpec],
     ) -> Result<BuildBundle> {
-        unimplemented!()
+        let runnable = runnable_to_debug(build)?;
+        let build_bundle = self
+            .install_all_apps(project, build)?
+            .into_iter()
+            .find(|build_bundle| build_bundle.id == runnable.id)
+            .ok_or_else(|| anyhow!("No bundle for {}", runnable.id))?;
+
+        let mut library_path = vec![build.target_path.clone(), build.target_path.join("deps")];
+        library_path.extend(::cargo::util::dylib_path());
+        let library_path = ::cargo::util::join_paths(&library_path, "library path")?;
+
+        let mut command = Debugger::for_host()?.run_command(&runnable.exe, args);
+        command
+            .current_dir(&runnable.source)
+            .env(::cargo::util::dylib_path_envvar(), &library_path)
+            .env("CARGO_MANIFEST_DIR", &runnable.source)
+            .envs(
+                build
+                    .runtime_env
+                    .iter()
+                    .chain(envs)
+                    .map(|env| (&env.key, &env.value)),
+            );
+        info!("Debug {} on {}", runnable.id, self.id());
+        debug!("Running {:?}", command);
+        command
+            .status()
+            .with_context(|| format!("Couldn't run {:?}", command))?;
+        Ok(build_bundle)
+    }
+
+    fn description(&self) -> DeviceDescription {
+        DeviceDescription {
+            id: self.id().to_string(),
+            name: self.name().to_string(),
+            kind: "host".to_string(),
+            supported_targets: vec![self.platform.rustc_triple().to_string()],
+            ..DeviceDescription::default()
+        }
     }
 
     fn id(&self) -> &str {
@@ -78,23 +129,59 @@ impl Device for HostDevice {
         project: &Project,
         build: &Build,
         args: &[&str],
-        envs: &[&str],
-    ) -> Result<Vec<BuildBundle>> {
-        for (env_key, env_value) in envs.iter().tuples() {
-            set_env(env_key, env_value);
-        }
+        envs: &[EnvSpec],
+    ) -> Result<Vec<RunnableOutcome>> {
         let build_bundles = self.install_all_apps(project, build)?;
         let args = args
             .iter()
             .map(|arg| Ok(shellexpand::full(arg)?.to_string()))
             .collect::<Result<Vec<_>>>()?;
         debug!("Arguments expanded to: {:?}", args);
-        self.compiler.run(&self.platform, &build.build_args, &*args)?;
-        Ok(build_bundles)
-    }
 
-    fn start_remote_lldb(&self) -> Result<String> {
-        unimplemented!()
+        let mut library_path = vec![build.target_path.clone(), build.target_path.join("deps")];
+        library_path.extend(::cargo::util::dylib_path());
+        let library_path = ::cargo::util::join_paths(&library_path, "library path")?;
+
+        let mut outcomes = vec![];
+        for (runnable, build_bundle) in build.runnables.iter().zip(build_bundles) {
+            info!(
+                "Run {} on {} ({:?})",
+                runnable.id,
+                self.id(),
+                build.build_args.compile_mode
+            );
+            let mut command = Command::new(&runnable.exe);
+            command
+                .current_dir(&runnable.source)
+                .env(::cargo::util::dylib_path_envvar(), &library_path)
+                .env("CARGO_MANIFEST_DIR", &runnable.source)
+                .envs(
+                    build
+                        .runtime_env
+                        .iter()
+                        .chain(envs)
+                        .map(|env| (&env.key, &env.value)),
+                );
+            if build.build_args.compile_mode == CompileMode::Bench {
+                command.arg("--bench");
+            }
+            command.args(&args);
+            let report = run_runnable(
+                self.id(),
+                runnable,
+                &mut command,
+                Timeout::new(build.build_args.timeout, None),
+            )?;
+            let success = report.is_success();
+            outcomes.push(RunnableOutcome {
+                build_bundle,
+                report,
+            });
+            if !success && !build.build_args.no_fail_fast {
+                break;
+            }
+        }
+        Ok(outcomes)
     }
 }
 
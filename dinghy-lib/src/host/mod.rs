@@ -34,7 +34,7 @@ impl HostManager {
 
 impl PlatformManager for HostManager {
     fn devices(&self) -> Result<Vec<Box<dyn Device>>> {
-        Ok(vec![Box::new(HostDevice::new(self.platform()?, &self.compiler))])
+        Ok(vec![Box::new(HostDevice::new(self.platform()?))])
     }
 
     fn platforms(&self) -> Result<Vec<Box<dyn Platform>>> {
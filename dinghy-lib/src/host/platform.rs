@@ -7,6 +7,7 @@ use crate::Build;
 use crate::BuildArgs;
 use crate::Device;
 use crate::Platform;
+use crate::PlatformDescription;
 use crate::Result;
 use cargo::core::compiler::CompileKind;
 use dinghy_build::build_env::set_all_env;
@@ -47,7 +48,20 @@ impl Platform for HostPlatform {
 
         Overlayer::overlay(&self.configuration, self, project, "/")?;
 
-        self.compiler.build(self, build_args)
+        let mut build = self.compiler.build(self, build_args)?;
+        build.runtime_env = self.configuration.runtime_env()?;
+        Ok(build)
+    }
+
+    fn description(&self) -> Result<PlatformDescription> {
+        Ok(PlatformDescription {
+            id: self.id(),
+            rustc_triple: self.rustc_triple().to_string(),
+            toolchain_root: None,
+            sysroot: self.sysroot()?,
+            is_host: true,
+            error: None,
+        })
     }
 
     fn id(&self) -> String {
@@ -1,14 +1,19 @@
 use super::mobiledevice_sys::*;
 use super::xcode;
 use crate::device::make_remote_app_with_name;
+use crate::env::EnvSpec;
 use crate::errors::*;
 use crate::ios::IosPlatform;
 use crate::project::Project;
+use crate::report::RunnableReport;
 use crate::Build;
 use crate::BuildBundle;
 use crate::Device;
+use crate::DeviceCapabilities;
 use crate::DeviceCompatibility;
+use crate::DeviceDescription;
 use crate::Runnable;
+use crate::RunnableOutcome;
 use core_foundation::array::CFArray;
 use core_foundation::base::{CFType, CFTypeRef, ItemRef, TCFType};
 use core_foundation::boolean::CFBoolean;
@@ -28,14 +33,20 @@ use std::path::Path;
 use std::path::PathBuf;
 use std::process;
 use std::ptr;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
 use std::thread;
 use std::time::Duration;
+use std::time::Instant;
 
 #[derive(Clone, Debug)]
 pub struct IosDevice {
     pub id: String,
     pub name: String,
     ptr: *const am_device,
+    /// Held while using `ptr`, which MobileDevice does not let threads share.
+    lock: Arc<Mutex<()>>,
     arch_cpu: &'static str,
     rustc_triple: String,
 }
@@ -47,7 +58,10 @@ pub struct IosSimDevice {
     pub os: String,
 }
 
+// SAFETY: the `am_device` handle is not tied to the thread that got it, and past
+// `IosDevice::new` it is only used with `lock` held, so that no two threads use it at once.
 unsafe impl Send for IosDevice {}
+unsafe impl Sync for IosDevice {}
 
 impl IosDevice {
     pub fn new(ptr: *const am_device) -> Result<IosDevice> {
@@ -67,6 +81,7 @@ impl IosDevice {
         };
         Ok(IosDevice {
             ptr: ptr,
+            lock: Arc::new(Mutex::new(())),
             name: name,
             id: id,
             arch_cpu: cpu.into(),
@@ -74,6 +89,13 @@ impl IosDevice {
         })
     }
 
+    /// To hold while using `ptr`.
+    fn session_lock(&self) -> Result<MutexGuard<()>> {
+        self.lock
+            .lock()
+            .map_err(|_| anyhow!("iOS device {} poisoned", self.id))
+    }
+
     fn make_app(
         &self,
         project: &Project,
@@ -102,14 +124,21 @@ impl IosDevice {
         runnable: &Runnable,
     ) -> Result<BuildBundle> {
         let build_bundle = self.make_app(project, build, runnable)?;
+        let _lock = self.session_lock()?;
         install_app(self.ptr, &build_bundle.bundle_dir)?;
         Ok(build_bundle)
     }
 }
 
 impl Device for IosDevice {
-    fn clean_app(&self, _build_bundle: &BuildBundle) -> Result<()> {
-        unimplemented!()
+    fn capabilities(&self) -> DeviceCapabilities {
+        DeviceCapabilities {
+            debug: true,
+            lldb_proxy: true,
+            clean: false,
+            shell: false,
+            streaming_output: true,
+        }
     }
 
     fn debug_app(
@@ -117,8 +146,14 @@ impl Device for IosDevice {
         project: &Project,
         build: &Build,
         args: &[&str],
-        envs: &[&str],
+        envs: &[EnvSpec],
     ) -> Result<BuildBundle> {
+        let envs = build
+            .runtime_env
+            .iter()
+            .chain(envs)
+            .cloned()
+            .collect::<Vec<_>>();
         let runnable = build
             .runnables
             .iter()
@@ -126,17 +161,28 @@ impl Device for IosDevice {
             .ok_or_else(|| anyhow!("No executable compiled"))?;
         let build_bundle = self.install_app(project, build, runnable)?;
         let lldb_proxy = self.start_remote_lldb()?;
+        let _lock = self.session_lock()?;
         run_remote(
             self.ptr,
             &lldb_proxy,
             &build_bundle.bundle_dir,
             args,
-            envs,
+            &envs,
             true,
         )?;
         Ok(build_bundle)
     }
 
+    fn description(&self) -> DeviceDescription {
+        DeviceDescription {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            kind: "ios".to_string(),
+            supported_targets: vec![self.rustc_triple.clone()],
+            ..DeviceDescription::default()
+        }
+    }
+
     fn id(&self) -> &str {
         &self.id
     }
@@ -147,29 +193,21 @@ impl Device for IosDevice {
 
     fn run_app(
         &self,
-        project: &Project,
-        build: &Build,
-        args: &[&str],
-        envs: &[&str],
-    ) -> Result<Vec<BuildBundle>> {
-        let mut build_bundles = vec![];
-        for runnable in &build.runnables {
-            let build_bundle = self.install_app(&project, &build, &runnable)?;
-            let lldb_proxy = self.start_remote_lldb()?;
-            run_remote(
-                self.ptr,
-                &lldb_proxy,
-                &build_bundle.bundle_dir,
-                args,
-                envs,
-                false,
-            )?;
-            build_bundles.push(build_bundle)
-        }
-        Ok(build_bundles)
+        _project: &Project,
+        _build: &Build,
+        _args: &[&str],
+        _envs: &[EnvSpec],
+    ) -> Result<Vec<RunnableOutcome>> {
+        // The lldb script does not give back the exit status and the output of the runnable,
+        // which test reports need: use `--debugger` meanwhile.
+        bail!(DinghyError::unsupported(
+            &self.id,
+            "running runnables with a test report"
+        ))
     }
 
     fn start_remote_lldb(&self) -> Result<String> {
+        let _lock = self.session_lock()?;
         let _ = ensure_session(self.ptr);
         let fd = start_remote_debug_server(self.ptr)?;
         debug!("start local lldb proxy");
@@ -219,8 +257,14 @@ impl IosSimDevice {
 }
 
 impl Device for IosSimDevice {
-    fn clean_app(&self, _build_bundle: &BuildBundle) -> Result<()> {
-        unimplemented!()
+    fn capabilities(&self) -> DeviceCapabilities {
+        DeviceCapabilities {
+            debug: true,
+            lldb_proxy: false,
+            clean: false,
+            shell: false,
+            streaming_output: true,
+        }
     }
 
     fn debug_app(
@@ -228,8 +272,14 @@ impl Device for IosSimDevice {
         project: &Project,
         build: &Build,
         args: &[&str],
-        envs: &[&str],
+        envs: &[EnvSpec],
     ) -> Result<BuildBundle> {
+        let envs = build
+            .runtime_env
+            .iter()
+            .chain(envs)
+            .cloned()
+            .collect::<Vec<_>>();
         let runnable = build
             .runnables
             .iter()
@@ -242,10 +292,20 @@ impl Device for IosSimDevice {
                 .output()?
                 .stdout,
         )?;
-        launch_lldb_simulator(&self, &install_path, args, envs, true)?;
+        launch_lldb_simulator(&self, &install_path, args, &envs, true)?;
         Ok(build_bundle)
     }
 
+    fn description(&self) -> DeviceDescription {
+        DeviceDescription {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            kind: "ios-simulator".to_string(),
+            supported_targets: vec!["x86_64-apple-ios".to_string()],
+            ..DeviceDescription::default()
+        }
+    }
+
     fn id(&self) -> &str {
         &self.id
     }
@@ -259,19 +319,36 @@ impl Device for IosSimDevice {
         project: &Project,
         build: &Build,
         args: &[&str],
-        envs: &[&str],
-    ) -> Result<Vec<BuildBundle>> {
-        let mut build_bundles = vec![];
+        envs: &[EnvSpec],
+    ) -> Result<Vec<RunnableOutcome>> {
+        let envs = build
+            .runtime_env
+            .iter()
+            .chain(envs)
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut outcomes = vec![];
         for runnable in &build.runnables {
             let build_bundle = self.install_app(&project, &build, &runnable)?;
-            launch_app(&self, args, envs)?;
-            build_bundles.push(build_bundle);
+            let start = Instant::now();
+            let (exit_code, output) = launch_app(&self, args, &envs)?;
+            let report = RunnableReport::new(
+                &runnable.id,
+                &self.id,
+                Some(exit_code),
+                start.elapsed(),
+                &output,
+            );
+            let success = report.is_success();
+            outcomes.push(RunnableOutcome {
+                build_bundle,
+                report,
+            });
+            if !success && !build.build_args.no_fail_fast {
+                break;
+            }
         }
-        Ok(build_bundles)
-    }
-
-    fn start_remote_lldb(&self) -> Result<String> {
-        unimplemented!()
+        Ok(outcomes)
     }
 }
 
@@ -642,7 +719,7 @@ fn launch_lldb_device<P: AsRef<Path>, P2: AsRef<Path>>(
     local: P,
     remote: P2,
     args: &[&str],
-    envs: &[&str],
+    envs: &[EnvSpec],
     debugger: bool,
 ) -> Result<()> {
     use std::io::Write;
@@ -658,7 +735,14 @@ fn launch_lldb_device<P: AsRef<Path>, P2: AsRef<Path>>(
     {
         let python_lldb_support = tmppath.join("helpers.py");
         let helper_py = include_str!("helpers.py");
-        let helper_py = helper_py.replace("ENV_VAR_PLACEHOLDER", &envs.join("\", \""));
+        let helper_py = helper_py.replace(
+            "\"ENV_VAR_PLACEHOLDER\"",
+            &envs
+                .iter()
+                .map(|env| format!("{:?}", env.to_string()))
+                .collect::<Vec<_>>()
+                .join(", "),
+        );
         fs::File::create(&python_lldb_support)?.write_fmt(format_args!("{}", &helper_py))?;
         let mut script = fs::File::create(&lldb_script_filename)?;
         writeln!(script, "platform select remote-ios --sysroot '{}'", sysroot)?;
@@ -710,7 +794,8 @@ fn launch_lldb_device<P: AsRef<Path>, P2: AsRef<Path>>(
     }
 }
 
-fn launch_app(dev: &IosSimDevice, app_args: &[&str], _envs: &[&str]) -> Result<()> {
+/// Runs the app on the simulator, returning its exit status and its output.
+fn launch_app(dev: &IosSimDevice, app_args: &[&str], _envs: &[EnvSpec]) -> Result<(i32, String)> {
     use std::io::Write;
     let dir = ::tempdir::TempDir::new("mobiledevice-rs-lldb")?;
     let tmppath = dir.path();
@@ -779,20 +864,15 @@ fn launch_app(dev: &IosSimDevice, app_args: &[&str], _envs: &[&str]) -> Result<(
     if let Some(exit_status_line) = exit_status_line {
         let words: Vec<&str> = exit_status_line.split_whitespace().rev().collect();
         if let Some(exit_status) = words.get(1) {
-            let exit_status = exit_status.parse::<u32>()?;
-            if exit_status == 0 {
-                Ok(())
-            } else {
-                panic!("Non-zero exit code from lldb: {}", exit_status);
-            }
+            Ok((exit_status.parse::<i32>()?, test_contents))
         } else {
-            panic!(
+            bail!(
                 "Failed to parse lldb exit line for an exit status. {:?}",
                 words
-            );
+            )
         }
     } else {
-        panic!("Failed to get the exit status line from lldb: {:?}", lines);
+        bail!("Failed to get the exit status line from lldb: {:?}", lines)
     }
 }
 
@@ -800,7 +880,7 @@ fn launch_lldb_simulator(
     dev: &IosSimDevice,
     installed: &str,
     args: &[&str],
-    envs: &[&str],
+    envs: &[EnvSpec],
     debugger: bool,
 ) -> Result<()> {
     use std::io::Write;
@@ -811,7 +891,14 @@ fn launch_lldb_simulator(
     {
         let python_lldb_support = tmppath.join("helpers.py");
         let helper_py = include_str!("helpers.py");
-        let helper_py = helper_py.replace("ENV_VAR_PLACEHOLDER", &envs.join("\", \""));
+        let helper_py = helper_py.replace(
+            "\"ENV_VAR_PLACEHOLDER\"",
+            &envs
+                .iter()
+                .map(|env| format!("{:?}", env.to_string()))
+                .collect::<Vec<_>>()
+                .join(", "),
+        );
         fs::File::create(&python_lldb_support)?.write_fmt(format_args!("{}", &helper_py))?;
         let mut script = fs::File::create(&lldb_script_filename)?;
         writeln!(script, "platform select ios-simulator")?;
@@ -851,7 +938,7 @@ pub fn run_remote<P: AsRef<Path>>(
     lldb_proxy: &str,
     app_path: P,
     args: &[&str],
-    envs: &[&str],
+    envs: &[EnvSpec],
     debugger: bool,
 ) -> Result<()> {
     let _session = ensure_session(dev)?;
@@ -8,6 +8,7 @@ use crate::Build;
 use crate::BuildArgs;
 use crate::Device;
 use crate::Platform;
+use crate::PlatformDescription;
 use cargo::core::compiler::{CompileKind, CompileTarget};
 use dinghy_build::build_env::set_env;
 use std::fmt::{Debug, Display, Formatter};
@@ -70,7 +71,20 @@ impl Platform for IosPlatform {
         dbg!(&self.toolchain);
         self.toolchain.setup_pkg_config()?;
 
-        self.compiler.build(self, build_args)
+        let mut build = self.compiler.build(self, build_args)?;
+        build.runtime_env = self.configuration.runtime_env()?;
+        Ok(build)
+    }
+
+    fn description(&self) -> Result<PlatformDescription> {
+        Ok(PlatformDescription {
+            id: self.id(),
+            rustc_triple: self.rustc_triple().to_string(),
+            toolchain_root: None,
+            sysroot: self.sysroot()?,
+            is_host: false,
+            error: None,
+        })
     }
 
     fn id(&self) -> String {
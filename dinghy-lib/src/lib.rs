@@ -23,17 +23,23 @@ extern crate regex;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+pub extern crate serde_json;
 extern crate shell_escape;
 #[cfg(target_os = "macos")]
 extern crate tempdir;
 extern crate toml;
 extern crate walkdir;
 extern crate which;
+extern crate zip;
 
 mod android;
 pub mod compiler;
 pub mod config;
+mod container;
+mod debugger;
 pub mod device;
+pub mod doctor;
+pub mod env;
 pub mod errors;
 mod host;
 #[cfg(target_os = "macos")]
@@ -41,7 +47,10 @@ mod ios;
 pub mod overlay;
 pub mod platform;
 pub mod project;
+mod qemu;
+pub mod report;
 mod script;
+pub mod shard;
 mod ssh;
 mod toolchain;
 pub mod utils;
@@ -51,14 +60,19 @@ pub use crate::config::Configuration;
 
 use crate::compiler::CompileMode;
 use crate::config::PlatformConfiguration;
+use crate::env::EnvSpec;
 #[cfg(target_os = "macos")]
 use crate::ios::IosManager;
 use crate::platform::regular_platform::RegularPlatform;
 use crate::project::Project;
+use crate::report::RunnableReport;
 use cargo::core::compiler::CompileKind;
+use std::collections::BTreeMap;
 use std::fmt::Display;
+use std::time::Duration;
 use std::{path, sync};
 
+use crate::errors::DinghyError;
 use crate::errors::Result;
 
 pub struct Dinghy {
@@ -75,7 +89,8 @@ impl Dinghy {
         if let Some(man) = host::HostManager::probe(sync::Arc::clone(compiler), conf) {
             managers.push(Box::new(man));
         }
-        if let Some(man) = android::AndroidManager::probe(sync::Arc::clone(compiler)) {
+        if let Some(man) = android::AndroidManager::probe(sync::Arc::clone(compiler), conf.clone())
+        {
             managers.push(Box::new(man));
         }
         if let Some(man) = script::ScriptDeviceManager::probe(conf.clone()) {
@@ -84,6 +99,12 @@ impl Dinghy {
         if let Some(man) = ssh::SshDeviceManager::probe(conf.clone()) {
             managers.push(Box::new(man));
         }
+        if let Some(man) = container::ContainerDeviceManager::probe(conf.clone()) {
+            managers.push(Box::new(man));
+        }
+        if let Some(man) = qemu::QemuManager::probe() {
+            managers.push(Box::new(man));
+        }
         #[cfg(target_os = "macos")]
         {
             std::thread::sleep(std::time::Duration::from_millis(100));
@@ -157,32 +178,80 @@ impl Dinghy {
             .map(|&(_, ref platform)| platform.clone())
             .next()
     }
+
+    /// Shuts down the devices dinghy started and would shut down when dropped, like the
+    /// emulators of `shutdown_avds`, for when dinghy is interrupted.
+    pub fn shutdown_started_devices() {
+        android::shutdown_emulators()
+    }
+
+    pub fn describe_device(&self, device: &dyn Device) -> DeviceDescription {
+        DeviceDescription {
+            compatible_platforms: self
+                .platforms
+                .iter()
+                .filter(|(_, platform)| platform.is_compatible_with(device))
+                .map(|(platform_name, _)| platform_name.clone())
+                .collect(),
+            capabilities: device.capabilities(),
+            ..device.description()
+        }
+    }
 }
 
-pub trait Device: std::fmt::Debug + Display + DeviceCompatibility {
-    fn clean_app(&self, build_bundle: &BuildBundle) -> Result<()>;
+pub trait Device: std::fmt::Debug + Display + DeviceCompatibility + Send + Sync {
+    /// What the device can do besides running runnables.
+    fn capabilities(&self) -> DeviceCapabilities;
+
+    fn clean_app(&self, _build_bundle: &BuildBundle) -> Result<()> {
+        bail!(DinghyError::unsupported(self.id(), "cleaning up"))
+    }
 
     fn debug_app(
         &self,
-        project: &Project,
-        build: &Build,
-        args: &[&str],
-        envs: &[&str],
-    ) -> Result<BuildBundle>;
+        _project: &Project,
+        _build: &Build,
+        _args: &[&str],
+        _envs: &[EnvSpec],
+    ) -> Result<BuildBundle> {
+        bail!(DinghyError::unsupported(self.id(), "debugging"))
+    }
+
+    fn description(&self) -> DeviceDescription;
 
     fn id(&self) -> &str;
 
     fn name(&self) -> &str;
 
+    /// Whether using the device starts it first, like an emulator that is not running. Such
+    /// devices are only selected by device hints starting their id, like `avd:<name>`.
+    fn starts_on_demand(&self) -> bool {
+        false
+    }
+
     fn run_app(
         &self,
         project: &Project,
         build: &Build,
         args: &[&str],
-        envs: &[&str],
-    ) -> Result<Vec<BuildBundle>>;
+        envs: &[EnvSpec],
+    ) -> Result<Vec<RunnableOutcome>>;
 
-    fn start_remote_lldb(&self) -> Result<String>;
+    /// Installs `runnable`, then opens an interactive shell in its bundle directory on the
+    /// device, with the environment it would be run with. Returns the exit code of the shell.
+    fn shell(
+        &self,
+        _project: &Project,
+        _build: &Build,
+        _runnable: &Runnable,
+        _envs: &[EnvSpec],
+    ) -> Result<i32> {
+        bail!(DinghyError::unsupported(self.id(), "interactive shells"))
+    }
+
+    fn start_remote_lldb(&self) -> Result<String> {
+        bail!(DinghyError::unsupported(self.id(), "lldb proxies"))
+    }
 }
 
 pub trait DeviceCompatibility {
@@ -203,6 +272,8 @@ pub trait DeviceCompatibility {
 pub trait Platform: std::fmt::Debug {
     fn build(&self, project: &Project, build_args: &BuildArgs) -> Result<Build>;
 
+    fn description(&self) -> Result<PlatformDescription>;
+
     fn id(&self) -> String;
 
     fn is_compatible_with(&self, device: &dyn Device) -> bool;
@@ -226,11 +297,75 @@ pub trait PlatformManager {
     fn platforms(&self) -> Result<Vec<Box<dyn Platform>>>;
 }
 
+/// Machine-readable summary of a device, as printed by `all-devices --format json`.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct DeviceDescription {
+    pub id: String,
+    pub name: String,
+    pub kind: String,
+    pub compatible_platforms: Vec<String>,
+    pub supported_targets: Vec<String>,
+    pub capabilities: DeviceCapabilities,
+    /// What the device tells about itself, like its model or OS version.
+    pub properties: BTreeMap<String, String>,
+}
+
+/// The operations a device supports, the others fail with a `DinghyError::Unsupported` error.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct DeviceCapabilities {
+    /// `--debugger`.
+    pub debug: bool,
+    /// `lldbproxy`.
+    pub lldb_proxy: bool,
+    /// `--cleanup`.
+    pub clean: bool,
+    /// `shell`.
+    pub shell: bool,
+    /// The output of runnables is shown while they run, rather than once they exit.
+    pub streaming_output: bool,
+}
+
+impl DeviceCapabilities {
+    /// Names of the supported operations, for humans.
+    pub fn names(&self) -> Vec<&'static str> {
+        [
+            (self.debug, "debug"),
+            (self.lldb_proxy, "lldb proxy"),
+            (self.clean, "clean"),
+            (self.shell, "shell"),
+            (self.streaming_output, "streaming output"),
+        ]
+        .iter()
+        .filter(|(supported, _)| *supported)
+        .map(|(_, name)| *name)
+        .collect()
+    }
+}
+
+/// Machine-readable summary of a platform, as printed by `all-platforms --format json`.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct PlatformDescription {
+    pub id: String,
+    pub rustc_triple: String,
+    pub toolchain_root: Option<path::PathBuf>,
+    pub sysroot: Option<path::PathBuf>,
+    pub is_host: bool,
+    /// Why the platform could not be fully described, the other fields being left empty.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
 #[derive(Clone, Debug)]
 pub struct Build {
     pub build_args: BuildArgs,
     pub dynamic_libraries: Vec<path::PathBuf>,
     pub runnables: Vec<Runnable>,
+    /// Environment variables the runnables are run with, from the platform configuration.
+    pub runtime_env: Vec<EnvSpec>,
+    /// Triple of the platform the runnables were built for.
+    pub rustc_triple: String,
+    /// Sysroot of the platform the runnables were built for.
+    pub sysroot: Option<path::PathBuf>,
     pub target_path: path::PathBuf,
 }
 
@@ -239,6 +374,10 @@ pub struct BuildArgs {
     pub compile_mode: CompileMode,
     pub verbose: bool,
     pub forced_overlays: Vec<String>,
+    /// Keep running the other runnables after one failed.
+    pub no_fail_fast: bool,
+    /// How long each runnable may run before being killed, overrides the device configuration.
+    pub timeout: Option<Duration>,
 }
 
 #[derive(Clone, Debug, Default)]
@@ -277,6 +416,13 @@ impl BuildBundle {
     }
 }
 
+/// What running one runnable on a device produced: the installed bundle and its test report.
+#[derive(Clone, Debug)]
+pub struct RunnableOutcome {
+    pub build_bundle: BuildBundle,
+    pub report: RunnableReport,
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct Runnable {
     pub id: String,
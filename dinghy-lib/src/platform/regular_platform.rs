@@ -1,13 +1,18 @@
 use crate::compiler::Compiler;
 use crate::config::PlatformConfiguration;
+use crate::doctor::Check;
+use crate::doctor::Lookup;
 use crate::overlay::Overlayer;
 use crate::platform;
 use crate::project::Project;
+use crate::toolchain::pkg_config_dirs;
 use crate::toolchain::ToolchainConfig;
 use crate::Build;
 use crate::BuildArgs;
+use crate::Configuration;
 use crate::Device;
 use crate::Platform;
+use crate::PlatformDescription;
 use crate::Result;
 use cargo::core::compiler::{CompileKind, CompileTarget};
 use dinghy_build::build_env::set_all_env;
@@ -53,48 +58,32 @@ impl RegularPlatform {
                     cc: "gcc".to_string(),
                     binutils_prefix: prefix.clone(),
                     cc_prefix: prefix.clone(),
+                    link_args: vec![],
                 },
             }));
         }
         let toolchain_path = toolchain_path.as_ref();
         let toolchain_bin_path = toolchain_path.join("bin");
 
-        let mut bin: Option<PathBuf> = None;
-        let mut prefix: Option<String> = None;
-        for file in toolchain_bin_path.read_dir().with_context(|| {
-            format!(
-                "Couldn't find toolchain directory {}",
-                toolchain_path.display()
-            )
-        })? {
-            let file = file?;
-            if file.file_name().to_string_lossy().ends_with("-gcc")
-                || file.file_name().to_string_lossy().ends_with("-gcc.exe")
-            {
-                bin = Some(toolchain_bin_path);
-                prefix = Some(
-                    file.file_name()
-                        .to_string_lossy()
-                        .replace(".exe", "")
-                        .replace("-gcc", ""),
-                );
-                break;
-            }
-        }
-        let bin_dir = bin.ok_or_else(|| anyhow!("no bin/*-gcc found in toolchain"))?;
-        let tc_triple = prefix
-            .ok_or_else(|| anyhow!("no gcc in toolchain"))?
-            .to_string();
+        let tc_triple = find_gcc_prefix(&toolchain_bin_path)
+            .with_context(|| {
+                format!(
+                    "Couldn't find toolchain directory {}",
+                    toolchain_path.display()
+                )
+            })?
+            .ok_or_else(|| anyhow!("no bin/*-gcc found in toolchain"))?;
         let sysroot = find_sysroot(&toolchain_path)?;
 
         let toolchain = ToolchainConfig {
-            bin_dir,
+            bin_dir: toolchain_bin_path,
             rustc_triple,
             root: toolchain_path.into(),
             sysroot,
             cc: "gcc".to_string(),
             binutils_prefix: tc_triple.clone(),
             cc_prefix: tc_triple,
+            link_args: vec![],
         };
         Self::new_with_tc(compiler.clone(), configuration, id, toolchain)
     }
@@ -164,6 +153,10 @@ impl Platform for RegularPlatform {
         if let Some(sr) = &self.toolchain.sysroot {
             linker_cmd.push_str(&format!(" --sysroot {}", sr.display()));
         }
+        for link_arg in &self.toolchain.link_args {
+            linker_cmd.push(' ');
+            linker_cmd.push_str(link_arg);
+        }
         for forced_overlay in &build_args.forced_overlays {
             linker_cmd.push_str(" -l");
             linker_cmd.push_str(&forced_overlay);
@@ -179,7 +172,20 @@ impl Platform for RegularPlatform {
         self.toolchain.shim_executables(&self.id)?;
 
         trace!("Internally invoke cargo");
-        self.compiler.build(self, &build_args)
+        let mut build = self.compiler.build(self, &build_args)?;
+        build.runtime_env = self.configuration.runtime_env()?;
+        Ok(build)
+    }
+
+    fn description(&self) -> Result<PlatformDescription> {
+        Ok(PlatformDescription {
+            id: self.id.clone(),
+            rustc_triple: self.toolchain.rustc_triple.clone(),
+            toolchain_root: Some(self.toolchain.root.clone()),
+            sysroot: self.toolchain.sysroot.clone(),
+            is_host: false,
+            error: None,
+        })
     }
 
     fn id(&self) -> String {
@@ -217,6 +223,17 @@ impl Platform for RegularPlatform {
     }
 }
 
+/// The prefix of the `<prefix>-gcc` compiler found in `bin_dir`, like `arm-linux-gnueabihf`.
+fn find_gcc_prefix(bin_dir: &Path) -> Result<Option<String>> {
+    for file in bin_dir.read_dir()? {
+        let file_name = file?.file_name().to_string_lossy().replace(".exe", "");
+        if file_name.ends_with("-gcc") {
+            return Ok(Some(file_name.replace("-gcc", "")));
+        }
+    }
+    Ok(None)
+}
+
 fn find_sysroot<P: AsRef<Path>>(toolchain_path: P) -> Result<Option<PathBuf>> {
     let toolchain = toolchain_path.as_ref();
     let immediate = toolchain.join("sysroot");
@@ -238,3 +255,198 @@ fn find_sysroot<P: AsRef<Path>>(toolchain_path: P) -> Result<Option<PathBuf>> {
     }
     Ok(None)
 }
+
+/// Checks the toolchains of the platforms of the configuration.
+pub fn doctor(conf: &Configuration, lookup: &dyn Lookup) -> Vec<Check> {
+    let rust_sysroot = Command::new("rustc")
+        .arg("--print")
+        .arg("sysroot")
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| PathBuf::from(String::from_utf8_lossy(&output.stdout).trim()));
+    let mut checks = vec![];
+    for (name, platform_conf) in &conf.platforms {
+        if name == "host" {
+            continue;
+        }
+        let subject = format!("platform {}", name);
+        let rustc_triple = match platform_conf.rustc_triple {
+            Some(ref rustc_triple) => rustc_triple,
+            None => {
+                checks.push(Check::error(
+                    subject,
+                    "rustc triple",
+                    "missing",
+                    format!("Add a rustc_triple to [platforms.{}]", name),
+                ));
+                continue;
+            }
+        };
+        if let Some(ref rust_sysroot) = rust_sysroot {
+            if rust_sysroot.join("lib/rustlib").join(rustc_triple).is_dir() {
+                checks.push(Check::ok(&*subject, "rust target", rustc_triple.as_str()));
+            } else {
+                checks.push(Check::error(
+                    &*subject,
+                    "rust target",
+                    format!("{} is not installed", rustc_triple),
+                    format!("Run `rustup target add {}`", rustc_triple),
+                ));
+            }
+        }
+        checks.extend(toolchain_checks(&subject, name, platform_conf, lookup));
+    }
+    checks
+}
+
+fn toolchain_checks(
+    subject: &str,
+    name: &str,
+    platform_conf: &PlatformConfiguration,
+    lookup: &dyn Lookup,
+) -> Vec<Check> {
+    if let Some(ref prefix) = platform_conf.deb_multiarch {
+        let gcc = PathBuf::from("/usr/bin").join(format!("{}-gcc", prefix));
+        return vec![if gcc.is_file() {
+            Check::ok(subject, "gcc", gcc.display().to_string())
+        } else {
+            Check::error(
+                subject,
+                "gcc",
+                format!("{} not found", gcc.display()),
+                format!("Install the gcc-{} package", prefix),
+            )
+        }];
+    }
+    let toolchain = match platform_conf
+        .toolchain
+        .as_ref()
+        .map(PathBuf::from)
+        .or_else(|| {
+            lookup.var("HOME").map(|it| {
+                PathBuf::from(it)
+                    .join(".dinghy")
+                    .join("toolchain")
+                    .join(name)
+            })
+        }) {
+        Some(toolchain) => toolchain,
+        None => return vec![],
+    };
+    let toolchain_hint = format!(
+        "Set `toolchain` in [platforms.{}] to the root of a cross-compilation toolchain, with a bin/<prefix>-gcc",
+        name
+    );
+    if !toolchain.is_dir() {
+        return vec![Check::error(
+            subject,
+            "toolchain",
+            format!("{} is not a directory", toolchain.display()),
+            toolchain_hint,
+        )];
+    }
+    let mut checks = vec![Check::ok(
+        subject,
+        "toolchain",
+        toolchain.display().to_string(),
+    )];
+    let bin_dir = toolchain.join("bin");
+    match find_gcc_prefix(&bin_dir) {
+        Ok(Some(prefix)) => {
+            checks.push(Check::ok(
+                subject,
+                "toolchain bin dir",
+                bin_dir.display().to_string(),
+            ));
+            checks.push(Check::ok(subject, "gcc prefix", prefix));
+        }
+        Ok(None) => checks.push(Check::error(
+            subject,
+            "toolchain bin dir",
+            format!("no *-gcc in {}", bin_dir.display()),
+            toolchain_hint,
+        )),
+        Err(e) => checks.push(Check::error(
+            subject,
+            "toolchain bin dir",
+            format!("{} can not be read: {}", bin_dir.display(), e),
+            toolchain_hint,
+        )),
+    }
+    match find_sysroot(&toolchain) {
+        Ok(Some(sysroot)) => checks.push(Check::ok(
+            subject,
+            "sysroot",
+            sysroot.display().to_string(),
+        )),
+        _ => checks.push(Check::warning(
+            subject,
+            "sysroot",
+            "not found",
+            "The target libraries and headers are expected in a `sysroot` directory, at the root of the toolchain or one level below",
+        )),
+    }
+    let pkg_config_dirs = pkg_config_dirs(&toolchain);
+    checks.push(Check::ok(
+        subject,
+        "pkg-config dirs",
+        if pkg_config_dirs.is_empty() {
+            "none (only needed by crates using pkg-config)".to_string()
+        } else {
+            pkg_config_dirs.join(":")
+        },
+    ));
+    checks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::doctor::tests::status_of;
+    use crate::doctor::tests::FakeLookup;
+    use crate::doctor::CheckStatus;
+    use std::fs;
+
+    #[test]
+    fn test_doctor_rejects_a_missing_toolchain() {
+        let home = tempfile::tempdir().unwrap();
+        let mut lookup = FakeLookup::default();
+        lookup
+            .vars
+            .insert("HOME".to_string(), home.path().display().to_string());
+        let mut conf = PlatformConfiguration::empty();
+
+        let checks = toolchain_checks("platform pi", "pi", &conf, &lookup);
+        assert_eq!(
+            Some(CheckStatus::Error),
+            status_of(&checks, "platform pi", "toolchain")
+        );
+
+        conf.toolchain = Some(home.path().join("nowhere").display().to_string());
+        let checks = toolchain_checks("platform pi", "pi", &conf, &lookup);
+        assert_eq!(
+            Some(CheckStatus::Error),
+            status_of(&checks, "platform pi", "toolchain")
+        );
+    }
+
+    #[test]
+    fn test_doctor_rejects_a_toolchain_without_gcc() {
+        let toolchain = tempfile::tempdir().unwrap();
+        fs::create_dir_all(toolchain.path().join("bin")).unwrap();
+        let mut conf = PlatformConfiguration::empty();
+        conf.toolchain = Some(toolchain.path().display().to_string());
+
+        let checks = toolchain_checks("platform pi", "pi", &conf, &FakeLookup::default());
+
+        assert_eq!(
+            Some(CheckStatus::Ok),
+            status_of(&checks, "platform pi", "toolchain")
+        );
+        assert_eq!(
+            Some(CheckStatus::Error),
+            status_of(&checks, "platform pi", "toolchain bin dir")
+        );
+    }
+}
@@ -0,0 +1,134 @@
+use crate::compiler::CompileMode;
+use crate::device::make_remote_app;
+use crate::device::run_runnable;
+use crate::device::Timeout;
+use crate::env::EnvSpec;
+use crate::platform::regular_platform::RegularPlatform;
+use crate::project::Project;
+use crate::qemu::qemu_arch;
+use crate::utils::path_to_str;
+use crate::Build;
+use crate::BuildBundle;
+use crate::Device;
+use crate::DeviceCapabilities;
+use crate::DeviceCompatibility;
+use crate::DeviceDescription;
+use crate::Platform;
+use crate::Result;
+use crate::RunnableOutcome;
+use std::fmt;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Runs the runnables on the host, through a qemu user-mode emulator.
+#[derive(Debug)]
+pub struct QemuDevice {
+    pub id: String,
+    pub arch: String,
+    pub qemu: PathBuf,
+    /// The rustc targets whose binaries `qemu` runs.
+    pub supported_targets: Vec<String>,
+}
+
+impl DeviceCompatibility for QemuDevice {
+    fn is_compatible_with_regular_platform(&self, platform: &RegularPlatform) -> bool {
+        qemu_arch(platform.rustc_triple()) == Some(self.arch.as_str())
+    }
+}
+
+impl Device for QemuDevice {
+    fn capabilities(&self) -> DeviceCapabilities {
+        DeviceCapabilities {
+            debug: false,
+            lldb_proxy: false,
+            clean: true,
+            shell: false,
+            streaming_output: true,
+        }
+    }
+
+    fn clean_app(&self, _build_bundle: &BuildBundle) -> Result<()> {
+        Ok(())
+    }
+
+    fn description(&self) -> DeviceDescription {
+        DeviceDescription {
+            id: self.id.clone(),
+            name: self.id.clone(),
+            kind: "qemu".to_string(),
+            supported_targets: self.supported_targets.clone(),
+            ..DeviceDescription::default()
+        }
+    }
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn name(&self) -> &str {
+        &self.id
+    }
+
+    fn run_app(
+        &self,
+        project: &Project,
+        build: &Build,
+        args: &[&str],
+        envs: &[EnvSpec],
+    ) -> Result<Vec<RunnableOutcome>> {
+        let mut outcomes = vec![];
+        for runnable in &build.runnables {
+            let build_bundle = make_remote_app(project, build, runnable)?;
+            info!(
+                "Run {} on {} ({:?})",
+                runnable.id, self.id, build.build_args.compile_mode
+            );
+            let mut command = Command::new(&self.qemu);
+            if let Some(ref sysroot) = build.sysroot {
+                command.arg("-L").arg(sysroot);
+            }
+            // Set with -E, so that it only applies to the emulated program, not to qemu.
+            command.arg("-E").arg(format!(
+                "LD_LIBRARY_PATH={}",
+                path_to_str(&build_bundle.lib_dir)?
+            ));
+            command
+                .arg(&build_bundle.bundle_exe)
+                .current_dir(&build_bundle.bundle_dir)
+                .env("DINGHY", "1")
+                .env("RUST_BACKTRACE", "1")
+                .envs(
+                    build
+                        .runtime_env
+                        .iter()
+                        .chain(envs)
+                        .map(|env| (&env.key, &env.value)),
+                );
+            if build.build_args.compile_mode == CompileMode::Bench {
+                command.arg("--bench");
+            }
+            command.args(args);
+            let report = run_runnable(
+                &self.id,
+                runnable,
+                &mut command,
+                Timeout::new(build.build_args.timeout, None),
+            )?;
+            let success = report.is_success();
+            outcomes.push(RunnableOutcome {
+                build_bundle,
+                report,
+            });
+            if !success && !build.build_args.no_fail_fast {
+                break;
+            }
+        }
+        Ok(outcomes)
+    }
+}
+
+impl fmt::Display for QemuDevice {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", self.id)
+    }
+}
@@ -0,0 +1,152 @@
+use crate::doctor::Check;
+use crate::{Device, Platform, PlatformManager, Result};
+use std::path::PathBuf;
+use std::process::Command;
+
+mod device;
+
+use self::device::QemuDevice;
+
+/// Architectures, as named by qemu user-mode binaries (`qemu-<arch>`), dinghy knows how to map
+/// rustc triples to.
+const ARCHS: &[&str] = &[
+    "aarch64", "arm", "i386", "mips", "mips64", "mips64el", "mipsel", "ppc", "ppc64", "ppc64le",
+    "riscv32", "riscv64", "s390x", "x86_64",
+];
+
+pub struct QemuManager {
+    emulators: Vec<(String, PathBuf)>,
+}
+
+impl QemuManager {
+    pub fn probe() -> Option<QemuManager> {
+        let emulators = ARCHS
+            .iter()
+            .filter_map(|arch| {
+                which::which(format!("qemu-{}", arch))
+                    .or_else(|_| which::which(format!("qemu-{}-static", arch)))
+                    .ok()
+                    .map(|path| (arch.to_string(), path))
+            })
+            .collect::<Vec<_>>();
+        debug!("Found qemu user-mode emulators: {:?}", emulators);
+        if emulators.is_empty() {
+            None
+        } else {
+            Some(QemuManager { emulators })
+        }
+    }
+}
+
+impl PlatformManager for QemuManager {
+    fn devices(&self) -> Result<Vec<Box<dyn Device>>> {
+        let targets = rustc_targets();
+        Ok(self
+            .emulators
+            .iter()
+            .map(|(arch, qemu)| {
+                Box::new(QemuDevice {
+                    id: format!("qemu-{}", arch),
+                    arch: arch.clone(),
+                    qemu: qemu.clone(),
+                    supported_targets: targets
+                        .iter()
+                        .filter(|target| qemu_arch(target) == Some(arch.as_str()))
+                        .cloned()
+                        .collect(),
+                }) as _
+            })
+            .collect())
+    }
+    fn platforms(&self) -> Result<Vec<Box<dyn Platform>>> {
+        Ok(vec![])
+    }
+}
+
+/// Reports the qemu user-mode emulators found.
+pub fn doctor() -> Vec<Check> {
+    match QemuManager::probe() {
+        Some(manager) => vec![Check::ok(
+            "qemu",
+            "emulators",
+            manager
+                .emulators
+                .iter()
+                .map(|(_, path)| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+        )],
+        None => vec![Check::warning(
+            "qemu",
+            "emulators",
+            "none found",
+            "Install qemu-user (or qemu-user-static) to run Linux binaries of other architectures",
+        )],
+    }
+}
+
+/// The targets rustc knows, empty if it can't tell.
+fn rustc_targets() -> Vec<String> {
+    match Command::new("rustc")
+        .arg("--print")
+        .arg("target-list")
+        .output()
+    {
+        Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|line| line.trim().to_string())
+            .filter(|line| !line.is_empty())
+            .collect(),
+        result => {
+            debug!("Couldn't list the targets of rustc: {:?}", result);
+            vec![]
+        }
+    }
+}
+
+/// The qemu architecture able to run binaries built for `rustc_triple`.
+///
+/// Only Linux targets can run in qemu user mode, and Android binaries need the device's own
+/// dynamic linker.
+pub fn qemu_arch(rustc_triple: &str) -> Option<&'static str> {
+    if !rustc_triple.contains("-linux-") || rustc_triple.contains("android") {
+        return None;
+    }
+    let arch = rustc_triple.split('-').next()?;
+    let qemu_arch = match arch {
+        "aarch64" => "aarch64",
+        "i386" | "i586" | "i686" => "i386",
+        "mips" => "mips",
+        "mipsel" => "mipsel",
+        "mips64" => "mips64",
+        "mips64el" => "mips64el",
+        "powerpc" => "ppc",
+        "powerpc64" => "ppc64",
+        "powerpc64le" => "ppc64le",
+        "s390x" => "s390x",
+        "x86_64" => "x86_64",
+        arch if arch.starts_with("riscv32") => "riscv32",
+        arch if arch.starts_with("riscv64") => "riscv64",
+        arch if arch.starts_with("arm") || arch.starts_with("thumb") => "arm",
+        _ => return None,
+    };
+    Some(qemu_arch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_qemu_arch() {
+        assert_eq!(Some("aarch64"), qemu_arch("aarch64-unknown-linux-gnu"));
+        assert_eq!(Some("arm"), qemu_arch("armv7-unknown-linux-gnueabihf"));
+        assert_eq!(Some("arm"), qemu_arch("arm-unknown-linux-musleabi"));
+        assert_eq!(Some("i386"), qemu_arch("i686-unknown-linux-gnu"));
+        assert_eq!(Some("riscv64"), qemu_arch("riscv64gc-unknown-linux-gnu"));
+        assert_eq!(Some("ppc64le"), qemu_arch("powerpc64le-unknown-linux-gnu"));
+        assert_eq!(None, qemu_arch("wasm32-unknown-unknown"));
+        assert_eq!(None, qemu_arch("aarch64-linux-android"));
+        assert_eq!(None, qemu_arch("aarch64-apple-ios"));
+    }
+}
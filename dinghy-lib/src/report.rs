@@ -0,0 +1,343 @@
+use crate::errors::*;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TestStatus {
+    Passed,
+    Failed,
+    Ignored,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TestCaseReport {
+    pub name: String,
+    pub status: TestStatus,
+    /// Duration in seconds, only known when the test harness reports it (`--report-time`).
+    pub duration: Option<f64>,
+    /// What libtest printed for this test in its `failures:` section.
+    pub output: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunnableStatus {
+    Success,
+    Failure,
+    /// Killed after running for longer than the configured timeout.
+    Timeout,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct RunnableReport {
+    pub id: String,
+    pub device: String,
+    pub status: RunnableStatus,
+    pub exit_code: Option<i32>,
+    /// Wall-clock duration of the runnable in seconds, as seen from dinghy.
+    pub duration: f64,
+    pub tests: Vec<TestCaseReport>,
+    /// Everything the runnable printed on stdout.
+    #[serde(skip)]
+    pub output: String,
+}
+
+impl RunnableReport {
+    pub fn new(
+        id: &str,
+        device: &str,
+        exit_code: Option<i32>,
+        duration: Duration,
+        output: &str,
+    ) -> RunnableReport {
+        RunnableReport {
+            id: id.to_string(),
+            device: device.to_string(),
+            status: if exit_code == Some(0) {
+                RunnableStatus::Success
+            } else {
+                RunnableStatus::Failure
+            },
+            exit_code,
+            duration: duration.as_secs_f64(),
+            tests: parse_libtest_output(output),
+            output: output.to_string(),
+        }
+    }
+
+    pub fn timed_out(self) -> RunnableReport {
+        RunnableReport {
+            status: RunnableStatus::Timeout,
+            ..self
+        }
+    }
+
+    /// Adds the report of another run of the same runnable, on other tests, to this one.
+    pub fn merge(&mut self, other: RunnableReport) {
+        if self.is_success() {
+            self.status = other.status;
+            self.exit_code = other.exit_code;
+        }
+        self.duration += other.duration;
+        self.tests.extend(other.tests);
+        self.output.push_str(&other.output);
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.status == RunnableStatus::Success
+    }
+
+    pub fn is_timeout(&self) -> bool {
+        self.status == RunnableStatus::Timeout
+    }
+}
+
+/// Extracts per-test results from the human output of the libtest harness.
+pub fn parse_libtest_output(output: &str) -> Vec<TestCaseReport> {
+    let test_line = ::regex::Regex::new(r"^test (.+) \.\.\. (ok|FAILED|ignored|bench:)(.*)$")
+        .expect("Invalid libtest regex");
+    let time = ::regex::Regex::new(r"<([0-9.]+)s>").expect("Invalid libtest time regex");
+
+    let mut tests: Vec<TestCaseReport> = vec![];
+    let mut failure_output: Option<(String, String)> = None;
+    for line in output.lines().map(|line| line.trim_end_matches('\r')) {
+        if let Some(caps) = test_line.captures(line) {
+            tests.push(TestCaseReport {
+                name: caps[1].to_string(),
+                status: match &caps[2] {
+                    "FAILED" => TestStatus::Failed,
+                    "ignored" => TestStatus::Ignored,
+                    _ => TestStatus::Passed,
+                },
+                duration: time
+                    .captures(&caps[3])
+                    .and_then(|it| it[1].parse::<f64>().ok()),
+                output: None,
+            });
+        } else if line.starts_with("---- ") && line.ends_with(" stdout ----") {
+            attach_failure_output(&mut tests, failure_output.take());
+            let name = line
+                .trim_start_matches("---- ")
+                .trim_end_matches(" stdout ----");
+            failure_output = Some((name.to_string(), String::new()));
+        } else if line == "failures:" || line.starts_with("test result:") {
+            attach_failure_output(&mut tests, failure_output.take());
+        } else if let Some((_, ref mut text)) = failure_output {
+            text.push_str(line);
+            text.push('\n');
+        }
+    }
+    attach_failure_output(&mut tests, failure_output);
+    tests
+}
+
+fn attach_failure_output(tests: &mut [TestCaseReport], failure_output: Option<(String, String)>) {
+    if let Some((name, text)) = failure_output {
+        if let Some(test) = tests.iter_mut().find(|test| test.name == name) {
+            test.output = Some(text.trim().to_string());
+        }
+    }
+}
+
+/// Writes `<device>.json` and `<device>.xml` (JUnit) reports in `dir`.
+pub fn write_reports<P: AsRef<Path>>(
+    dir: P,
+    device_id: &str,
+    reports: &[RunnableReport],
+) -> Result<(PathBuf, PathBuf)> {
+    let dir = dir.as_ref();
+    fs::create_dir_all(dir)
+        .with_context(|| format!("Couldn't create report directory {}", dir.display()))?;
+    let file_name: String = device_id
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    let json_path = dir.join(format!("{}.json", file_name));
+    fs::write(&json_path, serde_json::to_string_pretty(reports)?)
+        .with_context(|| format!("Couldn't write {}", json_path.display()))?;
+
+    let junit_path = dir.join(format!("{}.xml", file_name));
+    fs::write(&junit_path, junit_xml(reports)?)
+        .with_context(|| format!("Couldn't write {}", junit_path.display()))?;
+    Ok((json_path, junit_path))
+}
+
+fn junit_xml(reports: &[RunnableReport]) -> Result<String> {
+    let mut xml = String::new();
+    writeln!(xml, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(xml, "<testsuites>")?;
+    for report in reports {
+        let count = |status: TestStatus| report.tests.iter().filter(|t| t.status == status).count();
+        // A timeout, or a failure without any failed test, is reported as an extra test case.
+        let runnable_failure =
+            report.is_timeout() || (report.tests.is_empty() && !report.is_success());
+        writeln!(
+            xml,
+            r#"  <testsuite name="{}" hostname="{}" tests="{}" failures="{}" skipped="{}" time="{:.3}">"#,
+            xml_escape(&report.id),
+            xml_escape(&report.device),
+            report.tests.len() + runnable_failure as usize,
+            count(TestStatus::Failed) + runnable_failure as usize,
+            count(TestStatus::Ignored),
+            report.duration
+        )?;
+        for test in &report.tests {
+            write!(
+                xml,
+                r#"    <testcase classname="{}" name="{}""#,
+                xml_escape(&report.id),
+                xml_escape(&test.name)
+            )?;
+            if let Some(duration) = test.duration {
+                write!(xml, r#" time="{:.3}""#, duration)?;
+            }
+            match test.status {
+                TestStatus::Passed => writeln!(xml, "/>")?,
+                TestStatus::Ignored => writeln!(xml, ">\n      <skipped/>\n    </testcase>")?,
+                TestStatus::Failed => writeln!(
+                    xml,
+                    "><failure message=\"test failed\">{}</failure></testcase>",
+                    xml_escape(test.output.as_deref().unwrap_or(""))
+                )?,
+            }
+        }
+        if report.is_timeout() {
+            writeln!(
+                xml,
+                r#"    <testcase classname="{}" name="{}"><failure message="timed out after {:.0}s"/></testcase>"#,
+                xml_escape(&report.id),
+                xml_escape(&report.id),
+                report.duration
+            )?;
+        } else if report.tests.is_empty() && !report.is_success() {
+            writeln!(
+                xml,
+                r#"    <testcase classname="{}" name="{}"><failure message="exit code {}"/></testcase>"#,
+                xml_escape(&report.id),
+                xml_escape(&report.id),
+                report
+                    .exit_code
+                    .map(|it| it.to_string())
+                    .unwrap_or("unknown".to_string())
+            )?;
+        }
+        writeln!(xml, "  </testsuite>")?;
+    }
+    writeln!(xml, "</testsuites>")?;
+    Ok(xml)
+}
+
+fn xml_escape(text: &str) -> String {
+    text.chars()
+        .fold(String::with_capacity(text.len()), |mut escaped, c| {
+            match c {
+                '&' => escaped.push_str("&amp;"),
+                '<' => escaped.push_str("&lt;"),
+                '>' => escaped.push_str("&gt;"),
+                '"' => escaped.push_str("&quot;"),
+                '\'' => escaped.push_str("&apos;"),
+                c if c.is_control() && c != '\n' && c != '\t' => {}
+                c => escaped.push(c),
+            }
+            escaped
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_keeps_all_tests_and_the_first_failure() {
+        let mut report = RunnableReport::new(
+            "lib-abc",
+            "phone",
+            Some(0),
+            Duration::from_secs(1),
+            "test tests::a ... ok\n",
+        );
+        report.merge(RunnableReport::new(
+            "lib-abc",
+            "phone",
+            Some(101),
+            Duration::from_secs(2),
+            "test tests::b ... FAILED\n",
+        ));
+        report.merge(RunnableReport::new(
+            "lib-abc",
+            "phone",
+            Some(0),
+            Duration::from_secs(3),
+            "test tests::c ... ok\n",
+        ));
+        assert_eq!(RunnableStatus::Failure, report.status);
+        assert_eq!(Some(101), report.exit_code);
+        assert_eq!(6.0, report.duration);
+        assert_eq!(
+            vec!["tests::a", "tests::b", "tests::c"],
+            report
+                .tests
+                .iter()
+                .map(|test| &test.name)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_parse_libtest_output() {
+        let output = "\
+running 4 tests\r
+test tests::pass::it_works ... ok\r
+test tests::pass::slow ... ok <1.250s>\r
+test tests::skipped ... ignored\r
+test tests::fails::it_fails ... FAILED\r
+\r
+failures:\r
+\r
+---- tests::fails::it_fails stdout ----\r
+thread 'tests::fails::it_fails' panicked at 'Failing as expected', src/lib.rs:58:13\r
+\r
+\r
+failures:\r
+    tests::fails::it_fails\r
+\r
+test result: FAILED. 2 passed; 1 failed; 1 ignored; 0 measured; 0 filtered out\r
+";
+        let tests = parse_libtest_output(output);
+        assert_eq!(4, tests.len());
+        assert_eq!(TestStatus::Passed, tests[0].status);
+        assert_eq!(Some(1.25), tests[1].duration);
+        assert_eq!(TestStatus::Ignored, tests[2].status);
+        assert_eq!(TestStatus::Failed, tests[3].status);
+        assert_eq!(
+            Some("thread 'tests::fails::it_fails' panicked at 'Failing as expected', src/lib.rs:58:13"),
+            tests[3].output.as_deref()
+        );
+    }
+
+    #[test]
+    fn test_junit_xml_escapes_output() {
+        let report = RunnableReport::new(
+            "app-123",
+            "raspi",
+            Some(101),
+            Duration::from_millis(1500),
+            "test a::<b> ... FAILED\n\nfailures:\n\n---- a::<b> stdout ----\n\"boom\" & co\n\nfailures:\n",
+        );
+        let xml = junit_xml(&[report]).unwrap();
+        assert!(xml.contains(r#"name="a::&lt;b&gt;""#));
+        assert!(xml.contains("&quot;boom&quot; &amp; co"));
+        assert!(xml.contains(r#"failures="1""#));
+    }
+}
@@ -1,15 +1,71 @@
 use crate::config::ScriptDeviceConfiguration;
+use crate::config::ScriptProtocol;
+use crate::device::make_remote_app;
+use crate::device::run_runnable;
+use crate::device::run_runnable_with_input;
+use crate::device::Timeout;
+use crate::env::EnvSpec;
+use crate::errors::*;
 use crate::*;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::PathBuf;
 use std::{fmt, fs, process};
 
 #[derive(Debug)]
 pub struct ScriptDevice {
     pub id: String,
     pub conf: ScriptDeviceConfiguration,
+    /// The device of the script this one stands for, when the script listed several.
+    pub listed: Option<ListedDevice>,
+}
+
+/// A device, as listed by the `list-devices` subcommand of an extended protocol script.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ListedDevice {
+    pub id: String,
+    pub name: Option<String>,
+}
+
+/// What an extended protocol script receives on stdin.
+#[derive(Debug, Serialize)]
+struct ScriptRequest<'a> {
+    /// Id of the device in dinghy.
+    device: &'a str,
+    /// Id of the device as listed by the script.
+    listed_device: Option<&'a str>,
+    platform: Option<&'a str>,
+    compile_mode: Option<String>,
+    runnable: Option<ScriptRunnable>,
+    args: &'a [&'a str],
+    env: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize)]
+struct ScriptRunnable {
+    id: String,
+    bundle_dir: PathBuf,
+    bundle_exe: PathBuf,
+    lib_dir: PathBuf,
+    root_dir: PathBuf,
+    test_data: Option<PathBuf>,
+}
+
+impl ScriptRunnable {
+    fn new(build_bundle: &BuildBundle, test_data: Option<PathBuf>) -> ScriptRunnable {
+        ScriptRunnable {
+            id: build_bundle.id.clone(),
+            bundle_dir: build_bundle.bundle_dir.clone(),
+            bundle_exe: build_bundle.bundle_exe.clone(),
+            lib_dir: build_bundle.lib_dir.clone(),
+            root_dir: build_bundle.root_dir.clone(),
+            test_data,
+        }
+    }
 }
 
 impl ScriptDevice {
-    fn command(&self, build: &Build) -> Result<process::Command> {
+    fn command(&self, build: Option<&Build>) -> Result<process::Command> {
         if fs::metadata(&self.conf.path).is_err() {
             bail!("Can not read {:?} for {}.", self.conf.path, self.id);
         }
@@ -19,27 +75,207 @@ impl ScriptDevice {
         if let Some(ref pf) = self.conf.platform {
             cmd.env("DINGHY_PLATFORM", &*pf);
         }
-        cmd.env(
-            "DINGHY_COMPILE_MODE",
-            &*format!("{:?}", build.build_args.compile_mode),
-        );
+        if let Some(build) = build {
+            cmd.env(
+                "DINGHY_COMPILE_MODE",
+                &*format!("{:?}", build.build_args.compile_mode),
+            );
+        }
         Ok(cmd)
     }
+
+    fn is_extended(&self) -> bool {
+        self.conf.protocol == Some(ScriptProtocol::Extended)
+    }
+
+    /// Variables the executable needs when it runs from a self-contained bundle: as on ssh
+    /// devices, `DINGHY` makes dinghy-test look for the test data in the bundle.
+    fn bundle_env(&self) -> Vec<EnvSpec> {
+        if self.conf.bundle {
+            vec![EnvSpec {
+                key: "DINGHY".to_string(),
+                value: "1".to_string(),
+            }]
+        } else {
+            vec![]
+        }
+    }
+
+    fn request<'a>(
+        &'a self,
+        build: Option<&Build>,
+        runnable: Option<ScriptRunnable>,
+        args: &'a [&'a str],
+        envs: &[EnvSpec],
+    ) -> ScriptRequest<'a> {
+        ScriptRequest {
+            device: &self.id,
+            listed_device: self.listed.as_ref().map(|it| it.id.as_str()),
+            platform: self.conf.platform.as_deref(),
+            compile_mode: build.map(|it| format!("{:?}", it.build_args.compile_mode)),
+            runnable,
+            args,
+            env: self
+                .bundle_env()
+                .iter()
+                .chain(build.iter().flat_map(|build| build.runtime_env.iter()))
+                .chain(envs)
+                .map(|env| (env.key.clone(), env.value.clone()))
+                .collect(),
+        }
+    }
+
+    /// Calls the extended protocol `subcommand` of the script and returns its stdout.
+    fn call(
+        &self,
+        subcommand: &str,
+        build: Option<&Build>,
+        request: &ScriptRequest,
+    ) -> Result<String> {
+        let mut command = self.command(build)?;
+        command
+            .arg(subcommand)
+            .stdin(process::Stdio::piped())
+            .stdout(process::Stdio::piped());
+        debug!("Running {:?}", command);
+        let mut child = command
+            .spawn()
+            .with_context(|| format!("Couldn't run {:?}", command))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            // A script not reading its input closes the pipe, which is fine.
+            let _ = stdin.write_all(&serde_json::to_vec(request)?);
+        }
+        let output = child.wait_with_output()?;
+        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+        debug!("`{}` output: {}", subcommand, stdout);
+        if !output.status.success() {
+            bail!(
+                "`{} {}` failed for {} ({})",
+                self.conf.path,
+                subcommand,
+                self.id,
+                output.status
+            )
+        }
+        Ok(stdout)
+    }
+
+    /// Asks an extended protocol script for the devices it drives.
+    pub fn list_devices(&self) -> Result<Vec<ListedDevice>> {
+        let stdout = self.call("list-devices", None, &self.request(None, None, &[], &[]))?;
+        serde_json::from_str(&stdout).with_context(|| {
+            format!(
+                "Invalid `list-devices` output for {} (expected a JSON array of {{ \"id\": ..., \"name\": ... }}): {}",
+                self.id, stdout
+            )
+        })
+    }
+
+    /// The bundle of `runnable` to hand to the script, and the path to its test data.
+    ///
+    /// Unless the configuration asks for a self-contained bundle, the executable is run from
+    /// the target directory, next to the sources of its package.
+    fn bundle(
+        &self,
+        project: &Project,
+        build: &Build,
+        runnable: &Runnable,
+    ) -> Result<(BuildBundle, PathBuf)> {
+        if self.conf.bundle {
+            let build_bundle = make_remote_app(project, build, runnable)?;
+            let test_data_path = build_bundle.bundle_dir.join("test_data");
+            return Ok((build_bundle, test_data_path));
+        }
+        let test_data_path = project.link_test_data(runnable, &runnable.source)?;
+        let build_bundle = BuildBundle {
+            id: runnable.id.clone(),
+            bundle_dir: runnable.source.to_path_buf(),
+            bundle_exe: runnable.exe.to_path_buf(),
+            lib_dir: build.target_path.clone(),
+            root_dir: build.target_path.join("dinghy"),
+        };
+        Ok((build_bundle, test_data_path))
+    }
+
+    fn run_app_extended(
+        &self,
+        project: &Project,
+        build: &Build,
+        args: &[&str],
+        envs: &[EnvSpec],
+    ) -> Result<Vec<RunnableOutcome>> {
+        self.call(
+            "probe",
+            Some(build),
+            &self.request(Some(build), None, &[], envs),
+        )
+        .context(DinghyError::DeviceUnreachable(self.id.clone()))?;
+        let mut outcomes = vec![];
+        for runnable in &build.runnables {
+            let (build_bundle, test_data_path) = self.bundle(project, build, runnable)?;
+            let script_runnable =
+                || ScriptRunnable::new(&build_bundle, Some(test_data_path.clone()));
+
+            info!("Install {} on {}", runnable.id, self.id);
+            self.call(
+                "install",
+                Some(build),
+                &self.request(Some(build), Some(script_runnable()), args, envs),
+            )?;
+
+            info!(
+                "Run {} on {} ({:?})",
+                runnable.id, self.id, build.build_args.compile_mode
+            );
+            let request = self.request(Some(build), Some(script_runnable()), args, envs);
+            let report = run_runnable_with_input(
+                &self.id,
+                runnable,
+                self.command(Some(build))?
+                    .arg("run")
+                    .current_dir(&build_bundle.bundle_dir),
+                Some(serde_json::to_vec(&request)?),
+                Timeout::new(build.build_args.timeout, None),
+            )?;
+            let success = report.is_success();
+            outcomes.push(RunnableOutcome {
+                build_bundle,
+                report,
+            });
+            if !success && !build.build_args.no_fail_fast {
+                break;
+            }
+        }
+        Ok(outcomes)
+    }
 }
 
 impl Device for ScriptDevice {
-    fn clean_app(&self, _build_bundle: &BuildBundle) -> Result<()> {
+    fn capabilities(&self) -> DeviceCapabilities {
+        DeviceCapabilities {
+            debug: false,
+            lldb_proxy: false,
+            clean: true,
+            shell: false,
+            streaming_output: true,
+        }
+    }
+
+    fn clean_app(&self, build_bundle: &BuildBundle) -> Result<()> {
+        if self.is_extended() {
+            let runnable = ScriptRunnable::new(build_bundle, None);
+            self.call("clean", None, &self.request(None, Some(runnable), &[], &[]))?;
+        }
         Ok(())
     }
 
-    fn debug_app(
-        &self,
-        _project: &Project,
-        _build: &Build,
-        _args: &[&str],
-        _envs: &[&str],
-    ) -> Result<BuildBundle> {
-        unimplemented!()
+    fn description(&self) -> DeviceDescription {
+        DeviceDescription {
+            id: self.id.clone(),
+            name: self.name().to_string(),
+            kind: "script".to_string(),
+            ..DeviceDescription::default()
+        }
     }
 
     fn id(&self) -> &str {
@@ -47,7 +283,10 @@ impl Device for ScriptDevice {
     }
 
     fn name(&self) -> &str {
-        &self.id
+        self.listed
+            .as_ref()
+            .and_then(|it| it.name.as_deref())
+            .unwrap_or(&self.id)
     }
 
     fn run_app(
@@ -55,54 +294,50 @@ impl Device for ScriptDevice {
         project: &Project,
         build: &Build,
         args: &[&str],
-        envs: &[&str],
-    ) -> Result<Vec<BuildBundle>> {
-        let root_dir = build.target_path.join("dinghy");
-        let mut build_bundles = vec![];
+        envs: &[EnvSpec],
+    ) -> Result<Vec<RunnableOutcome>> {
+        if self.is_extended() {
+            return self.run_app_extended(project, build, args, envs);
+        }
+        let mut outcomes = vec![];
         for runnable in &build.runnables {
-            let bundle_path = &runnable.source;
-
             trace!("About to start runner script...");
-            let test_data_path = project.link_test_data(&runnable, &bundle_path)?;
-
-            let status = self
-                .command(build)?
-                .arg(&runnable.exe)
-                .current_dir(&runnable.source)
-                .env("DINGHY_TEST_DATA_PATH", test_data_path)
-                .args(args)
-                .envs(
-                    envs.iter()
-                        .map(|kv| {
-                            Ok((
-                                kv.split("=")
-                                    .nth(0)
-                                    .ok_or_else(|| anyhow!("Wrong env spec"))?,
-                                kv.split("=")
-                                    .nth(1)
-                                    .ok_or_else(|| anyhow!("Wrong env spec"))?,
-                            ))
-                        })
-                        .collect::<Result<Vec<_>>>()?,
-                )
-                .status()?;
-            if !status.success() {
-                bail!("Test failed")
-            }
+            let (build_bundle, test_data_path) = self.bundle(project, build, runnable)?;
 
-            build_bundles.push(BuildBundle {
-                id: runnable.id.clone(),
-                bundle_dir: bundle_path.to_path_buf(),
-                bundle_exe: runnable.exe.to_path_buf(),
-                lib_dir: build.target_path.clone(),
-                root_dir: root_dir.clone(),
+            let mut command = self.command(Some(build))?;
+            command
+                .arg(&build_bundle.bundle_exe)
+                .current_dir(&build_bundle.bundle_dir)
+                .env("DINGHY_TEST_DATA_PATH", test_data_path);
+            if self.conf.bundle {
+                command
+                    .env("DINGHY_BUNDLE_DIR", &build_bundle.bundle_dir)
+                    .env("DINGHY_LIB_DIR", &build_bundle.lib_dir)
+                    .env("DINGHY_ROOT_DIR", &build_bundle.root_dir);
+            }
+            command.args(args).envs(
+                self.bundle_env()
+                    .iter()
+                    .chain(&build.runtime_env)
+                    .chain(envs)
+                    .map(|env| (&env.key, &env.value)),
+            );
+            let report = run_runnable(
+                &self.id,
+                runnable,
+                &mut command,
+                Timeout::new(build.build_args.timeout, None),
+            )?;
+            let success = report.is_success();
+            outcomes.push(RunnableOutcome {
+                build_bundle,
+                report,
             });
+            if !success && !build.build_args.no_fail_fast {
+                break;
+            }
         }
-        Ok(build_bundles)
-    }
-
-    fn start_remote_lldb(&self) -> Result<String> {
-        unimplemented!()
+        Ok(outcomes)
     }
 }
 
@@ -120,3 +355,41 @@ impl Display for ScriptDevice {
         write!(fmt, "{}", self.id)
     }
 }
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_list_devices_reads_json_from_stdout() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("farm.sh");
+        fs::write(
+            &script,
+            r#"#!/bin/sh
+[ "$1" = list-devices ] || exit 1
+grep -q '"device":"farm"' || exit 2
+echo '[{"id": "pixel-3", "name": "Pixel 3"}, {"id": "moto-g"}]'
+"#,
+        )
+        .unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        let device = ScriptDevice {
+            id: "farm".to_string(),
+            conf: ScriptDeviceConfiguration {
+                path: script.to_str().unwrap().to_string(),
+                platform: None,
+                protocol: Some(ScriptProtocol::Extended),
+                bundle: false,
+            },
+            listed: None,
+        };
+        let listed = device.list_devices().unwrap();
+        assert_eq!(2, listed.len());
+        assert_eq!("pixel-3", listed[0].id);
+        assert_eq!(Some("Pixel 3".to_string()), listed[0].name);
+        assert_eq!("moto-g", listed[1].id);
+        assert_eq!(None, listed[1].name);
+    }
+}
@@ -1,4 +1,7 @@
+use crate::config::ScriptProtocol;
+use crate::doctor::Check;
 use crate::{Configuration, Device, Platform, PlatformManager, Result};
+use std::path::Path;
 use std::sync;
 
 mod device;
@@ -17,19 +20,75 @@ impl ScriptDeviceManager {
 
 impl PlatformManager for ScriptDeviceManager {
     fn devices(&self) -> Result<Vec<Box<dyn Device>>> {
-        Ok(self
-            .conf
-            .script_devices
-            .iter()
-            .map(|(k, conf)| {
-                Box::new(ScriptDevice {
-                    id: k.clone(),
-                    conf: conf.clone(),
-                }) as _
-            })
-            .collect())
+        let mut devices: Vec<Box<dyn Device>> = vec![];
+        for (k, conf) in &self.conf.script_devices {
+            let device = ScriptDevice {
+                id: k.clone(),
+                conf: conf.clone(),
+                listed: None,
+            };
+            if device.conf.protocol != Some(ScriptProtocol::Extended) {
+                devices.push(Box::new(device));
+                continue;
+            }
+            match device.list_devices() {
+                Ok(listed) => {
+                    for listed in listed {
+                        devices.push(Box::new(ScriptDevice {
+                            id: format!("{}/{}", k, listed.id),
+                            conf: conf.clone(),
+                            listed: Some(listed),
+                        }));
+                    }
+                }
+                Err(e) => {
+                    warn!("Could not list the devices of {}: {:?}", k, e);
+                    devices.push(Box::new(device));
+                }
+            }
+        }
+        Ok(devices)
     }
     fn platforms(&self) -> Result<Vec<Box<dyn Platform>>> {
         Ok(vec![])
     }
 }
+
+/// Checks the scripts of the configuration can be run, and list their devices.
+pub fn doctor(conf: &Configuration) -> Vec<Check> {
+    let mut checks = vec![];
+    for (id, conf) in &conf.script_devices {
+        let subject = format!("script device {}", id);
+        if !Path::new(&conf.path).is_file() {
+            checks.push(Check::error(
+                subject,
+                "script",
+                format!("{} not found", conf.path),
+                format!("Fix the path of [script_devices.{}]", id),
+            ));
+            continue;
+        }
+        checks.push(Check::ok(&*subject, "script", conf.path.as_str()));
+        if conf.protocol == Some(ScriptProtocol::Extended) {
+            let device = ScriptDevice {
+                id: id.clone(),
+                conf: conf.clone(),
+                listed: None,
+            };
+            checks.push(match device.list_devices() {
+                Ok(listed) => Check::ok(
+                    &*subject,
+                    "list-devices",
+                    format!("{} devices", listed.len()),
+                ),
+                Err(e) => Check::error(
+                    &*subject,
+                    "list-devices",
+                    format!("{:#}", e),
+                    "The script should print a JSON array of devices for `list-devices` (see docs/script.md)",
+                ),
+            });
+        }
+    }
+    checks
+}
@@ -0,0 +1,257 @@
+use crate::Runnable;
+use std::collections::BTreeMap;
+
+#[derive(Clone, Debug)]
+pub struct Shard {
+    pub index: usize,
+    pub count: usize,
+    pub runnables: Vec<ShardedRunnable>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ShardedRunnable {
+    pub runnable: Runnable,
+    /// Tests of the runnable assigned to this shard, in chunks to pass to one run of the runnable
+    /// each, `None` when the whole runnable is.
+    pub tests: Option<Vec<Vec<String>>>,
+}
+
+impl Shard {
+    pub fn test_count(&self) -> usize {
+        self.runnables
+            .iter()
+            .filter_map(|it| it.tests.as_ref())
+            .flatten()
+            .map(|chunk| chunk.len())
+            .sum()
+    }
+}
+
+/// Bound on the length of the test names, shell-escaped, passed to one run of a runnable: adb and
+/// ssh have command line limits, as low as 4 KiB for `adb shell` on old devices.
+pub const MAX_TEST_ARGS_LEN: usize = 3072;
+
+/// Index of the shard (out of `count`) that `name` is assigned to.
+///
+/// This is FNV-1a rather than the std hasher, whose output may change between Rust releases: a
+/// given test must always land on the same shard.
+pub fn shard_of(name: &str, count: usize) -> usize {
+    let hash = name.bytes().fold(0xcbf29ce484222325_u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    (hash % count.max(1) as u64) as usize
+}
+
+/// The name of the runnable `id`, without the metadata hash cargo appends to it
+/// (`my_crate-0123456789abcdef`), which changes with the toolchain and the build profile.
+fn runnable_name(id: &str) -> &str {
+    match id.rsplit_once('-') {
+        Some((name, hash)) if hash.len() == 16 && hash.chars().all(|c| c.is_ascii_hexdigit()) => {
+            name
+        }
+        _ => id,
+    }
+}
+
+/// Extracts test names from the output of a libtest harness run with `--list --format terse`.
+pub fn parse_test_list(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .map(|line| line.trim_end_matches('\r'))
+        .filter_map(|line| {
+            line.strip_suffix(": test")
+                .or_else(|| line.strip_suffix(": bench"))
+        })
+        .map(|name| name.to_string())
+        .collect()
+}
+
+/// Splits `runnables` into `count` shards.
+///
+/// Runnables with listed tests (by runnable id in `tests`) are split test by test, the others are
+/// assigned as a whole, by name so that they stay on the same shard from a build to another. The
+/// tests of a runnable in a shard are further split in several runs when their names exceed
+/// `MAX_TEST_ARGS_LEN`.
+pub fn plan(
+    runnables: &[Runnable],
+    tests: &BTreeMap<String, Vec<String>>,
+    count: usize,
+) -> Vec<Shard> {
+    let mut shards = (0..count)
+        .map(|index| Shard {
+            index,
+            count,
+            runnables: vec![],
+        })
+        .collect::<Vec<_>>();
+    for runnable in runnables {
+        match tests.get(&runnable.id).filter(|tests| !tests.is_empty()) {
+            Some(tests) => {
+                for shard in shards.iter_mut() {
+                    let shard_tests = tests
+                        .iter()
+                        .filter(|test| shard_of(test, count) == shard.index)
+                        .cloned()
+                        .collect::<Vec<_>>();
+                    if !shard_tests.is_empty() {
+                        shard.runnables.push(ShardedRunnable {
+                            runnable: runnable.clone(),
+                            tests: Some(chunk_test_args(shard_tests, MAX_TEST_ARGS_LEN)),
+                        });
+                    }
+                }
+            }
+            None => shards[shard_of(runnable_name(&runnable.id), count)]
+                .runnables
+                .push(ShardedRunnable {
+                    runnable: runnable.clone(),
+                    tests: None,
+                }),
+        }
+    }
+    shards
+}
+
+/// Splits `tests` in chunks whose names, shell-escaped and separated by spaces, fit in `max_len`
+/// (a longer name gets a chunk of its own).
+fn chunk_test_args(tests: Vec<String>, max_len: usize) -> Vec<Vec<String>> {
+    let mut chunks: Vec<Vec<String>> = vec![];
+    let mut len = 0;
+    for test in tests {
+        let test_len = escaped_len(&test);
+        match chunks.last_mut() {
+            Some(chunk) if len + 1 + test_len <= max_len => {
+                len += 1 + test_len;
+                chunk.push(test);
+            }
+            _ => {
+                len = test_len;
+                chunks.push(vec![test]);
+            }
+        }
+    }
+    chunks
+}
+
+/// Length of `arg` once escaped for the shell of the device.
+fn escaped_len(arg: &str) -> usize {
+    ::shell_escape::escape(arg.into()).len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn runnable(id: &str) -> Runnable {
+        Runnable {
+            id: id.to_string(),
+            ..Runnable::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_test_list() {
+        let output = "tests::fails::it_fails: test\r\ntests::pass::it_works: test\r\nbenches::fast: bench\r\n\r\n2 tests, 1 benchmark\r\n";
+        assert_eq!(
+            vec![
+                "tests::fails::it_fails",
+                "tests::pass::it_works",
+                "benches::fast"
+            ],
+            parse_test_list(output)
+        );
+    }
+
+    #[test]
+    fn test_plan_is_deterministic_and_complete() {
+        let runnables = vec![runnable("lib-abc"), runnable("integration-def")];
+        let mut tests = BTreeMap::new();
+        tests.insert(
+            "lib-abc".to_string(),
+            (0..20).map(|i| format!("tests::t{}", i)).collect(),
+        );
+
+        let shards = plan(&runnables, &tests, 3);
+        assert_eq!(3, shards.len());
+        assert_eq!(
+            20,
+            shards.iter().map(|shard| shard.test_count()).sum::<usize>()
+        );
+        assert_eq!(
+            1,
+            shards
+                .iter()
+                .flat_map(|shard| shard.runnables.iter())
+                .filter(|it| it.runnable.id == "integration-def" && it.tests.is_none())
+                .count()
+        );
+        for shard in &shards {
+            for sharded in &shard.runnables {
+                for test in sharded.tests.iter().flatten().flatten() {
+                    assert_eq!(shard.index, shard_of(test, 3));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_plan_bounds_the_test_args_of_each_run() {
+        let runnables = vec![runnable("lib-abc")];
+        let mut tests = BTreeMap::new();
+        tests.insert(
+            "lib-abc".to_string(),
+            (0..5000)
+                .map(|i| format!("some::rather::deeply::nested::module::test_{}", i))
+                .collect(),
+        );
+
+        let shards = plan(&runnables, &tests, 2);
+        assert_eq!(
+            5000,
+            shards.iter().map(|shard| shard.test_count()).sum::<usize>()
+        );
+        for shard in &shards {
+            assert_eq!(1, shard.runnables.len());
+            let chunks = shard.runnables[0].tests.as_ref().unwrap();
+            assert!(chunks.len() > 1);
+            for chunk in chunks {
+                assert!(chunk.join(" ").len() <= MAX_TEST_ARGS_LEN);
+            }
+        }
+    }
+
+    #[test]
+    fn test_chunks_are_bounded_once_escaped() {
+        let tests = (0..100)
+            .map(|i| format!("tests::it_handles_case_{}'s_quote", i))
+            .collect::<Vec<_>>();
+        let chunks = chunk_test_args(tests, 400);
+        assert_eq!(100, chunks.iter().map(|chunk| chunk.len()).sum::<usize>());
+        for chunk in &chunks {
+            let escaped = chunk
+                .iter()
+                .map(|test| ::shell_escape::escape(test.into()).to_string())
+                .collect::<Vec<_>>();
+            assert!(escaped.join(" ").len() <= 400);
+        }
+    }
+
+    #[test]
+    fn test_runnables_are_sharded_by_name() {
+        assert_eq!("my_crate", runnable_name("my_crate-0123456789abcdef"));
+        assert_eq!("my-crate", runnable_name("my-crate-0123456789abcdef"));
+        assert_eq!("my-crate", runnable_name("my-crate"));
+        assert_eq!("my-cafe", runnable_name("my-cafe"));
+
+        let tests = BTreeMap::new();
+        let shard_of_build = |id: &str| {
+            plan(&[runnable(id)], &tests, 5)
+                .iter()
+                .position(|shard| !shard.runnables.is_empty())
+        };
+        assert_eq!(
+            shard_of_build("integration-0123456789abcdef"),
+            shard_of_build("integration-fedcba9876543210")
+        );
+    }
+}
@@ -1,30 +1,121 @@
 use crate::config::SshDeviceConfiguration;
+use crate::config::SshTransport;
+use crate::debugger::attach;
+use crate::debugger::free_port;
+use crate::debugger::runnable_to_debug;
+use crate::debugger::server_command;
+use crate::debugger::start_server;
+use crate::debugger::Debugger;
 use crate::device::make_remote_app;
+use crate::device::run_runnable;
+use crate::device::Installed;
+use crate::device::Timeout;
+use crate::doctor::Check;
+use crate::env::to_shell_assignments;
+use crate::env::EnvSpec;
 use crate::errors::*;
 use crate::host::HostPlatform;
 use crate::platform::regular_platform::RegularPlatform;
 use crate::project::Project;
+#[cfg(feature = "native-ssh")]
+use crate::report::RunnableReport;
+#[cfg(feature = "native-ssh")]
+use crate::ssh::native::NativeSession;
 use crate::utils::path_to_str;
 use crate::Build;
 use crate::BuildBundle;
 use crate::Device;
+use crate::DeviceCapabilities;
 use crate::DeviceCompatibility;
+use crate::DeviceDescription;
 use crate::Runnable;
+use crate::RunnableOutcome;
 use std::fmt;
 use std::fmt::Formatter;
 use std::fmt::{Debug, Display};
 use std::path::Path;
 use std::path::PathBuf;
 use std::process::Command;
+use std::time::Duration;
+#[cfg(feature = "native-ssh")]
+use std::time::Instant;
 
 pub struct SshDevice {
     pub id: String,
     pub conf: SshDeviceConfiguration,
+    /// The bundles installed by `install_app`, as `run_app` runs them.
+    installed: Installed<(BuildBundle, BuildBundle)>,
+}
+
+/// How dinghy talks to a device, see `SshDeviceConfiguration::transport`.
+enum Transport {
+    Openssh,
+    #[cfg(feature = "native-ssh")]
+    Native(NativeSession),
 }
 
 impl SshDevice {
+    pub fn new(id: String, conf: SshDeviceConfiguration) -> SshDevice {
+        SshDevice {
+            id,
+            conf,
+            installed: Installed::default(),
+        }
+    }
+
+    fn transport(&self) -> Result<Transport> {
+        match self.conf.transport.unwrap_or(SshTransport::Openssh) {
+            SshTransport::Openssh => Ok(Transport::Openssh),
+            #[cfg(feature = "native-ssh")]
+            SshTransport::Native => Ok(Transport::Native(
+                NativeSession::connect(&self.conf)
+                    .context(DinghyError::DeviceUnreachable(self.id.clone()))?,
+            )),
+            #[cfg(not(feature = "native-ssh"))]
+            SshTransport::Native => bail!(
+                "{} uses the native ssh transport, which requires dinghy to be built with the `native-ssh` feature",
+                self.id
+            ),
+        }
+    }
+
+    /// Runs `command` on the device and tells whether it succeeded.
+    fn exec(&self, transport: &Transport, command: &str) -> Result<bool> {
+        match transport {
+            Transport::Openssh => Ok(self.ssh_command()?.arg(command).status()?.success()),
+            #[cfg(feature = "native-ssh")]
+            Transport::Native(session) => Ok(session.exec(command)? == 0),
+        }
+    }
+
+    /// Runs `command`, which never exits with 255 itself, on the device and tells whether it
+    /// succeeded. ssh exits with 255 when it can not connect, or when the connection drops: the
+    /// device is then unreachable.
+    fn probe_exec(&self, transport: &Transport, command: &str) -> Result<bool> {
+        match transport {
+            Transport::Openssh => {
+                let status = self.ssh_command()?.arg(command).status()?;
+                if status.code() == Some(255) {
+                    bail!(DinghyError::DeviceUnreachable(self.id.clone()))
+                }
+                Ok(status.success())
+            }
+            #[cfg(feature = "native-ssh")]
+            Transport::Native(_) => self.exec(transport, command),
+        }
+    }
+
+    fn upload(&self, transport: &Transport, from_path: &Path, to_path: &Path) -> Result<()> {
+        match transport {
+            Transport::Openssh => self.sync(from_path, to_path),
+            #[cfg(feature = "native-ssh")]
+            Transport::Native(session) => session.upload_dir(from_path, to_path),
+        }
+    }
+
     fn install_app(
         &self,
+        transport: &Transport,
         project: &Project,
         build: &Build,
         runnable: &Runnable,
@@ -35,24 +126,63 @@ impl SshDevice {
         let remote_bundle = self.to_remote_bundle(&build_bundle)?;
         trace!("Create remote dir: {:?}", remote_bundle.bundle_dir);
 
-        let _ = self
-            .ssh_command()?
-            .arg("mkdir")
-            .arg("-p")
-            .arg(&remote_bundle.bundle_dir)
-            .status();
+        // The first command of a run on the device.
+        self.probe_exec(
+            transport,
+            &format!(
+                "mkdir -p {}",
+                ::shell_escape::escape(path_to_str(&remote_bundle.bundle_dir)?.into())
+            ),
+        )?;
 
         info!("Install {} to {}", runnable.id, self.id);
-        self.sync(&build_bundle.bundle_dir, &remote_bundle.bundle_dir)?;
-        self.sync(&build_bundle.lib_dir, &remote_bundle.lib_dir)?;
+        self.upload(
+            transport,
+            &build_bundle.bundle_dir,
+            &remote_bundle.bundle_dir,
+        )?;
+        self.upload(transport, &build_bundle.lib_dir, &remote_bundle.lib_dir)?;
         Ok((build_bundle, remote_bundle))
     }
 
-    fn ssh_command(&self) -> Result<Command> {
-        let mut command = Command::new("ssh");
+    /// Arguments shared by all the ssh, scp and rsync invocations reaching the device.
+    fn ssh_args(&self) -> Vec<String> {
+        let mut args = vec![];
+        if let Some(ref identity_file) = self.conf.identity_file {
+            args.push("-i".to_string());
+            args.push(shellexpand::tilde(identity_file).to_string());
+        }
+        let mut options = vec![];
         if let Some(port) = self.conf.port {
-            command.arg("-p").arg(&format!("{}", port));
+            options.push(format!("Port={}", port));
         }
+        if self.conf.identity_file.is_some() {
+            options.push("IdentitiesOnly=yes".to_string());
+        }
+        if let Some(ref proxy_jump) = self.conf.proxy_jump {
+            options.push(format!("ProxyJump={}", proxy_jump));
+        }
+        if self.conf.control_master.unwrap_or(false) {
+            options.push("ControlMaster=auto".to_string());
+            options.push(format!(
+                "ControlPath={}",
+                ::std::env::temp_dir().join("dinghy-ssh-%C").display()
+            ));
+            options.push("ControlPersist=60".to_string());
+        }
+        for (key, value) in &self.conf.ssh_options {
+            options.push(format!("{}={}", key, value));
+        }
+        for option in options {
+            args.push("-o".to_string());
+            args.push(option);
+        }
+        args
+    }
+
+    fn ssh_command(&self) -> Result<Command> {
+        let mut command = Command::new("ssh");
+        command.args(self.ssh_args());
         if atty::is(atty::Stream::Stdout) {
             command.arg("-t").arg("-o").arg("LogLevel=QUIET");
         }
@@ -60,15 +190,128 @@ impl SshDevice {
         Ok(command)
     }
 
+    /// The variables to run with: device variables override the platform ones, and `--env`
+    /// overrides both.
+    fn runtime_env(&self, build: &Build, envs: &[EnvSpec]) -> Result<Vec<EnvSpec>> {
+        let mut env = build.runtime_env.clone();
+        for (key, value) in &self.conf.env {
+            env.push(EnvSpec::new(key.as_str(), value.as_str())?);
+        }
+        env.extend(envs.iter().cloned());
+        Ok(env)
+    }
+
+    /// Shell assignments of the whole environment of a runnable installed in `remote_bundle`.
+    fn remote_env(&self, remote_bundle: &BuildBundle, env: &[EnvSpec]) -> Result<String> {
+        Ok(format!(
+            "RUST_BACKTRACE=1 DINGHY=1 LD_LIBRARY_PATH=\"{}:$LD_LIBRARY_PATH\" {}",
+            path_to_str(&remote_bundle.lib_dir)?,
+            to_shell_assignments(env)
+        ))
+    }
+
+    /// Checks the device is reachable, and that files can be sent to it.
+    pub fn doctor(&self) -> Vec<Check> {
+        let subject = format!("ssh device {}", self.id);
+        match self.transport() {
+            Ok(Transport::Openssh) => {}
+            #[cfg(feature = "native-ssh")]
+            Ok(Transport::Native(_)) => {
+                return vec![Check::ok(subject, "reachable", "connected natively")]
+            }
+            Err(e) => {
+                let hint = "Check the hostname, port, username and credentials, or use the openssh transport";
+                return vec![Check::error(subject, "transport", format!("{:#}", e), hint)];
+            }
+        }
+        let destination = format!("{}@{}", self.conf.username, self.conf.hostname);
+        let probe = |command: &str| {
+            Command::new("ssh")
+                .args(self.ssh_args())
+                .arg("-o")
+                .arg("BatchMode=yes")
+                .arg("-o")
+                .arg("ConnectTimeout=5")
+                .arg(&destination)
+                .arg(command)
+                .output()
+        };
+        let mut checks = vec![];
+        match probe("true") {
+            Ok(output) if output.status.success() => {
+                checks.push(Check::ok(&*subject, "reachable", destination.as_str()))
+            }
+            Ok(output) => {
+                checks.push(Check::error(
+                    subject,
+                    "reachable",
+                    format!(
+                        "can not log in as {}: {}",
+                        destination,
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    ),
+                    "Check the hostname, port and username, and that `ssh` logs in without a password prompt (see identity_file)",
+                ));
+                return checks;
+            }
+            Err(e) => {
+                checks.push(Check::error(
+                    subject,
+                    "reachable",
+                    format!("can not run ssh: {}", e),
+                    "Install an OpenSSH client",
+                ));
+                return checks;
+            }
+        }
+        match which::which("rsync") {
+            Ok(rsync) => checks.push(Check::ok(
+                &*subject,
+                "local rsync",
+                rsync.display().to_string(),
+            )),
+            Err(_) => checks.push(Check::error(
+                &*subject,
+                "local rsync",
+                "not found",
+                "Install rsync on this machine",
+            )),
+        }
+        let rsync_hint = "Install rsync on the device, or set install_adhoc_rsync_local_path to an rsync built for it";
+        match self.conf.install_adhoc_rsync_local_path {
+            Some(ref rsync) if Path::new(rsync).is_file() => checks.push(Check::ok(
+                &*subject,
+                "remote rsync",
+                format!("uploaded from {}", rsync),
+            )),
+            Some(ref rsync) => checks.push(Check::error(
+                &*subject,
+                "remote rsync",
+                format!("install_adhoc_rsync_local_path {} not found", rsync),
+                rsync_hint,
+            )),
+            None => match probe("test -x /usr/bin/rsync") {
+                Ok(output) if output.status.success() => {
+                    checks.push(Check::ok(&*subject, "remote rsync", "/usr/bin/rsync"))
+                }
+                _ => checks.push(Check::error(
+                    &*subject,
+                    "remote rsync",
+                    "/usr/bin/rsync not found",
+                    rsync_hint,
+                )),
+            },
+        }
+        checks
+    }
+
     fn sync_rsync(&self, rsync: Option<String>) -> Result<String> {
         match rsync {
             Some(rsync) => {
                 let rsync_path = "/tmp/rsync";
                 let mut command = Command::new("scp");
                 command.arg("-q");
-                if let Some(port) = self.conf.port {
-                    command.arg("-P").arg(&format!("{}", port));
-                }
+                command.args(self.ssh_args());
                 command.arg(format!("{}", rsync));
                 command.arg(format!(
                     "{}@{}:{}",
@@ -84,6 +327,16 @@ impl SshDevice {
         }
     }
 
+    /// The remote shell rsync should use, as given to its `-e` option.
+    fn rsync_remote_shell(&self) -> String {
+        Some("ssh".to_string())
+            .into_iter()
+            .chain(self.ssh_args())
+            .map(|arg| ::shell_escape::escape(arg.into()).to_string())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
     fn sync<FP: AsRef<Path>, TP: AsRef<Path>>(&self, from_path: FP, to_path: TP) -> Result<()> {
         let rsync = self.sync_rsync(self.conf.install_adhoc_rsync_local_path.clone());
         let rsync = match rsync {
@@ -91,22 +344,20 @@ impl SshDevice {
             Err(error) => bail!("Problem with rsync on the target: {:?}", error),
         };
         let mut command = Command::new("rsync");
-        command.arg(&format!("--rsync-path={}", rsync));
+        command.arg(format!("--rsync-path={}", rsync));
         command.arg("-a").arg("-v");
-        if let Some(port) = self.conf.port {
-            command.arg("-e").arg(&*format!("ssh -p {}", port));
-        };
+        command.arg("-e").arg(self.rsync_remote_shell());
         if !log_enabled!(::log::Level::Debug) {
             command.stdout(::std::process::Stdio::null());
             command.stderr(::std::process::Stdio::null());
         }
         command
-            .arg(&format!("{}/", path_to_str(&from_path.as_ref())?))
-            .arg(&format!(
+            .arg(format!("{}/", path_to_str(from_path.as_ref())?))
+            .arg(format!(
                 "{}@{}:{}/",
                 self.conf.username,
                 self.conf.hostname,
-                path_to_str(&to_path.as_ref())?
+                path_to_str(to_path.as_ref())?
             ));
         debug!("Running {:?}", command);
         if !command.status().with_context(||format!("failed to run '{:?}'", command))?.success() {
@@ -140,15 +391,25 @@ impl DeviceCompatibility for SshDevice {
 }
 
 impl Device for SshDevice {
+    fn capabilities(&self) -> DeviceCapabilities {
+        // Debugging and shells need a terminal, and port forwarding.
+        let openssh = self.conf.transport.unwrap_or(SshTransport::Openssh) == SshTransport::Openssh;
+        DeviceCapabilities {
+            debug: openssh,
+            lldb_proxy: false,
+            clean: true,
+            shell: openssh,
+            streaming_output: true,
+        }
+    }
+
     fn clean_app(&self, build_bundle: &BuildBundle) -> Result<()> {
-        let status = self
-            .ssh_command()?
-            .arg(&format!(
-                "rm -rf {}",
-                path_to_str(&build_bundle.bundle_exe)?
-            ))
-            .status()?;
-        if !status.success() {
+        self.installed.remove(&build_bundle.id);
+        let success = self.exec(
+            &self.transport()?,
+            &format!("rm -rf {}", path_to_str(&build_bundle.bundle_exe)?),
+        )?;
+        if !success {
             bail!("test fail.")
         }
         Ok(())
@@ -156,12 +417,95 @@ impl Device for SshDevice {
 
     fn debug_app(
         &self,
-        _project: &Project,
-        _build: &Build,
-        _args: &[&str],
-        _envs: &[&str],
+        project: &Project,
+        build: &Build,
+        args: &[&str],
+        envs: &[EnvSpec],
     ) -> Result<BuildBundle> {
-        unimplemented!()
+        let transport = self.transport()?;
+        #[cfg(feature = "native-ssh")]
+        {
+            if let Transport::Native(_) = transport {
+                bail!(DinghyError::unsupported(
+                    &self.id,
+                    "debugging over the native ssh transport"
+                ))
+            }
+        }
+        let runnable = runnable_to_debug(build)?;
+        let debugger = Debugger::for_remote()?;
+        let env = self.runtime_env(build, envs)?;
+        let (build_bundle, remote_bundle) =
+            self.install_app(&transport, project, build, runnable)?;
+        let args: Vec<String> = args
+            .iter()
+            .map(|&a| ::shell_escape::escape(a.into()).to_string())
+            .collect();
+        let exe = path_to_str(&remote_bundle.bundle_exe)?;
+        let command = format!(
+            "cd '{}' ; export {} ; if command -v gdbserver >/dev/null 2>&1 ; then exec {} ; else exec {} ; fi",
+            path_to_str(&remote_bundle.bundle_dir)?,
+            self.remote_env(&remote_bundle, &env)?,
+            server_command("gdbserver", exe, &args),
+            server_command("lldb-server", exe, &args)
+        );
+        info!("Debug {} on {}", runnable.id, self.id);
+        let destination = format!("{}@{}", self.conf.username, self.conf.hostname);
+        let mut server = start_server(
+            Command::new("ssh")
+                .args(self.ssh_args())
+                .arg(&destination)
+                .arg(command),
+        )?;
+        let port = free_port()?;
+        // ssh runs the local command once the forwarding listens: it tells so as a debug server
+        // would.
+        let forward = start_server(
+            Command::new("ssh")
+                .args(self.ssh_args())
+                .arg("-N")
+                .arg("-o")
+                .arg("ExitOnForwardFailure=yes")
+                .arg("-o")
+                .arg("PermitLocalCommand=yes")
+                .arg("-o")
+                .arg(format!("LocalCommand=echo Listening on port {}", port))
+                .arg("-L")
+                .arg(format!("{}:localhost:{}", port, server.port))
+                .arg(&destination),
+        );
+        let forward = match forward {
+            Ok(forward) => forward,
+            Err(e) => {
+                let _ = server.child.kill();
+                let _ = server.child.wait();
+                return Err(e.context(format!(
+                    "Couldn't forward port {} from {}",
+                    server.port, self.id
+                )));
+            }
+        };
+        attach(
+            debugger.attach_command(
+                &runnable.exe,
+                build.sysroot.as_deref(),
+                &build_bundle.lib_dir,
+                port,
+                "remote-linux",
+            ),
+            vec![server, forward],
+        )?;
+        Ok(build_bundle)
+    }
+
+    fn description(&self) -> DeviceDescription {
+        DeviceDescription {
+            id: self.id.clone(),
+            name: self.id.clone(),
+            kind: "ssh".to_string(),
+            supported_targets: self.conf.target.iter().cloned().collect(),
+            ..DeviceDescription::default()
+        }
     }
 
     fn id(&self) -> &str {
@@ -177,9 +521,9 @@ impl Device for SshDevice {
         project: &Project,
         build: &Build,
         args: &[&str],
-        envs: &[&str],
-    ) -> Result<Vec<BuildBundle>> {
-        let mut build_bundles = vec![];
+        envs: &[EnvSpec],
+    ) -> Result<Vec<RunnableOutcome>> {
+        let mut outcomes = vec![];
         let remote_shell_vars_as_context = |a: &str| -> Option<std::borrow::Cow<str>> {
             self.conf.remote_shell_vars.get(a).map(|s| s.into())
         };
@@ -194,37 +538,121 @@ impl Device for SshDevice {
             })
             .map(|a| ::shell_escape::escape(a).to_string())
             .collect();
+        let env = self.runtime_env(build, envs)?;
+        let transport = self.transport()?;
         for runnable in &build.runnables {
             info!("Install {:?}", runnable.id);
-            let (build_bundle, remote_bundle) = self.install_app(&project, &build, &runnable)?;
+            let (build_bundle, remote_bundle) =
+                self.installed.get_or_install(&runnable.id, || {
+                    self.install_app(&transport, project, build, runnable)
+                })?;
             debug!("Installed {:?}", runnable.id);
             let command = format!(
-                        "cd '{}' ; {} RUST_BACKTRACE=1 DINGHY=1 LD_LIBRARY_PATH=\"{}:$LD_LIBRARY_PATH\" {} {} {}",
-                        path_to_str(&remote_bundle.bundle_dir)?,
-                        envs.join(" "),
-                        path_to_str(&remote_bundle.lib_dir)?,
-                        path_to_str(&remote_bundle.bundle_exe)?,
-                        if build.build_args.compile_mode == ::cargo::core::compiler::CompileMode::Bench { "--bench" } else { "" },
-                        args.join(" ")
-                        );
+                "cd '{}' ; {} {} {} {}",
+                path_to_str(&remote_bundle.bundle_dir)?,
+                self.remote_env(&remote_bundle, &env)?,
+                path_to_str(&remote_bundle.bundle_exe)?,
+                if build.build_args.compile_mode == ::cargo::core::compiler::CompileMode::Bench {
+                    "--bench"
+                } else {
+                    ""
+                },
+                args.join(" ")
+            );
             trace!("Ssh command: {}", command);
             info!(
                 "Run {} on {} ({:?})",
                 runnable.id, self.id, build.build_args.compile_mode
             );
 
-            let status = self.ssh_command()?.arg(&command).status()?;
-            if !status.success() {
-                bail!("Test failed 🐛")
+            let kill = format!(
+                "pkill -KILL -f {}",
+                ::shell_escape::escape(path_to_str(&remote_bundle.bundle_exe)?.into())
+            );
+            let timeout = build
+                .build_args
+                .timeout
+                .or(self.conf.timeout.map(Duration::from_secs));
+            let report = match transport {
+                Transport::Openssh => {
+                    let mut kill_command = self.ssh_command()?;
+                    kill_command.arg(&kill);
+                    run_runnable(
+                        &self.id,
+                        runnable,
+                        self.ssh_command()?.arg(&command),
+                        Timeout::new(timeout, Some(kill_command)),
+                    )?
+                }
+                #[cfg(feature = "native-ssh")]
+                Transport::Native(ref session) => {
+                    let start = Instant::now();
+                    let output = session.run(&command, timeout, &kill)?;
+                    let report = RunnableReport::new(
+                        &runnable.id,
+                        &self.id,
+                        output.exit_code,
+                        start.elapsed(),
+                        &output.output,
+                    );
+                    if output.timed_out {
+                        report.timed_out()
+                    } else {
+                        report
+                    }
+                }
+            };
+            let success = report.is_success();
+            outcomes.push(RunnableOutcome {
+                build_bundle,
+                report,
+            });
+            if !success && !build.build_args.no_fail_fast {
+                break;
             }
-
-            build_bundles.push(build_bundle);
         }
-        Ok(build_bundles)
+        Ok(outcomes)
     }
 
-    fn start_remote_lldb(&self) -> Result<String> {
-        unimplemented!()
+    fn shell(
+        &self,
+        project: &Project,
+        build: &Build,
+        runnable: &Runnable,
+        envs: &[EnvSpec],
+    ) -> Result<i32> {
+        let transport = self.transport()?;
+        #[cfg(feature = "native-ssh")]
+        {
+            if let Transport::Native(_) = transport {
+                bail!(DinghyError::unsupported(
+                    &self.id,
+                    "interactive shells over the native ssh transport"
+                ))
+            }
+        }
+        let env = self.runtime_env(build, envs)?;
+        let (_, remote_bundle) = self.install_app(&transport, project, build, runnable)?;
+        info!(
+            "Opening a shell on {} in {}, run {}",
+            self.id,
+            remote_bundle.bundle_dir.display(),
+            remote_bundle.bundle_exe.display()
+        );
+        let command = format!(
+            "cd '{}' && export {} && exec \"${{SHELL:-/bin/sh}}\"",
+            path_to_str(&remote_bundle.bundle_dir)?,
+            self.remote_env(&remote_bundle, &env)?
+        );
+        let mut ssh = Command::new("ssh");
+        ssh.args(self.ssh_args())
+            .arg("-t")
+            .arg(format!("{}@{}", self.conf.username, self.conf.hostname))
+            .arg(command);
+        debug!("Running {:?}", ssh);
+        let status = ssh.status()?;
+        // Killed by a signal: there is no exit code to pass on.
+        Ok(status.code().unwrap_or(1))
     }
 }
 
@@ -243,3 +671,43 @@ impl Display for SshDevice {
         write!(fmt, "{}", self.conf.hostname)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ssh_args_are_shared_by_ssh_and_rsync() {
+        let conf: SshDeviceConfiguration = ::toml::from_str(
+            r#"
+                hostname = "board.lab"
+                username = "ci"
+                port = 2222
+                identity_file = "/keys/lab key"
+                proxy_jump = "bastion.lab"
+                ssh_options = { StrictHostKeyChecking = "no" }
+            "#,
+        )
+        .unwrap();
+        let device = SshDevice::new("board".to_string(), conf);
+        assert_eq!(
+            vec![
+                "-i",
+                "/keys/lab key",
+                "-o",
+                "Port=2222",
+                "-o",
+                "IdentitiesOnly=yes",
+                "-o",
+                "ProxyJump=bastion.lab",
+                "-o",
+                "StrictHostKeyChecking=no",
+            ],
+            device.ssh_args()
+        );
+        assert_eq!(
+            "ssh -i '/keys/lab key' -o Port=2222 -o IdentitiesOnly=yes -o ProxyJump=bastion.lab -o StrictHostKeyChecking=no",
+            device.rsync_remote_shell()
+        );
+    }
+}
@@ -1,4 +1,7 @@
 mod device;
+#[cfg(feature = "native-ssh")]
+mod native;
+use crate::doctor::Check;
 use crate::{Configuration, Device, Platform, PlatformManager, Result};
 use std::sync;
 
@@ -20,15 +23,18 @@ impl PlatformManager for SshDeviceManager {
             .conf
             .ssh_devices
             .iter()
-            .map(|(k, conf)| {
-                Box::new(SshDevice {
-                    id: k.clone(),
-                    conf: conf.clone(),
-                }) as _
-            })
+            .map(|(k, conf)| Box::new(SshDevice::new(k.clone(), conf.clone())) as _)
             .collect())
     }
     fn platforms(&self) -> Result<Vec<Box<dyn Platform>>> {
         Ok(vec![])
     }
 }
+
+/// Checks the ssh devices of the configuration.
+pub fn doctor(conf: &Configuration) -> Vec<Check> {
+    conf.ssh_devices
+        .iter()
+        .flat_map(|(id, conf)| SshDevice::new(id.clone(), conf.clone()).doctor())
+        .collect()
+}
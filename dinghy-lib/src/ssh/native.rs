@@ -0,0 +1,487 @@
+use crate::config::SshDeviceConfiguration;
+use crate::device::OutputEcho;
+use crate::errors::*;
+use ssh2::Channel;
+use ssh2::CheckResult;
+use ssh2::FileStat;
+use ssh2::KnownHostFileKind;
+use ssh2::Session;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::net::TcpStream;
+use std::path::Path;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::UNIX_EPOCH;
+use walkdir::WalkDir;
+
+/// How long to wait for a command to end once it has been killed after a timeout.
+const KILL_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// An in-process ssh connection to a device.
+pub struct NativeSession {
+    address: String,
+    session: Session,
+}
+
+/// Checks the host key of the device against `~/.ssh/known_hosts`, as ssh does.
+fn check_host_key(session: &Session, conf: &SshDeviceConfiguration) -> Result<()> {
+    let (key, _) = session
+        .host_key()
+        .ok_or_else(|| anyhow!("{} sent no host key", conf.hostname))?;
+    let mut known_hosts = session.known_hosts()?;
+    let known_hosts_file = dirs::home_dir().map(|home| home.join(".ssh").join("known_hosts"));
+    if let Some(file) = known_hosts_file {
+        // Line by line, libssh2 gives up on the whole file at the first key type it
+        // doesn't know.
+        for line in fs::read_to_string(&file).unwrap_or_default().lines() {
+            if let Err(e) = known_hosts.read_str(line, KnownHostFileKind::OpenSSH) {
+                trace!("Skipping known_hosts line {:?}: {}", line, e);
+            }
+        }
+    }
+    match known_hosts.check_port(&conf.hostname, conf.port.unwrap_or(22), key) {
+        CheckResult::Match => Ok(()),
+        CheckResult::NotFound if conf.accept_unknown_host_key.unwrap_or(false) => {
+            warn!(
+                "Host key of {} is not in ~/.ssh/known_hosts, accepting it anyway",
+                conf.hostname
+            );
+            Ok(())
+        }
+        CheckResult::NotFound => bail!(
+            "Host key of {} is not in ~/.ssh/known_hosts, connect once with ssh to check and record it (or set `accept_unknown_host_key`)",
+            conf.hostname
+        ),
+        CheckResult::Mismatch => bail!(
+            "Host key of {} does not match the one in ~/.ssh/known_hosts, refusing to connect",
+            conf.hostname
+        ),
+        CheckResult::Failure => bail!("Couldn't check the host key of {}", conf.hostname),
+    }
+}
+
+/// What a command run with `NativeSession::run` did.
+pub struct RemoteOutput {
+    /// `None` when the command was killed by a signal.
+    pub exit_code: Option<i32>,
+    pub output: String,
+    pub timed_out: bool,
+}
+
+impl NativeSession {
+    /// Connects and authenticates with the configured identity file, or else with the ssh agent
+    /// and the default keys.
+    pub fn connect(conf: &SshDeviceConfiguration) -> Result<NativeSession> {
+        if conf.proxy_jump.is_some() || !conf.ssh_options.is_empty() {
+            bail!("`proxy_jump` and `ssh_options` are not supported by the native ssh transport, use the openssh one")
+        }
+        let address = format!("{}:{}", conf.hostname, conf.port.unwrap_or(22));
+        debug!("Connecting to {}", address);
+        let tcp = TcpStream::connect(&address)
+            .with_context(|| format!("Couldn't connect to {}", address))?;
+        let mut session = Session::new()?;
+        session.set_tcp_stream(tcp);
+        session
+            .handshake()
+            .with_context(|| format!("Ssh handshake with {} failed", address))?;
+        check_host_key(&session, conf)?;
+
+        match conf.identity_file {
+            Some(ref identity_file) => {
+                let identity_file = PathBuf::from(&*shellexpand::tilde(identity_file));
+                session
+                    .userauth_pubkey_file(&conf.username, None, &identity_file, None)
+                    .with_context(|| {
+                        format!(
+                            "Couldn't authenticate as {} on {} with {}",
+                            conf.username,
+                            address,
+                            identity_file.display()
+                        )
+                    })?;
+            }
+            None => {
+                if let Err(e) = session.userauth_agent(&conf.username) {
+                    debug!(
+                        "Ssh agent authentication failed ({}), trying default keys",
+                        e
+                    );
+                    let default_keys = ["id_ed25519", "id_ecdsa", "id_rsa"]
+                        .iter()
+                        .filter_map(|key| dirs::home_dir().map(|home| home.join(".ssh").join(key)))
+                        .filter(|key| key.exists());
+                    for key in default_keys {
+                        if session
+                            .userauth_pubkey_file(&conf.username, None, &key, None)
+                            .is_ok()
+                        {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        if !session.authenticated() {
+            bail!(
+                "Couldn't authenticate as {} on {} (tried the ssh agent and the default keys, see `identity_file`)",
+                conf.username,
+                address
+            )
+        }
+        Ok(NativeSession { address, session })
+    }
+
+    /// Runs `command` and returns its exit status, its output is only logged.
+    pub fn exec(&self, command: &str) -> Result<i32> {
+        debug!("Running {:?} on {}", command, self.address);
+        let mut channel = self.session.channel_session()?;
+        channel.exec(command)?;
+        let mut output = String::new();
+        channel.read_to_string(&mut output)?;
+        channel.stderr().read_to_string(&mut output)?;
+        channel.wait_close()?;
+        trace!("Output of {:?}: {}", command, output);
+        Ok(channel.exit_status()?)
+    }
+
+    /// Runs `command`, streaming its stdout and stderr while capturing its stdout.
+    ///
+    /// After `timeout`, `kill` is run to stop what `command` started.
+    pub fn run(
+        &self,
+        command: &str,
+        timeout: Option<Duration>,
+        kill: &str,
+    ) -> Result<RemoteOutput> {
+        debug!("Running {:?} on {}", command, self.address);
+        let mut channel = self.session.channel_session()?;
+        channel.exec(command)?;
+
+        let mut echo = OutputEcho::new();
+        self.session.set_blocking(false);
+        let forwarded = self.forward_output(&mut channel, &mut echo, timeout, kill);
+        self.session.set_blocking(true);
+        let (ended, timed_out) = forwarded?;
+
+        let exit_code = if ended {
+            channel.wait_close()?;
+            match channel.exit_signal()?.exit_signal {
+                Some(signal) => {
+                    debug!("{:?} was killed by SIG{}", command, signal);
+                    None
+                }
+                None => Some(channel.exit_status()?),
+            }
+        } else {
+            None
+        };
+        Ok(RemoteOutput {
+            exit_code,
+            output: echo.finish()?,
+            timed_out,
+        })
+    }
+
+    /// Forwards the output of `channel` to `echo` until the command ends, killing it after
+    /// `timeout`.
+    ///
+    /// Returns whether the command ended (it may not, even once killed) and whether it timed out.
+    fn forward_output(
+        &self,
+        channel: &mut Channel,
+        echo: &mut OutputEcho,
+        timeout: Option<Duration>,
+        kill: &str,
+    ) -> Result<(bool, bool)> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut killed_at: Option<Instant> = None;
+        let mut buffer = [0u8; 8192];
+        loop {
+            let stdout = read_available(channel, &mut buffer)?;
+            echo.stdout(&buffer[..stdout])?;
+            let stderr = read_available(&mut channel.stderr(), &mut buffer)?;
+            echo.stderr(&buffer[..stderr])?;
+            if stdout > 0 || stderr > 0 {
+                continue;
+            }
+            if channel.eof() {
+                return Ok((true, killed_at.is_some()));
+            }
+            match killed_at {
+                None if deadline.map(|it| Instant::now() >= it).unwrap_or(false) => {
+                    warn!(
+                        "Timed out after {}s, killing it",
+                        timeout.unwrap_or_default().as_secs()
+                    );
+                    self.session.set_blocking(true);
+                    let killed = self.exec(kill);
+                    self.session.set_blocking(false);
+                    match killed {
+                        Ok(0) => {}
+                        result => warn!("Couldn't kill the process on the device ({:?})", result),
+                    }
+                    killed_at = Some(Instant::now());
+                }
+                Some(killed_at) if killed_at.elapsed() >= KILL_GRACE_PERIOD => {
+                    return Ok((false, true))
+                }
+                _ => {}
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Uploads the content of `from` into `to` over sftp.
+    ///
+    /// Files with the same size and modification time on both sides are skipped, and symbolic
+    /// links are uploaded as the files they point to.
+    pub fn upload_dir(&self, from: &Path, to: &Path) -> Result<()> {
+        debug!(
+            "Uploading {} to {}:{}",
+            from.display(),
+            self.address,
+            to.display()
+        );
+        let sftp = self.session.sftp()?;
+        for entry in WalkDir::new(from).follow_links(true) {
+            let entry = entry?;
+            let remote_path = to.join(entry.path().strip_prefix(from)?);
+            let metadata = entry.metadata()?;
+            let remote_stat = sftp.stat(&remote_path).ok();
+            if metadata.is_dir() {
+                if !remote_stat.map(|stat| stat.is_dir()).unwrap_or(false) {
+                    sftp.mkdir(&remote_path, 0o755)
+                        .with_context(|| format!("Couldn't create {}", remote_path.display()))?;
+                }
+                continue;
+            }
+
+            let mtime = metadata.modified()?.duration_since(UNIX_EPOCH)?.as_secs();
+            let up_to_date = remote_stat
+                .map(|stat| stat.size == Some(metadata.len()) && stat.mtime == Some(mtime))
+                .unwrap_or(false);
+            if up_to_date {
+                trace!("{} is up to date", remote_path.display());
+                continue;
+            }
+            trace!("Uploading {}", remote_path.display());
+            let mut remote_file = sftp
+                .create(&remote_path)
+                .with_context(|| format!("Couldn't create {}", remote_path.display()))?;
+            io::copy(&mut fs::File::open(entry.path())?, &mut remote_file)
+                .with_context(|| format!("Couldn't upload {}", entry.path().display()))?;
+            drop(remote_file);
+            sftp.setstat(
+                &remote_path,
+                FileStat {
+                    size: None,
+                    uid: None,
+                    gid: None,
+                    perm: Some(permissions(&metadata)),
+                    atime: Some(mtime),
+                    mtime: Some(mtime),
+                },
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn read_available<R: Read>(from: &mut R, buffer: &mut [u8]) -> Result<usize> {
+    match from.read(buffer) {
+        Ok(count) => Ok(count),
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(0),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(unix)]
+fn permissions(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn permissions(_metadata: &fs::Metadata) -> u32 {
+    0o755
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::process::{Child, Command, Stdio};
+
+    /// A throwaway sshd standing in for a device, accepting a fresh key of the current user.
+    struct Sshd {
+        child: Child,
+        conf: SshDeviceConfiguration,
+        dir: tempfile::TempDir,
+    }
+
+    impl Drop for Sshd {
+        fn drop(&mut self) {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+
+    fn sshd() -> Sshd {
+        let sshd = which::which("sshd")
+            .ok()
+            .or_else(|| Some(PathBuf::from("/usr/sbin/sshd")).filter(|it| it.exists()))
+            .expect("sshd not found");
+        let dir = tempfile::tempdir().unwrap();
+        for key in &["host_key", "client_key"] {
+            let status = Command::new("ssh-keygen")
+                .args(["-q", "-t", "ed25519", "-N", "", "-f"].iter())
+                .arg(dir.path().join(key))
+                .status()
+                .unwrap();
+            assert!(status.success());
+        }
+        fs::copy(
+            dir.path().join("client_key.pub"),
+            dir.path().join("authorized_keys"),
+        )
+        .unwrap();
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let config = dir.path().join("sshd_config");
+        fs::write(
+            &config,
+            format!(
+                "Port {port}\n\
+                 ListenAddress 127.0.0.1\n\
+                 HostKey {dir}/host_key\n\
+                 AuthorizedKeysFile {dir}/authorized_keys\n\
+                 PidFile {dir}/sshd.pid\n\
+                 StrictModes no\n\
+                 UsePAM no\n\
+                 PermitRootLogin yes\n\
+                 PasswordAuthentication no\n\
+                 Subsystem sftp internal-sftp\n",
+                port = port,
+                dir = dir.path().display()
+            ),
+        )
+        .unwrap();
+        let child = Command::new(sshd)
+            .arg("-D")
+            .arg("-f")
+            .arg(&config)
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+
+        let started = Instant::now();
+        while TcpStream::connect(("127.0.0.1", port)).is_err() {
+            assert!(
+                started.elapsed() < Duration::from_secs(10),
+                "sshd didn't start"
+            );
+            thread::sleep(Duration::from_millis(50));
+        }
+        let username = String::from_utf8(Command::new("whoami").output().unwrap().stdout)
+            .unwrap()
+            .trim()
+            .to_string();
+        let conf = SshDeviceConfiguration {
+            hostname: "127.0.0.1".to_string(),
+            username,
+            port: Some(port),
+            path: None,
+            target: None,
+            toolchain: None,
+            platform: None,
+            remote_shell_vars: Default::default(),
+            env: Default::default(),
+            install_adhoc_rsync_local_path: None,
+            timeout: None,
+            transport: Some(crate::config::SshTransport::Native),
+            identity_file: Some(dir.path().join("client_key").display().to_string()),
+            proxy_jump: None,
+            ssh_options: Default::default(),
+            control_master: None,
+            accept_unknown_host_key: Some(true),
+        };
+        Sshd { child, conf, dir }
+    }
+
+    #[test]
+    #[ignore = "needs sshd"]
+    fn test_run_captures_stdout_and_exit_code() {
+        let sshd = sshd();
+        let session = NativeSession::connect(&sshd.conf).unwrap();
+        let output = session
+            .run("echo hello; echo oops >&2; exit 3", None, "true")
+            .unwrap();
+        assert_eq!(Some(3), output.exit_code);
+        assert_eq!("hello\n", output.output);
+        assert!(!output.timed_out);
+    }
+
+    #[test]
+    #[ignore = "needs sshd"]
+    fn test_run_kills_after_timeout() {
+        let sshd = sshd();
+        let session = NativeSession::connect(&sshd.conf).unwrap();
+        let start = Instant::now();
+        let output = session
+            .run(
+                "sleep 30",
+                Some(Duration::from_secs(1)),
+                "pkill -KILL -f 'sleep 30'",
+            )
+            .unwrap();
+        assert!(output.timed_out);
+        assert!(start.elapsed() < Duration::from_secs(20));
+    }
+
+    #[test]
+    #[ignore = "needs sshd"]
+    fn test_upload_dir_skips_unchanged_files() {
+        let sshd = sshd();
+        let session = NativeSession::connect(&sshd.conf).unwrap();
+        let local = sshd.dir.path().join("local");
+        let remote = sshd.dir.path().join("remote");
+        fs::create_dir_all(local.join("sub")).unwrap();
+        fs::write(local.join("exe"), "#!/bin/sh\n").unwrap();
+        fs::write(local.join("sub").join("data"), "data").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(local.join("exe"), fs::Permissions::from_mode(0o755)).unwrap();
+        }
+
+        session.upload_dir(&local, &remote).unwrap();
+        assert_eq!("data", fs::read_to_string(remote.join("sub/data")).unwrap());
+        assert_eq!(
+            permissions(&fs::metadata(local.join("exe")).unwrap()),
+            permissions(&fs::metadata(remote.join("exe")).unwrap())
+        );
+
+        // Same size and mtime: considered up to date.
+        let mtime = filetime::FileTime::from_last_modification_time(
+            &fs::metadata(remote.join("sub/data")).unwrap(),
+        );
+        fs::write(remote.join("sub/data"), "tada").unwrap();
+        filetime::set_file_mtime(remote.join("sub/data"), mtime).unwrap();
+        session.upload_dir(&local, &remote).unwrap();
+        assert_eq!("tada", fs::read_to_string(remote.join("sub/data")).unwrap());
+
+        fs::write(local.join("sub/data"), "new data").unwrap();
+        session.upload_dir(&local, &remote).unwrap();
+        assert_eq!(
+            "new data",
+            fs::read_to_string(remote.join("sub/data")).unwrap()
+        );
+    }
+}
@@ -5,7 +5,6 @@ use dinghy_build::build_env::append_path_to_target_env;
 use dinghy_build::build_env::envify;
 use dinghy_build::build_env::set_env;
 use dinghy_build::build_env::set_target_env;
-use itertools::Itertools;
 use std::io::Write;
 #[cfg(unix)]
 use std::os::unix::fs::PermissionsExt;
@@ -67,6 +66,8 @@ pub struct ToolchainConfig {
     pub cc: String,
     pub binutils_prefix: String,
     pub cc_prefix: String,
+    /// Extra arguments of the linker.
+    pub link_args: Vec<String>,
 }
 
 impl ToolchainConfig {
@@ -91,12 +92,7 @@ impl ToolchainConfig {
             append_path_to_target_env(
                 "PKG_CONFIG_LIBDIR",
                 Some(&self.rustc_triple),
-                WalkDir::new(self.root.to_string_lossy().as_ref())
-                    .into_iter()
-                    .filter_map(|e| e.ok()) // Ignore unreadable files, maybe could warn...
-                    .filter(|e| e.file_name() == "pkgconfig" && e.file_type().is_dir())
-                    .map(|e| e.path().to_string_lossy().into_owned())
-                    .join(":"),
+                pkg_config_dirs(&self.root).join(":"),
             );
         }
 
@@ -160,6 +156,16 @@ impl ToolchainConfig {
     }
 }
 
+/// The `pkgconfig` directories found below `root`.
+pub fn pkg_config_dirs(root: &path::Path) -> Vec<String> {
+    WalkDir::new(root.to_string_lossy().as_ref())
+        .into_iter()
+        .filter_map(|e| e.ok()) // Ignore unreadable files, maybe could warn...
+        .filter(|e| e.file_name() == "pkgconfig" && e.file_type().is_dir())
+        .map(|e| e.path().to_string_lossy().into_owned())
+        .collect()
+}
+
 fn create_shim<P: AsRef<path::Path>>(
     root: P,
     rustc_triple: &str,